@@ -1,25 +1,96 @@
-use std::{io::ErrorKind, thread::sleep};
+use std::{io::ErrorKind, sync::Arc};
 
-use chrono::{Days, Duration, NaiveDate, TimeZone, Utc};
-use chrono_tz::Europe::Rome;
+use chrono::{DateTime, Days, Duration, NaiveDate, TimeZone, Utc};
+use chrono_tz::{Europe::Rome, Tz};
 use color_eyre::eyre::Error;
+use futures::FutureExt;
 use teloxide::{
     dispatching::dialogue::{Dialogue, InMemStorage},
     prelude::*,
-    utils::command::BotCommands,
+    types::{InlineKeyboardButton, InlineKeyboardMarkup, InputFile, ParseMode},
+    utils::{command::BotCommands, html},
 };
 
 use crate::{
     User,
+    user::Passenger,
     utils::file_manager::TelegramUser,
     utils::sticker::{get_stickers, send_cached_sticker},
 };
 
-use crate::utils::booking::*;
+use crate::utils::backend::{
+    BackendKind, BookingBackend, SearchQuery, book_when_open, build_backend, check_route,
+    fetch_departures, preview_trip,
+};
+use crate::utils::booking::validate_city_id;
+use crate::utils::booking_error::BookingError;
+use crate::utils::config::Config;
+use crate::utils::departures::{Departure, DepartureFilter};
+use crate::utils::driver::DriverSupervisor;
 use crate::utils::file_manager::FileManager;
+use crate::utils::jobs::{BookingJob, JobStatus, JobStore, booking_open_datetime};
+use crate::utils::mail::{MailIngest, PendingTicket};
+use crate::utils::receipt::{BookingReceipt, ReceiptStore};
+use crate::utils::recurring::{RecurringRule, RecurringStore, parse_weekdays};
+use crate::utils::retry::{RetryPolicy, RetrySummary, with_retry_from};
+use crate::utils::scheduler::Scheduler;
+use crate::utils::sessions::SessionRegistry;
+use crate::utils::stops::{StopMatch, find_stop};
+use crate::utils::watch::{Watch, WatchAction, WatchList};
 
 type MyDialogue = Dialogue<State, InMemStorage<State>>;
-type HandlerResult = Result<(), Box<dyn std::error::Error + Send + Sync>>;
+type HandlerError = Box<dyn std::error::Error + Send + Sync>;
+type HandlerResult = Result<(), HandlerError>;
+
+/// Scheduled bookings: the jobs kept on disk, the queue running them and the
+/// recurring rules adding to them.
+#[derive(Clone)]
+struct BookingJobs {
+    store: Arc<JobStore>,
+    scheduler: Arc<Scheduler<BookingJob>>,
+    recurring: Arc<RecurringStore>,
+    /// Held while recurring bookings are turned into jobs, so that two passes never
+    /// schedule the same date.
+    recurring_pass: Arc<tokio::sync::Mutex<()>>,
+    receipts: Arc<ReceiptStore>,
+    /// How early jobs start getting ready, when they race.
+    race_lead: Option<Duration>,
+}
+
+impl BookingJobs {
+    /// Queues `job` to run once its booking window is open, or to get ready for it
+    /// when jobs race.
+    fn schedule(&self, job: BookingJob) {
+        let start = match self.race_lead {
+            Some(lead) => job.due - lead,
+            None => job.due + WINDOW_BUFFER,
+        };
+        self.scheduler.schedule(job.id, start, job);
+    }
+}
+
+/// Makes `/bookticket` stop before confirming the purchase.
+const DRY_RUN_FLAG: &str = "--dry-run";
+/// Makes `/watch` tell about a free seat instead of booking it.
+const NOTIFY_FLAG: &str = "--notify";
+/// Precedes the last travel date of a `/recurring` booking.
+const UNTIL_FLAG: &str = "--until";
+
+/// How long after the booking window opens a scheduled booking starts, in case the
+/// clocks of the bot and the site disagree.
+const WINDOW_BUFFER: Duration = Duration::seconds(1);
+
+/// How often stale booking sessions are looked for.
+const SESSION_REAP_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60);
+
+/// How many finished bookings `/myjobs` lists besides the pending ones.
+const RECENT_JOBS: usize = 5;
+
+/// How often recurring bookings are turned into scheduled ones.
+const RECURRING_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60 * 60);
+/// How far ahead recurring bookings are scheduled: a day more than the booking
+/// window, so that each is queued before its window opens.
+const RECURRING_HORIZON: Days = Days::new(8);
 
 #[derive(Clone, Default)]
 pub enum State {
@@ -57,10 +128,40 @@ enum Command {
     Getuser,
     #[command(description = "Delete my user information")]
     Deleteuser,
-    #[command(description = "Get available cities")]
-    Getcities,
-    #[command(description = "Book a ticket")]
+    #[command(description = "Get available cities, or the destinations from a city")]
+    Getcities(String),
+    #[command(description = "Book a ticket, or check that it would go through with --dry-run")]
     Bookticket(String),
+    #[command(description = "Book a return trip")]
+    Bookreturn(String),
+    #[command(description = "Book a ticket for a group")]
+    Bookgroup(String),
+    #[command(description = "List departures for a route and date")]
+    Timetable(String),
+    #[command(description = "Book a sold-out run as soon as a seat frees up, or only --notify")]
+    Watch(String),
+    #[command(description = "List my seat watches")]
+    Watches,
+    #[command(description = "Stop a seat watch")]
+    Unwatch(String),
+    #[command(description = "List my scheduled and recent bookings")]
+    Myjobs,
+    #[command(description = "Cancel a scheduled booking")]
+    Canceljob(String),
+    #[command(description = "Move a scheduled booking to another date")]
+    Reschedulejob(String),
+    #[command(description = "Book a route every week on the given weekdays")]
+    Recurring(String),
+    #[command(description = "List my recurring bookings")]
+    Recurrings,
+    #[command(description = "Pause a recurring booking")]
+    Pauserecurring(String),
+    #[command(description = "Resume a recurring booking")]
+    Resumerecurring(String),
+    #[command(description = "Fetch the list of cities again (admins only)")]
+    Refreshcities,
+    #[command(description = "Show open booking sessions (admins only)")]
+    Sessions,
     #[command(description = "Show help menu")]
     Help,
     #[command(description = "Cancel current operation")]
@@ -68,12 +169,101 @@ enum Command {
 }
 
 pub async fn bot_init() {
+    let config = Config::from_env().expect("Invalid configuration");
+    let driver = match (&config.driver, config.backend) {
+        (Some(driver), BackendKind::Selenium) => Some(
+            DriverSupervisor::start(driver.clone())
+                .await
+                .expect("Failed to start the WebDriver"),
+        ),
+        _ => None,
+    };
+    let backend = build_backend(&config, driver.clone());
+    println!("Using {} booking backend", backend.name());
+    if let (BackendKind::Selenium, Some(sessions)) = (config.backend, backend.sessions()) {
+        close_leftover_sessions(sessions, driver.as_deref(), &config.browser.url).await;
+    }
+    let mail = config.mail.clone().map(|mail| {
+        let path = config.jobs_file.with_file_name("pending_tickets.json");
+        Arc::new(MailIngest::open(mail, path).expect("Failed to load the pending tickets"))
+    });
+    let watches = Arc::new(WatchList::new(config.watch.clone()));
+    let jobs =
+        Arc::new(JobStore::open(&config.jobs_file).expect("Failed to load the booking jobs"));
+    let recovered = jobs
+        .recover(Utc::now(), config.missed_jobs)
+        .expect("Failed to update the booking jobs");
+    let recurring = Arc::new(
+        RecurringStore::open(&config.recurring_file)
+            .expect("Failed to load the recurring bookings"),
+    );
+    let receipts =
+        Arc::new(ReceiptStore::open(&config.receipts_file).expect("Failed to load the receipts"));
+    let config = Arc::new(config);
+
     let bot = Bot::from_env();
+    let scheduler = {
+        let (bot, backend, mail, config, jobs, receipts) = (
+            bot.clone(),
+            backend.clone(),
+            mail.clone(),
+            config.clone(),
+            jobs.clone(),
+            receipts.clone(),
+        );
+        Scheduler::start(move |job| {
+            run_booking_job(
+                bot.clone(),
+                backend.clone(),
+                mail.clone(),
+                config.clone(),
+                jobs.clone(),
+                receipts.clone(),
+                job,
+            )
+            .boxed()
+        })
+    };
     bot.set_my_commands(Command::bot_commands())
         .await
         .expect("Failed to set commands");
 
-    let handler = Update::filter_message()
+    if let Some(mail) = &mail {
+        spawn_mail_ingest(bot.clone(), mail.clone());
+    }
+    for job in recovered.missed {
+        println!("Booking job #{} was missed", job.id);
+        send_message(
+            bot.clone(),
+            ChatId(job.chat_id),
+            format!(
+                "⚠️ A scheduled booking did not go through while the bot was offline:\n{}",
+                job
+            ),
+            Some("error_cat"),
+        )
+        .await;
+    }
+    let jobs = BookingJobs {
+        store: jobs,
+        scheduler,
+        recurring,
+        recurring_pass: Arc::new(tokio::sync::Mutex::new(())),
+        receipts,
+        race_lead: config
+            .race_lead
+            .and_then(|lead| Duration::from_std(lead).ok()),
+    };
+    for job in recovered.rearm {
+        println!("Scheduling booking job #{} again", job.id);
+        jobs.schedule(job);
+    }
+    if let Some(sessions) = backend.sessions() {
+        spawn_session_reaper(sessions.clone(), config.session_max_age);
+    }
+    spawn_recurring_scheduler(bot.clone(), jobs.clone());
+
+    let message_handler = Update::filter_message()
         .enter_dialogue::<Message, InMemStorage<State>, State>()
         .branch(
             dptree::entry()
@@ -113,12 +303,27 @@ pub async fn bot_init() {
             .endpoint(receive_institutional_email),
         );
 
+    let handler = dptree::entry()
+        .branch(message_handler)
+        .branch(Update::filter_callback_query().endpoint(handle_callback_query));
+
     Dispatcher::builder(bot, handler)
-        .dependencies(dptree::deps![InMemStorage::<State>::new()])
+        .dependencies(dptree::deps![
+            InMemStorage::<State>::new(),
+            backend,
+            config,
+            mail,
+            watches,
+            jobs
+        ])
         .enable_ctrlc_handler()
         .build()
         .dispatch()
         .await;
+
+    if let Some(driver) = driver {
+        driver.shutdown().await;
+    }
 }
 
 async fn get_username(msg: Message) -> Result<String, Error> {
@@ -170,7 +375,7 @@ async fn handle_deleteuser(bot: Bot, msg: Message) -> HandlerResult {
         Ok(()) => {
             send_cached_sticker(
                 bot.clone(),
-                msg.clone(),
+                msg.chat.id,
                 get_stickers().get("bye").unwrap().to_string(),
             )
             .await;
@@ -191,30 +396,189 @@ async fn handle_deleteuser(bot: Bot, msg: Message) -> HandlerResult {
     Ok(())
 }
 
-async fn handle_getcities(bot: Bot, msg: Message) -> HandlerResult {
-    let cities = get_cities().await?;
+async fn handle_getcities(
+    bot: Bot,
+    msg: Message,
+    backend: Arc<dyn BookingBackend>,
+    args: String,
+) -> HandlerResult {
+    // With a departure ID, only list the stops reachable from it
+    let (title, cities) = match args.trim() {
+        "" => (
+            "Available cities (ID. name)".to_string(),
+            backend.get_cities().await?,
+        ),
+        from => {
+            let Ok(from_id) = from.parse::<u32>() else {
+                send_message(
+                    bot.clone(),
+                    msg.chat.id,
+                    "❌ Invalid command syntax.\nUsage: /getcities [from]".to_string(),
+                    Some("error_cat_invalid_syntax"),
+                )
+                .await;
+                return Err("Invalid command syntax".into());
+            };
+            let cities = backend.get_cities().await?;
+            let city_from = match validate_city_id(&cities, from_id) {
+                Ok(city_from) => city_from.to_string(),
+                Err(e) => {
+                    send_message(
+                        bot.clone(),
+                        msg.chat.id,
+                        format!("❌ {}", e),
+                        Some("error_cat_invalid_syntax"),
+                    )
+                    .await;
+                    return Err(e.to_string().into());
+                }
+            };
+            (
+                format!("Destinations from {} (ID. name)", city_from),
+                backend.get_destinations(from_id).await?,
+            )
+        }
+    };
+
     let cities_list = cities
         .iter()
         .map(|(name, id)| format!("{}. {}", id, name))
         .collect::<Vec<_>>()
         .join("\n");
 
+    bot.send_message(msg.chat.id, format!("{}:\n{}", title, cities_list))
+        .await?;
+    Ok(())
+}
+
+async fn handle_refreshcities(
+    bot: Bot,
+    msg: Message,
+    backend: Arc<dyn BookingBackend>,
+    config: Arc<Config>,
+) -> HandlerResult {
+    let username = get_username(msg.clone()).await?;
+    if !config.is_admin(&username) {
+        bot.send_message(msg.chat.id, "❌ Only admins can refresh the cities.")
+            .await?;
+        return Ok(());
+    }
+
+    match backend.refresh_cities().await {
+        Ok(cities) => {
+            send_message(
+                bot,
+                msg.chat.id,
+                format!("✅ Loaded {} cities from the site.", cities.len()),
+                Some("success_cat"),
+            )
+            .await;
+        }
+        Err(e) => {
+            send_message(
+                bot,
+                msg.chat.id,
+                format!("❌ Failed to refresh cities: {}", e),
+                Some("error_cat"),
+            )
+            .await;
+        }
+    }
+    Ok(())
+}
+
+async fn handle_sessions(
+    bot: Bot,
+    msg: Message,
+    backend: Arc<dyn BookingBackend>,
+    config: Arc<Config>,
+) -> HandlerResult {
+    let username = get_username(msg.clone()).await?;
+    if !config.is_admin(&username) {
+        bot.send_message(msg.chat.id, "❌ Only admins can list the sessions.")
+            .await?;
+        return Ok(());
+    }
+
+    let Some(sessions) = backend.sessions() else {
+        bot.send_message(
+            msg.chat.id,
+            format!(
+                "ℹ️ The {} backend does not keep booking sessions.",
+                backend.name()
+            ),
+        )
+        .await?;
+        return Ok(());
+    };
+
+    let sessions = sessions.list();
+    if sessions.is_empty() {
+        bot.send_message(msg.chat.id, "✅ No open sessions.")
+            .await?;
+        return Ok(());
+    }
+
+    let sessions_list = sessions
+        .iter()
+        .map(|session| {
+            format!(
+                "#{} {}{}, started {}, open for {}s",
+                session.id,
+                session.backend,
+                session
+                    .webdriver_id
+                    .as_ref()
+                    .map(|id| format!(" ({})", id))
+                    .unwrap_or_default(),
+                session.started.format("%H:%M:%S"),
+                session.age.as_secs()
+            )
+        })
+        .collect::<Vec<String>>()
+        .join("\n");
     bot.send_message(
         msg.chat.id,
-        format!("Available cities (ID. name):\n{}", cities_list),
+        format!("🖥️ {} open session(s):\n{}", sessions.len(), sessions_list),
     )
     .await?;
     Ok(())
 }
 
-async fn handle_bookticket(bot: Bot, msg: Message, args: String) -> HandlerResult {
+async fn handle_bookticket(
+    bot: Bot,
+    msg: Message,
+    backend: Arc<dyn BookingBackend>,
+    mail: Option<Arc<MailIngest>>,
+    jobs: BookingJobs,
+    args: String,
+) -> HandlerResult {
+    let username = get_username(msg.clone()).await?;
+    request_booking(bot, msg.chat.id, &username, backend, mail, jobs, &args).await
+}
+
+/// Validates `/bookticket` arguments and schedules the booking as a job, to run once
+/// the booking window opens; with `--dry-run` the booking stops before the
+/// purchase, without waiting.
+async fn request_booking(
+    bot: Bot,
+    chat_id: ChatId,
+    username: &str,
+    backend: Arc<dyn BookingBackend>,
+    mail: Option<Arc<MailIngest>>,
+    jobs: BookingJobs,
+    args: &str,
+) -> HandlerResult {
     // Argument parsing and validation
-    let parts: Vec<&str> = args.split_whitespace().collect();
-    if parts.len() != 3 {
+    let mut parts = split_args(args);
+    let dry_run = parts.iter().any(|part| part == DRY_RUN_FLAG);
+    parts.retain(|part| part != DRY_RUN_FLAG);
+    if !(3..=4).contains(&parts.len()) {
         send_message(
             bot.clone(),
-            msg.clone(),
-            "❌ Invalid command syntax.\nUsage: /bookticket <from> <to> <date> (YYYY-MM-DD)"
+            chat_id,
+            "❌ Invalid command syntax.\nUsage: /bookticket <from> <to> <date> (YYYY-MM-DD) [time (HH:MM or HH:MM-HH:MM)] [--dry-run]\n\
+             Cities are IDs or names, quote names with spaces: \"ancona stazione\""
                 .to_string(),
             Some("error_cat_invalid_syntax"),
         )
@@ -223,197 +587,1866 @@ async fn handle_bookticket(bot: Bot, msg: Message, args: String) -> HandlerResul
         return Err("Invalid command syntax".into());
     }
 
-    let from = parts[0].parse::<u32>();
-    let to = parts[1].parse::<u32>();
-    let date = parts[2].to_string();
+    let user = get_registered_user(&bot, chat_id, username).await?;
+    // The buttons offered for an ambiguous stop must not turn a dry run into a purchase
+    let mut rebook_args = parts.clone();
+    if dry_run {
+        rebook_args.push(DRY_RUN_FLAG.to_string());
+    }
+    let (id_from, id_to) =
+        parse_city_ids(&bot, chat_id, backend.as_ref(), &rebook_args, true).await?;
+    let parsed_date = parse_travel_date(&bot, chat_id, &parts[2]).await?;
 
-    let file_manager = FileManager::new("users.json");
+    let departure = parse_departure(&bot, chat_id, parts.get(3)).await?;
 
-    let user = match file_manager.get_user(get_username(msg.clone()).await?) {
-        Ok(user) => user,
-        Err(e) => {
-            bot.send_message(msg.chat.id, match e.kind() {
-                // Handle file not found specifically
-                ErrorKind::NotFound => "❌ No user registered yet!\nUse /createuser to register.",
-                _ => "❌ Failed to access user data. Please try again later.",
-            })
-            .await?;
-            return Err("User not found".into());
-        }
+    let query = SearchQuery::new(id_from, id_to, parts[2].clone()).with_departure(departure);
+    if dry_run {
+        // Filled in the same way as the real booking, so the preview shows what it sends
+        let passengers = [mailbox_buyer(&mail, Passenger::student(user.user_data))];
+        return dry_run_booking(&bot, chat_id, backend.as_ref(), &passengers, &query).await;
+    }
+
+    let (city_from, city_to) =
+        checked_route(&bot, chat_id, backend.as_ref(), id_from, id_to).await?;
+    let job = BookingJob::new(
+        chat_id.0,
+        username,
+        format!("{} → {}", city_from, city_to),
+        &query,
+        booking_open_datetime(parsed_date).with_timezone(&Utc),
+    );
+    queue_job(&bot, chat_id, &jobs, job).await
+}
+
+/// Saves `job` and schedules it, telling the user when it will run.
+async fn queue_job(
+    bot: &Bot,
+    chat_id: ChatId,
+    jobs: &BookingJobs,
+    job: BookingJob,
+) -> HandlerResult {
+    let job = store_job(bot, chat_id, jobs, job).await?;
+    // The result may take minutes with retries, so the user hears back right away
+    let message = match job.due > Utc::now() {
+        true => format!(
+            "Booking #{} scheduled, it will run when booking opens on {}.\nCancel it with /canceljob {}",
+            job.id,
+            job.due.with_timezone(&Rome).format("%Y-%m-%d %H:%M"),
+            job.id
+        ),
+        false => format!("Booking is open, booking #{} now...", job.id),
     };
+    send_message(bot.clone(), chat_id, message, Some("hourglass")).await;
+    jobs.schedule(job);
+    Ok(())
+}
 
-    let id_from = match from {
-        Ok(id) => id,
-        Err(_e) => {
+/// Saves `job` under a new ID, telling the user when it cannot be saved.
+async fn store_job(
+    bot: &Bot,
+    chat_id: ChatId,
+    jobs: &BookingJobs,
+    job: BookingJob,
+) -> Result<BookingJob, HandlerError> {
+    match jobs.store.add(job) {
+        Ok(job) => Ok(job),
+        Err(e) => {
             send_message(
                 bot.clone(),
-                msg.clone(),
-                "❌ Departure city ID not found.".to_string(),
-                Some("error_cat_invalid_syntax"),
+                chat_id,
+                format!("❌ Failed to save the booking: {}", e),
+                Some("error_cat"),
             )
             .await;
-            return Err("Departure city ID not found".into());
+            Err(e.to_string().into())
         }
-    };
+    }
+}
 
-    let id_to = match to {
-        Ok(id) => id,
-        Err(_e) => {
-            send_message(
-                bot.clone(),
-                msg.clone(),
-                "❌ Arrival city ID not found.".to_string(),
-                Some("error_cat_invalid_syntax"),
-            )
-            .await;
-            return Err("Arrival city ID not found".into());
+/// Tells when `job` runs, for the messages about it.
+fn job_start(job: &BookingJob) -> String {
+    match job.due > Utc::now() {
+        true => format!(
+            "it will run when booking opens on {}",
+            job.due.with_timezone(&Rome).format("%Y-%m-%d %H:%M")
+        ),
+        false => "booking is open, it runs now".to_string(),
+    }
+}
+
+/// Books `job`, keeping its status in `jobs` up to date.
+async fn run_booking_job(
+    bot: Bot,
+    backend: Arc<dyn BookingBackend>,
+    mail: Option<Arc<MailIngest>>,
+    config: Arc<Config>,
+    jobs: Arc<JobStore>,
+    receipts: Arc<ReceiptStore>,
+    job: BookingJob,
+) {
+    let chat_id = ChatId(job.chat_id);
+    let set_status = |status: JobStatus, note: Option<String>| {
+        if let Err(e) = jobs.set_status(job.id, status, note) {
+            println!("Failed to update booking job #{}: {}", job.id, e);
         }
     };
 
-    let parsed_date = match NaiveDate::parse_from_str(&date, "%Y-%m-%d") {
-        Ok(d) => d,
+    // The way back is called off while it waits when its outbound trip fails
+    if jobs
+        .get(job.id)
+        .is_some_and(|stored| stored.status != JobStatus::Pending)
+    {
+        println!("Booking job #{} is no longer pending, skipping it", job.id);
+        return;
+    }
+
+    // The user may have changed or deleted their profile in the meantime
+    let user = match FileManager::new("users.json").get_user(job.username.clone()) {
+        Ok(user) => user,
         Err(_) => {
+            set_status(JobStatus::Failed, Some("user not registered".to_string()));
             send_message(
-                bot.clone(),
-                msg.clone(),
-                "❌ Invalid date format (use YYYY-MM-DD)".to_string(),
-                Some("error_cat_invalid_syntax"),
+                bot,
+                chat_id,
+                format!(
+                    "❌ Booking #{} cancelled, @{} is no longer registered.",
+                    job.id, job.username
+                ),
+                Some("error_cat"),
             )
             .await;
-            return Err("Invalid date format".into());
+            return;
         }
     };
+    let legs = match job.legs() {
+        Ok(legs) => legs,
+        Err(e) => {
+            set_status(JobStatus::Failed, Some(e.to_string()));
+            return;
+        }
+    };
+    // The buyer comes first, the other passengers are looked up again as well
+    let mut passengers = vec![mailbox_buyer(&mail, Passenger::student(user.user_data))];
+    for entry in &job.companions {
+        match parse_passenger(entry) {
+            Ok(passenger) => passengers.push(passenger),
+            Err(e) => {
+                set_status(JobStatus::Failed, Some(e.clone()));
+                send_message(
+                    bot,
+                    chat_id,
+                    format!("❌ Booking #{} cancelled: {}", job.id, e),
+                    Some("error_cat"),
+                )
+                .await;
+                return;
+            }
+        }
+    }
 
-    // Create DateTime in Rome timezone at midnight
-    let parsed_datetime = Rome
-        .from_local_datetime(&parsed_date.and_hms_opt(0, 0, 0).unwrap())
-        .unwrap();
-
-    // Calculate booking open time (7 days before at midnight)
-    let booking_open_date = parsed_date.checked_sub_days(Days::new(7)).unwrap();
-    let booking_open_datetime = Rome
-        .from_local_datetime(&booking_open_date.and_hms_opt(0, 0, 0).unwrap())
-        .unwrap();
-
-    // Get current time in Rome
-    let now = Utc::now().with_timezone(&Rome);
+    // Racing only makes sense while booking has not opened yet
+    let race_at = Some(job.due).filter(|due| config.race_lead.is_some() && *due > Utc::now());
 
-    if parsed_datetime <= now + Days::new(1) {
-        send_message(
-            bot.clone(),
-            msg.clone(),
-            "❌ Invalid date".to_string(),
-            Some("error_cat_invalid_syntax"),
-        )
-        .await;
-        return Err("Invalid date".into());
+    set_status(JobStatus::Running, None);
+    match book_retrying(
+        &bot,
+        chat_id,
+        backend.as_ref(),
+        &config.retry,
+        &passengers,
+        &legs,
+        race_at,
+    )
+    .await
+    {
+        Ok(receipt) => {
+            set_status(JobStatus::Booked, receipt.code.clone());
+            println!("Response from book_ticket: {}", receipt);
+            keep_receipt(
+                &receipts,
+                &mail,
+                chat_id,
+                &job.username,
+                &passengers,
+                &receipt,
+            );
+            send_message(bot, chat_id, receipt.to_string(), Some("success_cat")).await;
+        }
+        Err(e) => {
+            set_status(JobStatus::Failed, Some(e.root_cause().to_string()));
+            report_booking_error(&bot, chat_id, &e).await;
+            cancel_way_back(&bot, &jobs, &job).await;
+        }
     }
+}
 
-    // Wait until booking opens every minute
-    if now < booking_open_datetime {
+/// Cancels the way back booked as a job of its own once its outbound `job` has
+/// failed, telling the user.
+async fn cancel_way_back(bot: &Bot, jobs: &JobStore, job: &BookingJob) {
+    let way_back = jobs
+        .trip(job.id)
+        .into_iter()
+        .filter(|leg| leg.outbound_id == Some(job.id) && leg.status == JobStatus::Pending);
+    for leg in way_back {
+        let note = format!("the outbound booking #{} failed", job.id);
+        if let Err(e) = jobs.set_status(leg.id, JobStatus::Cancelled, Some(note)) {
+            println!("Failed to cancel booking job #{}: {}", leg.id, e);
+            continue;
+        }
         send_message(
             bot.clone(),
-            msg.clone(),
+            ChatId(job.chat_id),
             format!(
-                "Waiting until {} for booking to open...",
-                booking_open_datetime.date_naive()
+                "❌ The way back, booking #{}, is cancelled as well.",
+                leg.id
             ),
-            Some("hourglass"),
+            None,
         )
         .await;
-        while Utc::now().with_timezone(&Rome) < booking_open_datetime {
-            println!("...");
-            sleep(Duration::seconds(60).to_std().unwrap());
-        }
-        sleep(Duration::seconds(1).to_std().unwrap()); // Buffer for precision issues
     }
+}
 
-    let response = match book_ticket(&user.user_data, id_from, id_to, date, Some(false)).await {
-        Ok(r) => r,
-        Err(e) => {
+/// Parses the optional departure time of a command, `HH:MM` or `HH:MM-HH:MM`.
+async fn parse_departure(
+    bot: &Bot,
+    chat_id: ChatId,
+    time: Option<&String>,
+) -> Result<Option<DepartureFilter>, HandlerError> {
+    match time.map(|time| DepartureFilter::parse(time)) {
+        Some(Ok(filter)) => Ok(Some(filter)),
+        Some(Err(e)) => {
             send_message(
                 bot.clone(),
-                msg.clone(),
-                e.to_string(),
+                chat_id,
+                format!("❌ {}", e),
                 Some("error_cat_invalid_syntax"),
             )
             .await;
-            return Err(e.to_string().into());
+            Err(e.to_string().into())
+        }
+        None => Ok(None),
+    }
+}
+
+/// Runs a booking of `query` up to the purchase summary and shows what would have
+/// been submitted.
+async fn dry_run_booking(
+    bot: &Bot,
+    chat_id: ChatId,
+    backend: &dyn BookingBackend,
+    passengers: &[Passenger],
+    query: &SearchQuery,
+) -> HandlerResult {
+    let preview = match preview_trip(backend, passengers, std::slice::from_ref(query)).await {
+        Ok(preview) => preview,
+        Err(e) => {
+            let error = Error::from(e);
+            report_booking_error(bot, chat_id, &error).await;
+            return Err(error.to_string().into());
         }
     };
 
-    println!("Response from book_ticket: {}", response);
-    send_message(bot.clone(), msg.clone(), response, Some("success_cat")).await;
+    println!("Response from preview_trip: {}", preview);
+    send_message(
+        bot.clone(),
+        chat_id,
+        format!("🧪 {}", preview),
+        Some("success_cat"),
+    )
+    .await;
+    if let Some(screenshot) = preview.summary.screenshot {
+        bot.send_photo(
+            chat_id,
+            InputFile::memory(screenshot).file_name("summary.png"),
+        )
+        .caption("Purchase summary, not confirmed")
+        .await
+        .log_on_error()
+        .await;
+    }
     Ok(())
 }
 
-async fn handle_help(bot: Bot, msg: Message) -> HandlerResult {
-    let help_text = Command::descriptions().to_string();
-    bot.send_message(msg.chat.id, help_text).await?;
-    Ok(())
+/// Books every leg for `passengers`, trying again as `policy` allows and telling
+/// the user about each failed attempt.
+///
+/// With `race_at`, attempts made before booking opens at that time race for the
+/// opening, and the retry deadline counts from then rather than from the head start.
+async fn book_retrying(
+    bot: &Bot,
+    chat_id: ChatId,
+    backend: &dyn BookingBackend,
+    policy: &RetryPolicy,
+    passengers: &[Passenger],
+    legs: &[SearchQuery],
+    race_at: Option<DateTime<Utc>>,
+) -> Result<BookingReceipt, Error> {
+    let started = std::time::Instant::now()
+        + race_at
+            .and_then(|opens_at| (opens_at - Utc::now()).to_std().ok())
+            .unwrap_or_default();
+    with_retry_from(
+        policy,
+        started,
+        || async move {
+            book_when_open(backend, passengers, legs, race_at)
+                .await
+                .map_err(Error::from)
+        },
+        |notice| send_message(bot.clone(), chat_id, notice.to_string(), None),
+    )
+    .await
 }
 
-async fn handle_cancel(bot: Bot, dialogue: MyDialogue, msg: Message) -> HandlerResult {
-    handle_stop(bot, dialogue, msg).await
+/// Keeps `receipt` in `receipts`, logging instead of failing the booking, and
+/// waits for its ticket email when the booking mailbox is watched.
+fn keep_receipt(
+    receipts: &ReceiptStore,
+    mail: &Option<Arc<MailIngest>>,
+    chat_id: ChatId,
+    username: &str,
+    passengers: &[Passenger],
+    receipt: &BookingReceipt,
+) {
+    if let Err(e) = receipts.add(username, receipt) {
+        println!("Failed to save receipt: {}", e);
+    }
+    if let Some(mail) = mail
+        && let Err(e) = mail.expect(PendingTicket::new(chat_id.0, receipt, passengers))
+    {
+        println!("Failed to save the pending ticket: {}", e);
+    }
 }
 
-async fn send_message(bot: Bot, msg: Message, response: String, sticker_id: Option<&str>) {
-    bot.send_message(msg.chat.id, response)
-        .await
-        .log_on_error()
-        .await;
-    if let Some(sticker_id) = sticker_id {
-        send_cached_sticker(
-            bot,
-            msg,
-            get_stickers().get(sticker_id).unwrap().to_string(),
-        )
-        .await;
+/// Makes `buyer` give the address of the booking mailbox when it is watched, so
+/// that the ticket email arrives where it is picked up.
+fn mailbox_buyer(mail: &Option<Arc<MailIngest>>, mut buyer: Passenger) -> Passenger {
+    if let Some(mail) = mail {
+        buyer.buyer_email = Some(mail.address().to_string());
     }
+    buyer
 }
 
-// Main command handler
-async fn handle_command(
-    bot: Bot,
-    dialogue: MyDialogue,
-    msg: Message,
-    cmd: Command,
-) -> HandlerResult {
-    match cmd {
-        Command::Start => handle_start(bot, dialogue, msg).await,
-        Command::Createuser => handle_createuser(bot, dialogue, msg).await,
-        Command::Getuser => handle_getuser(bot, msg).await,
-        Command::Deleteuser => handle_deleteuser(bot, msg).await,
-        Command::Getcities => handle_getcities(bot, msg).await,
-        Command::Bookticket(args) => handle_bookticket(bot, msg, args).await,
-        Command::Help => handle_help(bot, msg).await,
-        Command::Cancel => handle_cancel(bot, dialogue, msg).await,
-    }
+/// Forwards the PDF tickets found in the booking mailbox to their chats.
+fn spawn_mail_ingest(bot: Bot, mail: Arc<MailIngest>) {
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(mail.poll_interval()).await;
+            let tickets = match mail.poll().await {
+                Ok(tickets) => tickets,
+                Err(e) => {
+                    println!("Failed to check the booking mailbox: {}", e);
+                    continue;
+                }
+            };
+
+            for (ticket, ticket_mail) in tickets {
+                println!("Forwarding ticket email \"{}\"", ticket_mail.subject);
+                for pdf in ticket_mail.pdfs {
+                    bot.send_document(
+                        ChatId(ticket.chat_id),
+                        InputFile::memory(pdf.contents).file_name(pdf.file_name),
+                    )
+                    .caption(format!("🎫 {}", ticket_mail.subject))
+                    .await
+                    .log_on_error()
+                    .await;
+                }
+            }
+        }
+    });
 }
 
-async fn handle_invalid_command(bot: Bot, msg: Message) -> HandlerResult {
-    bot.send_message(
-        msg.chat.id,
-        "⚠️ Invalid command syntax. Use /help for command usage.",
-    )
-    .await?;
-    Ok(())
+/// Closes the booking sessions left open for longer than `max_age`.
+fn spawn_session_reaper(sessions: Arc<SessionRegistry>, max_age: std::time::Duration) {
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(SESSION_REAP_INTERVAL).await;
+            let reaped = sessions.reap(max_age).await;
+            if reaped > 0 {
+                println!("Closed {} stale booking session(s)", reaped);
+            }
+        }
+    });
 }
 
-async fn handle_unexpected_messages(bot: Bot, _: MyDialogue, msg: Message) -> HandlerResult {
-    bot.send_message(
-        msg.chat.id,
-        "⚠️ Please use commands to interact with me!\n\nAvailable commands:\n/help - Show all commands",
-    ).await?;
-    Ok(())
+/// Schedules the bookings of recurring rules as their dates come close.
+fn spawn_recurring_scheduler(bot: Bot, jobs: BookingJobs) {
+    tokio::spawn(async move {
+        loop {
+            schedule_recurring(&bot, &jobs).await;
+            tokio::time::sleep(RECURRING_INTERVAL).await;
+        }
+    });
 }
 
-async fn handle_stop(bot: Bot, dialogue: MyDialogue, msg: Message) -> HandlerResult {
-    match dialogue.get().await? {
-        Some(State::Start) => {
-            send_cached_sticker(
+/// Adds a booking job for every travel date of the recurring rules from tomorrow
+/// to [`RECURRING_HORIZON`] that has none yet.
+async fn schedule_recurring(bot: &Bot, jobs: &BookingJobs) {
+    // The rules are read once the pass is ours, so they show what earlier passes did
+    let _pass = jobs.recurring_pass.lock().await;
+    let today = Utc::now().with_timezone(&Rome).date_naive();
+    let (first, last) = (today + Days::new(1), today + RECURRING_HORIZON);
+    for rule in jobs.recurring.list() {
+        for date in rule.dates(first, last) {
+            let query = match rule.query(date) {
+                Ok(query) => query,
+                Err(e) => {
+                    println!("Invalid recurring booking #{}: {}", rule.id, e);
+                    break;
+                }
+            };
+            let job = BookingJob::new(
+                rule.chat_id,
+                &rule.username,
+                rule.route.clone(),
+                &query,
+                booking_open_datetime(date).with_timezone(&Utc),
+            );
+            let job = match jobs.store.add(job) {
+                Ok(job) => job,
+                Err(e) => {
+                    println!(
+                        "Failed to save a job of recurring booking #{}: {}",
+                        rule.id, e
+                    );
+                    break;
+                }
+            };
+            if let Err(e) = jobs.recurring.mark_scheduled(rule.id, date) {
+                println!("Failed to update recurring booking #{}: {}", rule.id, e);
+            }
+
+            println!("Recurring booking #{} scheduled job #{}", rule.id, job.id);
+            send_message(
                 bot.clone(),
-                msg.clone(),
+                ChatId(rule.chat_id),
+                format!(
+                    "🔁 Booking #{} for {} scheduled by recurring booking #{}, it will run when booking opens on {}.",
+                    job.id,
+                    date,
+                    rule.id,
+                    job.due.with_timezone(&Rome).format("%Y-%m-%d %H:%M")
+                ),
+                None,
+            )
+            .await;
+            jobs.schedule(job);
+        }
+    }
+}
+
+/// Closes the browser sessions a previous run of the bot left open on the WebDriver,
+/// once the supervised driver, if any, is up.
+async fn close_leftover_sessions(
+    sessions: &SessionRegistry,
+    driver: Option<&DriverSupervisor>,
+    url: &str,
+) {
+    if let Some(driver) = driver
+        && let Err(e) = driver.wait_until_healthy().await
+    {
+        println!("Not closing leftover sessions: {}", e);
+        return;
+    }
+    match sessions.close_orphans(url).await {
+        Ok(0) => {}
+        Ok(closed) => println!(
+            "Closed {} WebDriver session(s) left by a previous run",
+            closed
+        ),
+        Err(e) => println!("Failed to close leftover WebDriver sessions: {}", e),
+    }
+}
+
+/// Tells the user why a booking failed, attaching what was saved of the last page.
+async fn report_booking_error(bot: &Bot, chat_id: ChatId, error: &Error) {
+    let Some(booking_error) = error.downcast_ref::<BookingError>() else {
+        send_message(
+            bot.clone(),
+            chat_id,
+            format!("❌ {}", error),
+            Some("error_cat"),
+        )
+        .await;
+        return;
+    };
+
+    let (message, sticker) = booking_error_message(booking_error);
+    // After several attempts, list what each of them ran into
+    let message = match error.downcast_ref::<RetrySummary>() {
+        Some(summary) => format!("{}\n\n{}", message, summary),
+        None => message,
+    };
+    send_message(bot.clone(), chat_id, message, Some(sticker)).await;
+
+    let Some(failure) = booking_error.failure() else {
+        return;
+    };
+    if let Some(screenshot) = &failure.screenshot {
+        bot.send_photo(chat_id, InputFile::file(screenshot))
+            .caption(format!("Page at step \"{}\"", failure.step))
+            .await
+            .log_on_error()
+            .await;
+    } else if let Some(page) = &failure.page {
+        bot.send_document(chat_id, InputFile::file(page))
+            .caption(format!("Page at step \"{}\"", failure.step))
+            .await
+            .log_on_error()
+            .await;
+    }
+}
+
+/// What to tell the user, and with which sticker, for each way a booking fails.
+fn booking_error_message(error: &BookingError) -> (String, &'static str) {
+    match error {
+        BookingError::NoPassengers => (
+            "❌ No passengers to book.".to_string(),
+            "error_cat_invalid_syntax",
+        ),
+        BookingError::CityLookup(e) => (
+            format!("❌ Could not look up the stops: {}", e),
+            "error_cat",
+        ),
+        BookingError::InvalidRoute { from, to_id } => (
+            format!("❌ No line goes from {} to stop {}.", from, to_id),
+            "error_cat_invalid_syntax",
+        ),
+        BookingError::WebDriverUnavailable(_) => (
+            "😴 The browser used for booking is not available right now, try again later."
+                .to_string(),
+            "sleepy_cat",
+        ),
+        BookingError::ElementNotFound(failure) => (
+            format!(
+                "❌ The Contram site seems to have changed: the booking got stuck at step \"{}\".",
+                failure.step
+            ),
+            "error_cat",
+        ),
+        BookingError::SoldOut(_) => (
+            "😿 Sold out: there are no seats left for this trip.".to_string(),
+            "error_cat",
+        ),
+        BookingError::ConfirmationMissing(_) => (
+            "⚠️ The purchase was sent but never confirmed. Check your email before booking again."
+                .to_string(),
+            "error_cat",
+        ),
+        BookingError::Step(failure) => (format!("❌ {}", failure), "error_cat"),
+    }
+}
+
+async fn handle_bookreturn(
+    bot: Bot,
+    msg: Message,
+    backend: Arc<dyn BookingBackend>,
+    jobs: BookingJobs,
+    args: String,
+) -> HandlerResult {
+    let parts = split_args(&args);
+    if parts.len() != 4 {
+        send_message(
+            bot.clone(),
+            msg.chat.id,
+            "❌ Invalid command syntax.\nUsage: /bookreturn <from> <to> <outbound date> <return date> (YYYY-MM-DD)"
+                .to_string(),
+            Some("error_cat_invalid_syntax"),
+        )
+        .await;
+
+        return Err("Invalid command syntax".into());
+    }
+
+    let username = get_username(msg.clone()).await?;
+    get_registered_user(&bot, msg.chat.id, &username).await?;
+    let (id_from, id_to) =
+        parse_city_ids(&bot, msg.chat.id, backend.as_ref(), &parts, false).await?;
+    let outbound_date = parse_travel_date(&bot, msg.chat.id, &parts[2]).await?;
+    let return_date = parse_travel_date(&bot, msg.chat.id, &parts[3]).await?;
+
+    if return_date < outbound_date {
+        send_message(
+            bot.clone(),
+            msg.chat.id,
+            "❌ The return date must not be before the outbound date".to_string(),
+            Some("error_cat_invalid_syntax"),
+        )
+        .await;
+        return Err("Return date before outbound date".into());
+    }
+
+    let (city_from, city_to) =
+        checked_route(&bot, msg.chat.id, backend.as_ref(), id_from, id_to).await?;
+    let outbound = SearchQuery::new(id_from, id_to, parts[2].clone());
+    let outbound_due = booking_open_datetime(outbound_date).with_timezone(&Utc);
+    let return_due = booking_open_datetime(return_date).with_timezone(&Utc);
+
+    // Each leg opens for booking on its own day: when the return leg is already
+    // bookable once the outbound one opens, both share a single cart.
+    if return_due <= outbound_due.max(Utc::now()) {
+        let job = BookingJob::new(
+            msg.chat.id.0,
+            &username,
+            format!("{} → {}", city_from, city_to),
+            &outbound,
+            outbound_due,
+        )
+        .with_return_date(Some(parts[3].clone()));
+        return queue_job(&bot, msg.chat.id, &jobs, job).await;
+    }
+
+    // Otherwise the way back is a job of its own, linked to the outbound one so
+    // that both are cancelled and moved together
+    let outbound_job = BookingJob::new(
+        msg.chat.id.0,
+        &username,
+        format!("{} → {}", city_from, city_to),
+        &outbound,
+        outbound_due,
+    );
+    let outbound_job = store_job(&bot, msg.chat.id, &jobs, outbound_job).await?;
+    let return_job = BookingJob::new(
+        msg.chat.id.0,
+        &username,
+        format!("{} → {}", city_to, city_from),
+        &SearchQuery::new(id_to, id_from, parts[3].clone()),
+        return_due,
+    )
+    .with_outbound(outbound_job.id);
+    let return_job = match store_job(&bot, msg.chat.id, &jobs, return_job).await {
+        Ok(return_job) => return_job,
+        Err(e) => {
+            if let Err(e) = jobs
+                .store
+                .set_status(outbound_job.id, JobStatus::Cancelled, None)
+            {
+                println!("Failed to cancel booking job #{}: {}", outbound_job.id, e);
+            }
+            return Err(e);
+        }
+    };
+
+    send_message(
+        bot.clone(),
+        msg.chat.id,
+        format!(
+            "Return trip scheduled as booking #{} out, {}, and booking #{} back, {}.\nCancel both with /canceljob {}",
+            outbound_job.id,
+            job_start(&outbound_job),
+            return_job.id,
+            job_start(&return_job),
+            outbound_job.id
+        ),
+        Some("hourglass"),
+    )
+    .await;
+    jobs.schedule(outbound_job);
+    jobs.schedule(return_job);
+    Ok(())
+}
+
+async fn handle_bookgroup(
+    bot: Bot,
+    msg: Message,
+    backend: Arc<dyn BookingBackend>,
+    jobs: BookingJobs,
+    args: String,
+) -> HandlerResult {
+    // Passengers come after the date, separated by commas
+    let (parts, rest) = take_args(&args, 3);
+    let entries: Vec<&str> = rest
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .collect();
+    if parts.len() != 3 || entries.is_empty() {
+        send_message(
+            bot.clone(),
+            msg.chat.id,
+            "❌ Invalid command syntax.\nUsage: /bookgroup <from> <to> <date> <passenger>, <passenger>, ...\n\
+             A passenger is either @username of a registered user or\n\
+             First name;Last name;Email;Phone[;adult]"
+                .to_string(),
+            Some("error_cat_invalid_syntax"),
+        )
+        .await;
+
+        return Err("Invalid command syntax".into());
+    }
+
+    let username = get_username(msg.clone()).await?;
+    get_registered_user(&bot, msg.chat.id, &username).await?;
+    let (id_from, id_to) =
+        parse_city_ids(&bot, msg.chat.id, backend.as_ref(), &parts, false).await?;
+    let parsed_date = parse_travel_date(&bot, msg.chat.id, &parts[2]).await?;
+
+    // The sender is the buyer and always the first passenger, the others are
+    // checked now and looked up again when the booking runs
+    for entry in &entries {
+        if let Err(e) = parse_passenger(entry) {
+            send_message(
+                bot.clone(),
+                msg.chat.id,
+                format!("❌ {}", e),
+                Some("error_cat_invalid_syntax"),
+            )
+            .await;
+            return Err(e.into());
+        }
+    }
+
+    let (city_from, city_to) =
+        checked_route(&bot, msg.chat.id, backend.as_ref(), id_from, id_to).await?;
+    let job = BookingJob::new(
+        msg.chat.id.0,
+        &username,
+        format!("{} → {}", city_from, city_to),
+        &SearchQuery::new(id_from, id_to, parts[2].clone()),
+        booking_open_datetime(parsed_date).with_timezone(&Utc),
+    )
+    .with_companions(entries.into_iter().map(str::to_string).collect());
+    queue_job(&bot, msg.chat.id, &jobs, job).await
+}
+
+/// Parses a `/bookgroup` passenger: `@username` of a registered user, or an ad-hoc
+/// `First name;Last name;Email;Phone[;adult]` entry.
+fn parse_passenger(entry: &str) -> Result<Passenger, String> {
+    if let Some(username) = entry.strip_prefix('@') {
+        let file_manager = FileManager::new("users.json");
+        return file_manager
+            .get_user(username.to_string())
+            .map(|user| Passenger::student(user.user_data))
+            .map_err(|_| format!("@{} is not registered", username));
+    }
+
+    let fields: Vec<&str> = entry.split(';').map(str::trim).collect();
+    let (first_name, last_name, email, phone, kind) = match fields[..] {
+        [first_name, last_name, email, phone] => (first_name, last_name, email, phone, None),
+        [first_name, last_name, email, phone, kind] => {
+            (first_name, last_name, email, phone, Some(kind))
+        }
+        _ => return Err(format!("Invalid passenger: {}", entry)),
+    };
+
+    let user = User::new(
+        email.to_string(),
+        first_name.to_string(),
+        last_name.to_string(),
+        email.to_string(),
+        phone.to_string(),
+    );
+    match kind.map(str::to_ascii_lowercase).as_deref() {
+        None | Some("student") => Ok(Passenger::student(user)),
+        Some("adult") => Ok(Passenger::adult(user)),
+        Some(other) => Err(format!("Unknown passenger type: {}", other)),
+    }
+}
+
+async fn handle_timetable(
+    bot: Bot,
+    msg: Message,
+    backend: Arc<dyn BookingBackend>,
+    args: String,
+) -> HandlerResult {
+    let parts = split_args(&args);
+    if parts.len() != 3 {
+        send_message(
+            bot.clone(),
+            msg.chat.id,
+            "❌ Invalid command syntax.\nUsage: /timetable <from> <to> <date> (YYYY-MM-DD)"
+                .to_string(),
+            Some("error_cat_invalid_syntax"),
+        )
+        .await;
+
+        return Err("Invalid command syntax".into());
+    }
+
+    let (id_from, id_to) =
+        parse_city_ids(&bot, msg.chat.id, backend.as_ref(), &parts, false).await?;
+    if NaiveDate::parse_from_str(&parts[2], "%Y-%m-%d").is_err() {
+        send_message(
+            bot.clone(),
+            msg.chat.id,
+            "❌ Invalid date format (use YYYY-MM-DD)".to_string(),
+            Some("error_cat_invalid_syntax"),
+        )
+        .await;
+        return Err("Invalid date format".into());
+    }
+
+    let query = SearchQuery::new(id_from, id_to, parts[2].clone());
+    let (city_from, city_to, departures) = match load_timetable(backend.as_ref(), &query).await {
+        Ok(timetable) => timetable,
+        Err(e) => {
+            send_message(
+                bot.clone(),
+                msg.chat.id,
+                e.to_string(),
+                Some("error_cat_invalid_syntax"),
+            )
+            .await;
+            return Err(e.to_string().into());
+        }
+    };
+
+    let header = format!(
+        "🚌 {} → {}, {}",
+        html::escape(&city_from),
+        html::escape(&city_to),
+        query.date
+    );
+    if departures.is_empty() {
+        bot.send_message(msg.chat.id, format!("{}\nNo departures found.", header))
+            .parse_mode(ParseMode::Html)
+            .await?;
+        return Ok(());
+    }
+
+    let rows = departures
+        .iter()
+        .map(|departure| {
+            let or_dash = |value: Option<String>| value.unwrap_or_else(|| "-".to_string());
+            format!(
+                "{:<6} {:<6} {:>8} {:>5}",
+                departure.time.format("%H:%M"),
+                or_dash(
+                    departure
+                        .arrival_time
+                        .map(|t| t.format("%H:%M").to_string())
+                ),
+                or_dash(departure.price.map(|price| format!("{:.2} €", price))),
+                or_dash(departure.seats.map(|seats| seats.to_string())),
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+    let table = format!(
+        "{:<6} {:<6} {:>8} {:>5}\n{}",
+        "Dep.", "Arr.", "Price", "Seats", rows
+    );
+
+    // Each button carries the /bookticket arguments of its run
+    let keyboard = InlineKeyboardMarkup::new(departures.iter().map(|departure| {
+        let time = departure.time.format("%H:%M");
+        vec![InlineKeyboardButton::callback(
+            format!("Book this ({})", time),
+            format!("book:{} {} {} {}", id_from, id_to, query.date, time),
+        )]
+    }));
+
+    bot.send_message(
+        msg.chat.id,
+        format!("{}\n<pre>{}</pre>", header, html::escape(&table)),
+    )
+    .parse_mode(ParseMode::Html)
+    .reply_markup(keyboard)
+    .await?;
+    Ok(())
+}
+
+/// Returns the stop names and the runs of a trip.
+async fn load_timetable(
+    backend: &dyn BookingBackend,
+    query: &SearchQuery,
+) -> Result<(String, String, Vec<Departure>), Error> {
+    let (city_from, city_to) = check_route(backend, query.from_id, query.to_id).await?;
+    let departures = fetch_departures(backend, query).await?;
+    Ok((city_from, city_to, departures))
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn handle_watch(
+    bot: Bot,
+    msg: Message,
+    backend: Arc<dyn BookingBackend>,
+    mail: Option<Arc<MailIngest>>,
+    config: Arc<Config>,
+    watches: Arc<WatchList>,
+    receipts: Arc<ReceiptStore>,
+    args: String,
+) -> HandlerResult {
+    let mut parts = split_args(&args);
+    let action = match parts.iter().any(|part| part == NOTIFY_FLAG) {
+        true => WatchAction::Notify,
+        false => WatchAction::Book,
+    };
+    parts.retain(|part| part != NOTIFY_FLAG);
+    if !(3..=4).contains(&parts.len()) {
+        send_message(
+            bot.clone(),
+            msg.chat.id,
+            "❌ Invalid command syntax.\nUsage: /watch <from> <to> <date> (YYYY-MM-DD) [time (HH:MM or HH:MM-HH:MM)] [--notify]"
+                .to_string(),
+            Some("error_cat_invalid_syntax"),
+        )
+        .await;
+
+        return Err("Invalid command syntax".into());
+    }
+
+    let username = get_username(msg.clone()).await?;
+    let user = get_registered_user(&bot, msg.chat.id, &username).await?;
+    let (id_from, id_to) =
+        parse_city_ids(&bot, msg.chat.id, backend.as_ref(), &parts, false).await?;
+    let parsed_date = parse_travel_date(&bot, msg.chat.id, &parts[2]).await?;
+    let departure = parse_departure(&bot, msg.chat.id, parts.get(3)).await?;
+    let (city_from, city_to) =
+        checked_route(&bot, msg.chat.id, backend.as_ref(), id_from, id_to).await?;
+
+    let watch = Watch {
+        id: watches.next_id(),
+        chat_id: msg.chat.id.0,
+        username,
+        query: SearchQuery::new(id_from, id_to, parts[2].clone()).with_departure(departure),
+        route: format!("{} → {}", city_from, city_to),
+        action,
+        started: chrono::Local::now(),
+    };
+    // Seats are of no use once the day of the trip has come
+    let until = Rome
+        .from_local_datetime(&parsed_date.and_hms_opt(0, 0, 0).unwrap())
+        .unwrap();
+    let passenger = mailbox_buyer(&mail, Passenger::student(user.user_data));
+    let task = tokio::spawn(run_watch(
+        bot.clone(),
+        backend,
+        mail,
+        config,
+        watches.clone(),
+        receipts,
+        watch.clone(),
+        passenger,
+        until,
+    ));
+    watches.insert(watch.clone(), task.abort_handle());
+
+    send_message(
+        bot,
+        msg.chat.id,
+        format!(
+            "👀 Watching for a free seat: {}\nStop with /unwatch {}",
+            watch, watch.id
+        ),
+        None,
+    )
+    .await;
+    Ok(())
+}
+
+/// Waits for a seat on the run of `watch`, then books it or tells about it.
+#[allow(clippy::too_many_arguments)]
+async fn run_watch(
+    bot: Bot,
+    backend: Arc<dyn BookingBackend>,
+    mail: Option<Arc<MailIngest>>,
+    config: Arc<Config>,
+    watches: Arc<WatchList>,
+    receipts: Arc<ReceiptStore>,
+    watch: Watch,
+    passenger: Passenger,
+    until: DateTime<Tz>,
+) {
+    let chat_id = ChatId(watch.chat_id);
+    let passengers = [passenger];
+    loop {
+        let remaining = (until - Utc::now().with_timezone(&Rome))
+            .to_std()
+            .unwrap_or_default();
+        let departure = match tokio::time::timeout(
+            remaining,
+            watches.wait_for_seat(backend.as_ref(), &watch.query),
+        )
+        .await
+        {
+            Ok(departure) => departure,
+            Err(_) => {
+                send_message(
+                    bot,
+                    chat_id,
+                    format!("⌛ No seat freed up, watch #{} ended.", watch.id),
+                    None,
+                )
+                .await;
+                return;
+            }
+        };
+        println!("Watch #{} found a seat on the {} run", watch.id, departure);
+        let time = departure.time.format("%H:%M").to_string();
+
+        if watch.action == WatchAction::Notify {
+            send_message(
+                bot,
+                chat_id,
+                format!(
+                    "🔔 A seat is free on the {} run {} on {}.\nBook it with /bookticket {} {} {} {}",
+                    time,
+                    watch.route,
+                    watch.query.date,
+                    watch.query.from_id,
+                    watch.query.to_id,
+                    watch.query.date,
+                    time
+                ),
+                Some("success_cat"),
+            )
+            .await;
+            return;
+        }
+
+        // Book the run that was found, not whichever else matches the filter
+        let query = watch
+            .query
+            .clone()
+            .with_departure(Some(DepartureFilter::At(departure.time)));
+        match book_retrying(
+            &bot,
+            chat_id,
+            backend.as_ref(),
+            &config.retry,
+            &passengers,
+            std::slice::from_ref(&query),
+            None,
+        )
+        .await
+        {
+            Ok(receipt) => {
+                println!("Response from book_ticket: {}", receipt);
+                keep_receipt(
+                    &receipts,
+                    &mail,
+                    chat_id,
+                    &watch.username,
+                    &passengers,
+                    &receipt,
+                );
+                send_message(
+                    bot,
+                    chat_id,
+                    format!("👀 Watch #{} got a seat!\n{}", watch.id, receipt),
+                    Some("success_cat"),
+                )
+                .await;
+                return;
+            }
+            // Someone else was quicker, keep watching
+            Err(e) if matches!(e.downcast_ref(), Some(BookingError::SoldOut(_))) => {
+                println!("Watch #{} lost the seat: {}", watch.id, e);
+            }
+            Err(e) => {
+                report_booking_error(&bot, chat_id, &e).await;
+                return;
+            }
+        }
+    }
+}
+
+async fn handle_watches(bot: Bot, msg: Message, watches: Arc<WatchList>) -> HandlerResult {
+    let watches = watches.list(msg.chat.id.0);
+    if watches.is_empty() {
+        bot.send_message(
+            msg.chat.id,
+            "No seat watches running.\nStart one with /watch.",
+        )
+        .await?;
+        return Ok(());
+    }
+
+    let watches_list = watches
+        .iter()
+        .map(|watch| watch.to_string())
+        .collect::<Vec<String>>()
+        .join("\n");
+    bot.send_message(msg.chat.id, format!("👀 Seat watches:\n{}", watches_list))
+        .await?;
+    Ok(())
+}
+
+async fn handle_unwatch(
+    bot: Bot,
+    msg: Message,
+    watches: Arc<WatchList>,
+    args: String,
+) -> HandlerResult {
+    let Ok(id) = args.trim().trim_start_matches('#').parse::<u64>() else {
+        send_message(
+            bot.clone(),
+            msg.chat.id,
+            "❌ Invalid command syntax.\nUsage: /unwatch <id> (see /watches)".to_string(),
+            Some("error_cat_invalid_syntax"),
+        )
+        .await;
+        return Err("Invalid command syntax".into());
+    };
+
+    match watches.cancel(msg.chat.id.0, id) {
+        Some(watch) => {
+            bot.send_message(msg.chat.id, format!("✅ Stopped watch {}", watch))
+                .await?;
+        }
+        None => {
+            bot.send_message(msg.chat.id, format!("❌ No watch #{} running", id))
+                .await?;
+        }
+    }
+    Ok(())
+}
+
+async fn handle_myjobs(bot: Bot, msg: Message, jobs: BookingJobs) -> HandlerResult {
+    let (pending, finished): (Vec<BookingJob>, Vec<BookingJob>) = jobs
+        .store
+        .list()
+        .into_iter()
+        .filter(|job| job.chat_id == msg.chat.id.0)
+        .partition(|job| matches!(job.status, JobStatus::Pending | JobStatus::Running));
+    if pending.is_empty() && finished.is_empty() {
+        bot.send_message(
+            msg.chat.id,
+            "No scheduled bookings.\nSchedule one with /bookticket.",
+        )
+        .await?;
+        return Ok(());
+    }
+
+    let list = |jobs: Vec<&BookingJob>| {
+        jobs.iter()
+            .map(|job| job.to_string())
+            .collect::<Vec<String>>()
+            .join("\n")
+    };
+    let mut text = match pending.is_empty() {
+        true => "🗓️ No pending bookings.".to_string(),
+        false => format!(
+            "🗓️ Pending bookings:\n{}\n\nMove one with /reschedulejob <id> <date>",
+            list(pending.iter().collect())
+        ),
+    };
+    if !finished.is_empty() {
+        text.push_str(&format!(
+            "\n\nRecent bookings:\n{}",
+            list(finished.iter().rev().take(RECENT_JOBS).collect())
+        ));
+    }
+
+    // Bookings already running can no longer be stopped
+    let keyboard = InlineKeyboardMarkup::new(
+        pending
+            .iter()
+            .filter(|job| job.status == JobStatus::Pending)
+            .map(|job| {
+                vec![InlineKeyboardButton::callback(
+                    format!("Cancel #{} ({})", job.id, job.date),
+                    format!("canceljob:{}", job.id),
+                )]
+            }),
+    );
+    bot.send_message(msg.chat.id, text)
+        .reply_markup(keyboard)
+        .await?;
+    Ok(())
+}
+
+/// Parses the `#id` of a booking job, showing the user `usage` otherwise.
+async fn parse_job_id(
+    bot: &Bot,
+    chat_id: ChatId,
+    id: &str,
+    usage: &str,
+) -> Result<u64, HandlerError> {
+    match id.trim().trim_start_matches('#').parse::<u64>() {
+        Ok(id) => Ok(id),
+        Err(_) => {
+            send_message(
+                bot.clone(),
+                chat_id,
+                format!("❌ Invalid command syntax.\nUsage: {} (see /myjobs)", usage),
+                Some("error_cat_invalid_syntax"),
+            )
+            .await;
+            Err("Invalid command syntax".into())
+        }
+    }
+}
+
+/// Takes pending job `id` of `chat_id` off the queue, telling the user why when
+/// it cannot be.
+async fn unqueue_job(
+    bot: &Bot,
+    chat_id: ChatId,
+    jobs: &BookingJobs,
+    id: u64,
+) -> Result<BookingJob, HandlerError> {
+    let Some(job) = jobs.store.get(id).filter(|job| job.chat_id == chat_id.0) else {
+        bot.send_message(chat_id, format!("❌ No booking #{}", id))
+            .await?;
+        return Err("Unknown job".into());
+    };
+    if job.status != JobStatus::Pending {
+        bot.send_message(
+            chat_id,
+            format!(
+                "❌ Booking #{} is {}, only pending bookings can be changed.",
+                id, job.status
+            ),
+        )
+        .await?;
+        return Err("Job not pending".into());
+    }
+    // The job may have been handed to the booking a moment ago
+    if jobs.scheduler.cancel(id).is_none() {
+        bot.send_message(chat_id, format!("❌ Booking #{} has already started.", id))
+            .await?;
+        return Err("Job already started".into());
+    }
+    Ok(job)
+}
+
+async fn cancel_job(bot: &Bot, chat_id: ChatId, jobs: &BookingJobs, id: u64) -> HandlerResult {
+    let job = unqueue_job(bot, chat_id, jobs, id).await?;
+    let mut legs = vec![job];
+    legs.extend(unqueue_other_leg(jobs, id));
+    for (index, leg) in legs.iter().enumerate() {
+        if let Err(e) = jobs.store.set_status(leg.id, JobStatus::Cancelled, None) {
+            // Left pending on disk, so the booking would run after a restart anyway
+            for leg in &legs[index..] {
+                jobs.schedule(leg.clone());
+            }
+            send_message(
+                bot.clone(),
+                chat_id,
+                format!("❌ Failed to cancel booking #{}: {}", leg.id, e),
+                Some("error_cat"),
+            )
+            .await;
+            return Err(e.to_string().into());
+        }
+        println!("Booking job #{} cancelled", leg.id);
+    }
+
+    let ids = legs
+        .iter()
+        .map(|leg| format!("#{}", leg.id))
+        .collect::<Vec<_>>()
+        .join(" and ");
+    bot.send_message(chat_id, format!("✅ Cancelled booking {}", ids))
+        .await?;
+    Ok(())
+}
+
+/// Takes the other leg of the return trip of job `id` off the queue, when it is a
+/// job of its own still waiting to run.
+fn unqueue_other_leg(jobs: &BookingJobs, id: u64) -> Option<BookingJob> {
+    jobs.store.trip(id).into_iter().find(|leg| {
+        leg.id != id && leg.status == JobStatus::Pending && jobs.scheduler.cancel(leg.id).is_some()
+    })
+}
+
+async fn handle_canceljob(
+    bot: Bot,
+    msg: Message,
+    jobs: BookingJobs,
+    args: String,
+) -> HandlerResult {
+    let id = parse_job_id(&bot, msg.chat.id, &args, "/canceljob <id>").await?;
+    cancel_job(&bot, msg.chat.id, &jobs, id).await
+}
+
+async fn handle_reschedulejob(
+    bot: Bot,
+    msg: Message,
+    jobs: BookingJobs,
+    args: String,
+) -> HandlerResult {
+    let usage = "/reschedulejob <id> <date> (YYYY-MM-DD)";
+    let parts = split_args(&args);
+    let [id, date] = parts.as_slice() else {
+        send_message(
+            bot.clone(),
+            msg.chat.id,
+            format!("❌ Invalid command syntax.\nUsage: {} (see /myjobs)", usage),
+            Some("error_cat_invalid_syntax"),
+        )
+        .await;
+        return Err("Invalid command syntax".into());
+    };
+    let id = parse_job_id(&bot, msg.chat.id, id, usage).await?;
+    let parsed_date = parse_travel_date(&bot, msg.chat.id, date).await?;
+
+    let job = unqueue_job(&bot, msg.chat.id, &jobs, id).await?;
+    // The other leg of a return trip moves by as many days, keeping the trip length
+    let travel_date = |job: &BookingJob| NaiveDate::parse_from_str(&job.date, "%Y-%m-%d");
+    let shift = travel_date(&job)
+        .map(|old_date| parsed_date - old_date)
+        .unwrap_or_default();
+    let mut moves = vec![(job, parsed_date)];
+    if let Some(leg) = unqueue_other_leg(&jobs, id) {
+        let leg_date = travel_date(&leg).map_or(parsed_date, |leg_date| leg_date + shift);
+        moves.push((leg, leg_date));
+    }
+
+    let today = Utc::now().with_timezone(&Rome).date_naive();
+    let mut lines = Vec::new();
+    for (index, (leg, leg_date)) in moves.iter().enumerate() {
+        let due = booking_open_datetime(*leg_date).with_timezone(&Utc);
+        let moved = match *leg_date < today {
+            true => Err(Error::msg(format!("{} has already passed", leg_date))),
+            false => jobs.store.reschedule(leg.id, leg_date.to_string(), due),
+        };
+        match moved {
+            Ok(moved) => {
+                println!("Booking job #{} moved to {}", moved.id, moved.date);
+                lines.push(format!(
+                    "🗓️ Booking #{} moved to {}, {}.",
+                    moved.id,
+                    moved.date,
+                    job_start(&moved)
+                ));
+                jobs.schedule(moved);
+            }
+            Err(e) => {
+                for (leg, _) in &moves[index..] {
+                    jobs.schedule(leg.clone());
+                }
+                lines.push(format!(
+                    "❌ Failed to reschedule booking #{}: {}",
+                    leg.id, e
+                ));
+                send_message(bot, msg.chat.id, lines.join("\n"), Some("error_cat")).await;
+                return Err(e.to_string().into());
+            }
+        }
+    }
+
+    send_message(bot, msg.chat.id, lines.join("\n"), Some("hourglass")).await;
+    Ok(())
+}
+
+async fn handle_recurring(
+    bot: Bot,
+    msg: Message,
+    backend: Arc<dyn BookingBackend>,
+    jobs: BookingJobs,
+    args: String,
+) -> HandlerResult {
+    let mut parts = split_args(&args);
+    let until = match parts.iter().position(|part| part == UNTIL_FLAG) {
+        Some(position) if position + 1 < parts.len() => {
+            let until = parts.remove(position + 1);
+            parts.remove(position);
+            Some(until)
+        }
+        Some(_) => {
+            parts.clear();
+            None
+        }
+        None => None,
+    };
+    if !(3..=4).contains(&parts.len()) {
+        send_message(
+            bot.clone(),
+            msg.chat.id,
+            "❌ Invalid command syntax.\nUsage: /recurring <from> <to> <weekdays> (mon,wed or mon-fri) [time (HH:MM or HH:MM-HH:MM)] [--until YYYY-MM-DD]"
+                .to_string(),
+            Some("error_cat_invalid_syntax"),
+        )
+        .await;
+
+        return Err("Invalid command syntax".into());
+    }
+
+    let username = get_username(msg.clone()).await?;
+    get_registered_user(&bot, msg.chat.id, &username).await?;
+    let (id_from, id_to) =
+        parse_city_ids(&bot, msg.chat.id, backend.as_ref(), &parts, false).await?;
+    let weekdays = match parse_weekdays(&parts[2]) {
+        Ok(weekdays) => weekdays,
+        Err(e) => {
+            send_message(
+                bot.clone(),
+                msg.chat.id,
+                format!("❌ {}", e),
+                Some("error_cat_invalid_syntax"),
+            )
+            .await;
+            return Err(e.to_string().into());
+        }
+    };
+    let departure = parse_departure(&bot, msg.chat.id, parts.get(3)).await?;
+    let until = match until {
+        Some(until) => Some(parse_travel_date(&bot, msg.chat.id, &until).await?),
+        None => None,
+    };
+    let (city_from, city_to) =
+        checked_route(&bot, msg.chat.id, backend.as_ref(), id_from, id_to).await?;
+
+    let rule = RecurringRule::new(
+        msg.chat.id.0,
+        &username,
+        format!("{} → {}", city_from, city_to),
+        (id_from, id_to),
+        weekdays,
+    )
+    .with_departure(departure)
+    .with_until(until);
+    let rule = match jobs.recurring.add(rule) {
+        Ok(rule) => rule,
+        Err(e) => {
+            send_message(
+                bot.clone(),
+                msg.chat.id,
+                format!("❌ Failed to save the recurring booking: {}", e),
+                Some("error_cat"),
+            )
+            .await;
+            return Err(e.to_string().into());
+        }
+    };
+
+    send_message(
+        bot.clone(),
+        msg.chat.id,
+        format!(
+            "🔁 Recurring booking {} added, each trip is scheduled when its date comes within a week.\nPause it with /pauserecurring {}",
+            rule, rule.id
+        ),
+        Some("success_cat"),
+    )
+    .await;
+    schedule_recurring(&bot, &jobs).await;
+    Ok(())
+}
+
+async fn handle_recurrings(bot: Bot, msg: Message, jobs: BookingJobs) -> HandlerResult {
+    let rules: Vec<RecurringRule> = jobs
+        .recurring
+        .list()
+        .into_iter()
+        .filter(|rule| rule.chat_id == msg.chat.id.0)
+        .collect();
+    if rules.is_empty() {
+        bot.send_message(
+            msg.chat.id,
+            "No recurring bookings.\nAdd one with /recurring.",
+        )
+        .await?;
+        return Ok(());
+    }
+
+    let rules_list = rules
+        .iter()
+        .map(|rule| rule.to_string())
+        .collect::<Vec<String>>()
+        .join("\n");
+    bot.send_message(
+        msg.chat.id,
+        format!("🔁 Recurring bookings:\n{}", rules_list),
+    )
+    .await?;
+    Ok(())
+}
+
+/// Pauses or resumes a recurring booking; bookings it already scheduled still run.
+async fn handle_pause_recurring(
+    bot: Bot,
+    msg: Message,
+    jobs: BookingJobs,
+    args: String,
+    paused: bool,
+) -> HandlerResult {
+    let command = match paused {
+        true => "pauserecurring",
+        false => "resumerecurring",
+    };
+    let Ok(id) = args.trim().trim_start_matches('#').parse::<u64>() else {
+        send_message(
+            bot.clone(),
+            msg.chat.id,
+            format!(
+                "❌ Invalid command syntax.\nUsage: /{} <id> (see /recurrings)",
+                command
+            ),
+            Some("error_cat_invalid_syntax"),
+        )
+        .await;
+        return Err("Invalid command syntax".into());
+    };
+
+    match jobs.recurring.set_paused(msg.chat.id.0, id, paused)? {
+        Some(rule) if paused => {
+            bot.send_message(msg.chat.id, format!("⏸️ Paused recurring booking {}", rule))
+                .await?;
+        }
+        Some(rule) => {
+            bot.send_message(
+                msg.chat.id,
+                format!("▶️ Resumed recurring booking {}", rule),
+            )
+            .await?;
+            schedule_recurring(&bot, &jobs).await;
+        }
+        None => {
+            bot.send_message(msg.chat.id, format!("❌ No recurring booking #{}", id))
+                .await?;
+        }
+    }
+    Ok(())
+}
+
+async fn handle_callback_query(
+    bot: Bot,
+    q: CallbackQuery,
+    backend: Arc<dyn BookingBackend>,
+    mail: Option<Arc<MailIngest>>,
+    jobs: BookingJobs,
+) -> HandlerResult {
+    bot.answer_callback_query(q.id.clone()).await?;
+
+    let Some(chat_id) = q.message.as_ref().map(|message| message.chat().id) else {
+        return Ok(());
+    };
+    let Some(username) = q.from.username.clone() else {
+        bot.send_message(chat_id, "❌ Could not identify username")
+            .await?;
+        return Ok(());
+    };
+
+    match q.data.as_deref().and_then(|data| data.split_once(':')) {
+        Some(("book", args)) => {
+            request_booking(bot, chat_id, &username, backend, mail, jobs, args).await
+        }
+        Some(("canceljob", id)) => match id.parse() {
+            Ok(id) => cancel_job(&bot, chat_id, &jobs, id).await,
+            Err(_) => Ok(()),
+        },
+        _ => Ok(()),
+    }
+}
+
+async fn get_registered_user(
+    bot: &Bot,
+    chat_id: ChatId,
+    username: &str,
+) -> Result<TelegramUser, HandlerError> {
+    let file_manager = FileManager::new("users.json");
+
+    match file_manager.get_user(username.to_string()) {
+        Ok(user) => Ok(user),
+        Err(e) => {
+            bot.send_message(
+                chat_id,
+                match e.kind() {
+                    // Handle file not found specifically
+                    ErrorKind::NotFound => {
+                        "❌ No user registered yet!\nUse /createuser to register."
+                    }
+                    _ => "❌ Failed to access user data. Please try again later.",
+                },
+            )
+            .await?;
+            Err("User not found".into())
+        }
+    }
+}
+
+/// Splits command arguments on whitespace, keeping "quoted text" together, and
+/// returns at most `max` of them along with the unsplit rest.
+fn take_args(args: &str, max: usize) -> (Vec<String>, &str) {
+    let mut parts = Vec::new();
+    let mut rest = args.trim_start();
+    while parts.len() < max && !rest.is_empty() {
+        let (part, tail) = match rest.strip_prefix(['"', '“']) {
+            Some(quoted) => quoted.split_once(['"', '”']).unwrap_or((quoted, "")),
+            None => rest.split_once(char::is_whitespace).unwrap_or((rest, "")),
+        };
+        parts.push(part.to_string());
+        rest = tail.trim_start();
+    }
+    (parts, rest)
+}
+
+fn split_args(args: &str) -> Vec<String> {
+    take_args(args, usize::MAX).0
+}
+
+/// Joins arguments back into a command line, quoting those with spaces.
+fn join_args(args: &[String]) -> String {
+    args.iter()
+        .map(|arg| match arg.contains(char::is_whitespace) {
+            true => format!("\"{}\"", arg),
+            false => arg.clone(),
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Resolves the stops of a route, the first two of `args`, given as IDs or names,
+/// and checks that a line links them.
+///
+/// When a name fits several stops the user is asked to pick one; with `rebook`
+/// each choice is a button that reruns `/bookticket` with the same arguments.
+async fn parse_city_ids(
+    bot: &Bot,
+    chat_id: ChatId,
+    backend: &dyn BookingBackend,
+    args: &[String],
+    rebook: bool,
+) -> Result<(u32, u32), HandlerError> {
+    let mut args = args.to_vec();
+    let cities = match backend.get_cities().await {
+        Ok(cities) => cities,
+        Err(e) => return Err(report_city_lookup(bot, chat_id, e).await),
+    };
+
+    let (city_from, id_from) = match find_stop(&cities, &args[0]) {
+        StopMatch::Found(name, id) => (name, id),
+        StopMatch::Ambiguous(candidates) => {
+            return ask_stop(bot, chat_id, &args, 0, &candidates, rebook).await;
+        }
+        StopMatch::NotFound => {
+            send_message(
+                bot.clone(),
+                chat_id,
+                format!("❌ Departure city not found: {}", args[0]),
+                Some("error_cat_invalid_syntax"),
+            )
+            .await;
+            return Err("Departure city not found".into());
+        }
+    };
+    args[0] = id_from.to_string();
+
+    // Only stops served from the departure are valid arrivals
+    let destinations = match backend.get_destinations(id_from).await {
+        Ok(destinations) => destinations,
+        Err(e) => return Err(report_city_lookup(bot, chat_id, e).await),
+    };
+    let id_to = match find_stop(&destinations, &args[1]) {
+        StopMatch::Found(_, id) => id,
+        StopMatch::Ambiguous(candidates) => {
+            return ask_stop(bot, chat_id, &args, 1, &candidates, rebook).await;
+        }
+        StopMatch::NotFound => {
+            let error = format!("No line from {} to {}", city_from, args[1]);
+            send_message(
+                bot.clone(),
+                chat_id,
+                format!("❌ {}", error),
+                Some("error_cat_invalid_syntax"),
+            )
+            .await;
+            return Err(error.into());
+        }
+    };
+
+    Ok((id_from, id_to))
+}
+
+/// Tells the user that the stops could not be fetched, returning the error for the
+/// handler.
+async fn report_city_lookup(bot: &Bot, chat_id: ChatId, error: Error) -> HandlerError {
+    let error = Error::from(BookingError::CityLookup(error));
+    report_booking_error(bot, chat_id, &error).await;
+    error.to_string().into()
+}
+
+/// Returns the stop names of a route like [`check_route`], telling the user when
+/// the stops cannot be fetched or no line links them.
+async fn checked_route(
+    bot: &Bot,
+    chat_id: ChatId,
+    backend: &dyn BookingBackend,
+    from_id: u32,
+    to_id: u32,
+) -> Result<(String, String), HandlerError> {
+    match check_route(backend, from_id, to_id).await {
+        Ok(names) => Ok(names),
+        Err(e) => {
+            let error = Error::from(e);
+            report_booking_error(bot, chat_id, &error).await;
+            Err(error.to_string().into())
+        }
+    }
+}
+
+/// Asks which of `candidates` the stop at `args[position]` refers to.
+async fn ask_stop(
+    bot: &Bot,
+    chat_id: ChatId,
+    args: &[String],
+    position: usize,
+    candidates: &[(String, u32)],
+    rebook: bool,
+) -> Result<(u32, u32), HandlerError> {
+    let list = candidates
+        .iter()
+        .map(|(name, id)| format!("{}. {}", id, name))
+        .collect::<Vec<_>>()
+        .join("\n");
+    let mut request = bot.send_message(
+        chat_id,
+        format!(
+            "🤔 \"{}\" matches several cities, which one did you mean?\n{}",
+            args[position], list
+        ),
+    );
+
+    // Callback data is limited to 64 bytes
+    let callbacks: Vec<String> = candidates
+        .iter()
+        .map(|(_, id)| {
+            let mut args = args.to_vec();
+            args[position] = id.to_string();
+            format!("book:{}", join_args(&args))
+        })
+        .collect();
+    if rebook && callbacks.iter().all(|data| data.len() <= 64) {
+        request = request.reply_markup(InlineKeyboardMarkup::new(
+            candidates
+                .iter()
+                .zip(callbacks)
+                .map(|((name, _), data)| vec![InlineKeyboardButton::callback(name.clone(), data)]),
+        ));
+    }
+    request.await?;
+
+    Err("Ambiguous city name".into())
+}
+
+/// Parses a travel date, which must be at least one day ahead.
+async fn parse_travel_date(
+    bot: &Bot,
+    chat_id: ChatId,
+    date: &str,
+) -> Result<NaiveDate, HandlerError> {
+    let parsed_date = match NaiveDate::parse_from_str(date, "%Y-%m-%d") {
+        Ok(d) => d,
+        Err(_) => {
+            send_message(
+                bot.clone(),
+                chat_id,
+                "❌ Invalid date format (use YYYY-MM-DD)".to_string(),
+                Some("error_cat_invalid_syntax"),
+            )
+            .await;
+            return Err("Invalid date format".into());
+        }
+    };
+
+    // Create DateTime in Rome timezone at midnight
+    let parsed_datetime = Rome
+        .from_local_datetime(&parsed_date.and_hms_opt(0, 0, 0).unwrap())
+        .unwrap();
+
+    // Get current time in Rome
+    let now = Utc::now().with_timezone(&Rome);
+
+    if parsed_datetime <= now + Days::new(1) {
+        send_message(
+            bot.clone(),
+            chat_id,
+            "❌ Invalid date".to_string(),
+            Some("error_cat_invalid_syntax"),
+        )
+        .await;
+        return Err("Invalid date".into());
+    }
+
+    Ok(parsed_date)
+}
+
+async fn handle_help(bot: Bot, msg: Message) -> HandlerResult {
+    let help_text = Command::descriptions().to_string();
+    bot.send_message(msg.chat.id, help_text).await?;
+    Ok(())
+}
+
+async fn handle_cancel(bot: Bot, dialogue: MyDialogue, msg: Message) -> HandlerResult {
+    handle_stop(bot, dialogue, msg).await
+}
+
+async fn send_message(bot: Bot, chat_id: ChatId, response: String, sticker_id: Option<&str>) {
+    bot.send_message(chat_id, response)
+        .await
+        .log_on_error()
+        .await;
+    if let Some(sticker_id) = sticker_id {
+        send_cached_sticker(
+            bot,
+            chat_id,
+            get_stickers().get(sticker_id).unwrap().to_string(),
+        )
+        .await;
+    }
+}
+
+// Main command handler
+#[allow(clippy::too_many_arguments)]
+async fn handle_command(
+    bot: Bot,
+    dialogue: MyDialogue,
+    msg: Message,
+    cmd: Command,
+    backend: Arc<dyn BookingBackend>,
+    config: Arc<Config>,
+    mail: Option<Arc<MailIngest>>,
+    watches: Arc<WatchList>,
+    jobs: BookingJobs,
+) -> HandlerResult {
+    match cmd {
+        Command::Start => handle_start(bot, dialogue, msg).await,
+        Command::Createuser => handle_createuser(bot, dialogue, msg).await,
+        Command::Getuser => handle_getuser(bot, msg).await,
+        Command::Deleteuser => handle_deleteuser(bot, msg).await,
+        Command::Getcities(args) => handle_getcities(bot, msg, backend, args).await,
+        Command::Bookticket(args) => handle_bookticket(bot, msg, backend, mail, jobs, args).await,
+        Command::Bookreturn(args) => handle_bookreturn(bot, msg, backend, jobs, args).await,
+        Command::Bookgroup(args) => handle_bookgroup(bot, msg, backend, jobs, args).await,
+        Command::Timetable(args) => handle_timetable(bot, msg, backend, args).await,
+        Command::Watch(args) => {
+            let receipts = jobs.receipts.clone();
+            handle_watch(bot, msg, backend, mail, config, watches, receipts, args).await
+        }
+        Command::Watches => handle_watches(bot, msg, watches).await,
+        Command::Unwatch(args) => handle_unwatch(bot, msg, watches, args).await,
+        Command::Myjobs => handle_myjobs(bot, msg, jobs).await,
+        Command::Canceljob(args) => handle_canceljob(bot, msg, jobs, args).await,
+        Command::Reschedulejob(args) => handle_reschedulejob(bot, msg, jobs, args).await,
+        Command::Recurring(args) => handle_recurring(bot, msg, backend, jobs, args).await,
+        Command::Recurrings => handle_recurrings(bot, msg, jobs).await,
+        Command::Pauserecurring(args) => handle_pause_recurring(bot, msg, jobs, args, true).await,
+        Command::Resumerecurring(args) => handle_pause_recurring(bot, msg, jobs, args, false).await,
+        Command::Refreshcities => handle_refreshcities(bot, msg, backend, config).await,
+        Command::Sessions => handle_sessions(bot, msg, backend, config).await,
+        Command::Help => handle_help(bot, msg).await,
+        Command::Cancel => handle_cancel(bot, dialogue, msg).await,
+    }
+}
+
+async fn handle_invalid_command(bot: Bot, msg: Message) -> HandlerResult {
+    bot.send_message(
+        msg.chat.id,
+        "⚠️ Invalid command syntax. Use /help for command usage.",
+    )
+    .await?;
+    Ok(())
+}
+
+async fn handle_unexpected_messages(bot: Bot, _: MyDialogue, msg: Message) -> HandlerResult {
+    bot.send_message(
+        msg.chat.id,
+        "⚠️ Please use commands to interact with me!\n\nAvailable commands:\n/help - Show all commands",
+    ).await?;
+    Ok(())
+}
+
+async fn handle_stop(bot: Bot, dialogue: MyDialogue, msg: Message) -> HandlerResult {
+    match dialogue.get().await? {
+        Some(State::Start) => {
+            send_cached_sticker(
+                bot.clone(),
+                msg.chat.id,
                 get_stickers().get("sleepy_cat").unwrap().to_string(),
             )
             .await;
@@ -0,0 +1,5 @@
+pub use user::User;
+
+pub mod bot;
+pub mod user;
+pub mod utils;
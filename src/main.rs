@@ -1,10 +1,5 @@
-use bot::*;
 use color_eyre::eyre::Error;
-use user::*;
-
-mod bot;
-mod user;
-mod utils;
+use contram_ticket_automated::bot::bot_init;
 
 #[tokio::main]
 async fn main() -> Result<(), Error> {
@@ -53,6 +53,94 @@ impl User {
     pub fn get_first_name(&self) -> String {
         self.first_name.clone()
     }
+
+    /// Booking form fields of this user as the `index`-th passenger.
+    ///
+    /// The names mirror the serde renames above; only the first passenger is the
+    /// buyer and carries `EmailAcquirente`.
+    pub fn form_fields(&self, index: usize) -> Vec<(String, String)> {
+        let mut fields = Vec::new();
+        if index == 0 {
+            fields.push(("EmailAcquirente".to_string(), self.personal_email.clone()));
+        }
+        fields.extend([
+            (
+                format!("Nominativi[{}].Nome", index),
+                self.first_name.clone(),
+            ),
+            (
+                format!("Nominativi[{}].Cognome", index),
+                self.last_name.clone(),
+            ),
+            (format!("Nominativi[{}].Email", index), self.email.clone()),
+            (
+                format!("Nominativi[{}].Telefono", index),
+                self.phone.clone(),
+            ),
+        ]);
+        fields
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PassengerKind {
+    Student,
+    Adult,
+}
+
+/// A traveller in a booking, counted as a student or an adult by the site.
+#[derive(Debug, Clone)]
+pub struct Passenger {
+    pub user: User,
+    pub kind: PassengerKind,
+    /// Where the purchase email goes instead of the user's own address, for the
+    /// buyer of the booking.
+    pub buyer_email: Option<String>,
+}
+
+impl Passenger {
+    pub fn student(user: User) -> Self {
+        Self {
+            user,
+            kind: PassengerKind::Student,
+            buyer_email: None,
+        }
+    }
+
+    pub fn adult(user: User) -> Self {
+        Self {
+            user,
+            kind: PassengerKind::Adult,
+            buyer_email: None,
+        }
+    }
+
+    /// Booking form fields of this passenger as the `index`-th one.
+    pub fn form_fields(&self, index: usize) -> Vec<(String, String)> {
+        let mut fields = self.user.form_fields(index);
+        if let Some(email) = &self.buyer_email {
+            for (name, value) in fields.iter_mut() {
+                if name == "EmailAcquirente" {
+                    *value = email.clone();
+                }
+            }
+        }
+        fields
+    }
+}
+
+impl Display for Passenger {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        let kind = match self.kind {
+            PassengerKind::Student => "student",
+            PassengerKind::Adult => "adult",
+        };
+        write!(
+            f,
+            "{} {} ({}), {}",
+            self.user.first_name, self.user.last_name, kind, self.user.email
+        )
+    }
 }
 
 impl Display for User {
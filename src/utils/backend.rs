@@ -0,0 +1,534 @@
+use std::{fmt::Display, path::Path, str::FromStr, sync::Arc};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use color_eyre::eyre::Error;
+
+use crate::user::{Passenger, PassengerKind};
+use crate::utils::booking::{SeleniumBackend, validate_city_id};
+use crate::utils::booking_error::BookingError;
+use crate::utils::config::Config;
+use crate::utils::departures::{Departure, DepartureFilter, select_departure};
+use crate::utils::diagnostics::{BookingFailure, PageSnapshot, classify, diagnose};
+use crate::utils::driver::DriverSupervisor;
+use crate::utils::http_booking::HttpBackend;
+use crate::utils::mock_booking::MockBackend;
+use crate::utils::receipt::{
+    BookingPreview, BookingReceipt, Confirmation, ReceiptTrip, parse_confirmation,
+};
+use crate::utils::sessions::SessionRegistry;
+use crate::utils::stops::StopCatalogue;
+
+/// Route, date and party size of a trip as understood by the Contram search page,
+/// plus the run to pick among the results.
+#[derive(Debug, Clone)]
+pub struct SearchQuery {
+    pub from_id: u32,
+    pub to_id: u32,
+    pub date: String,
+    pub students: usize,
+    pub adults: usize,
+    pub departure: Option<DepartureFilter>,
+}
+
+impl SearchQuery {
+    /// Query for a single student.
+    pub fn new(from_id: u32, to_id: u32, date: String) -> Self {
+        Self {
+            from_id,
+            to_id,
+            date,
+            students: 1,
+            adults: 0,
+            departure: None,
+        }
+    }
+
+    /// Returns the same query restricted to the runs matching `departure`.
+    pub fn with_departure(self, departure: Option<DepartureFilter>) -> Self {
+        Self { departure, ..self }
+    }
+
+    /// Returns the same query with the party size counted from `passengers`.
+    pub fn for_passengers(&self, passengers: &[Passenger]) -> Self {
+        let students = passengers
+            .iter()
+            .filter(|passenger| passenger.kind == PassengerKind::Student)
+            .count();
+        Self {
+            students,
+            adults: passengers.len() - students,
+            ..self.clone()
+        }
+    }
+
+    /// Path of the search results page, relative to the site root.
+    pub fn path(&self) -> String {
+        format!(
+            "home/Ricerca?PartenzaID={}&DestinazioneID={}&DataPartenza={}&NumeroStudenti={}&NumeroAdulti={}",
+            self.from_id, self.to_id, self.date, self.students, self.adults
+        )
+    }
+}
+
+/// Engine able to open booking sessions on the Contram website.
+#[async_trait]
+pub trait BookingBackend: Send + Sync {
+    /// Short name shown in logs.
+    fn name(&self) -> &'static str;
+
+    /// Returns the departure stops as `(name, id)` pairs sorted by ID.
+    async fn get_cities(&self) -> Result<Vec<(String, u32)>, Error>;
+
+    /// Fetches the departure stops again, bypassing any cache.
+    async fn refresh_cities(&self) -> Result<Vec<(String, u32)>, Error> {
+        self.get_cities().await
+    }
+
+    /// Returns the stops reachable from `from_id` as `(name, id)` pairs sorted by ID.
+    async fn get_destinations(&self, from_id: u32) -> Result<Vec<(String, u32)>, Error>;
+
+    async fn open_session(&self) -> Result<Box<dyn BookingSession>, Error>;
+
+    /// Tells why [`open_session`](Self::open_session) failed with `error`.
+    fn open_error(&self, error: Error) -> BookingError {
+        BookingFailure {
+            step: "open",
+            kind: classify(&error, "open", "", None),
+            error,
+            screenshot: None,
+            page: None,
+        }
+        .into()
+    }
+
+    /// Directory where the pages of failed bookings are saved, if any.
+    fn diagnostics_dir(&self) -> Option<&Path> {
+        None
+    }
+
+    /// Sessions this backend has open, if it keeps track of them.
+    fn sessions(&self) -> Option<&Arc<SessionRegistry>> {
+        None
+    }
+}
+
+/// A session that is closed even when whatever uses it stops early, be it on an
+/// error, a panic or a cancelled future.
+pub struct ManagedSession(Option<Box<dyn BookingSession>>);
+
+impl ManagedSession {
+    pub async fn open(backend: &dyn BookingBackend) -> Result<Self, Error> {
+        Ok(Self(Some(backend.open_session().await?)))
+    }
+
+    pub fn session(&mut self) -> &mut dyn BookingSession {
+        self.0.as_deref_mut().expect("session already closed")
+    }
+
+    pub async fn close(mut self) -> Result<(), Error> {
+        match self.0.take() {
+            Some(session) => session.close().await,
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for ManagedSession {
+    fn drop(&mut self) {
+        let Some(session) = self.0.take() else {
+            return;
+        };
+        println!("Closing a booking session left open");
+        match tokio::runtime::Handle::try_current() {
+            Ok(runtime) => {
+                runtime.spawn(async move {
+                    if let Err(e) = session.close().await {
+                        println!("Failed to close the booking session: {}", e);
+                    }
+                });
+            }
+            Err(_) => println!("No runtime left to close the booking session"),
+        }
+    }
+}
+
+/// A single booking in progress, walked through search, cart, passenger data and
+/// confirmation in this order.
+#[async_trait]
+pub trait BookingSession: Send {
+    /// Loads the site ahead of the booking, so that the first step does not wait
+    /// for it.
+    async fn prepare(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// Loads the search results for the trip and lists its runs.
+    async fn search(&mut self, query: &SearchQuery) -> Result<Vec<Departure>, Error>;
+
+    /// Books `departure` from the current results and opens the cart.
+    async fn add_to_cart(&mut self, departure: &Departure) -> Result<(), Error>;
+
+    /// Fills the passenger forms, in order, and proceeds to the purchase summary.
+    async fn fill_passenger_data(&mut self, passengers: &[Passenger]) -> Result<(), Error>;
+
+    /// Confirms the purchase and returns the page shown afterwards.
+    async fn confirm(&mut self) -> Result<String, Error>;
+
+    /// Checks that the purchase summary can be confirmed and captures it, without
+    /// confirming anything.
+    async fn review(&mut self) -> Result<PageSnapshot, Error>;
+
+    /// Captures the current page, to find out why a step failed.
+    async fn snapshot(&mut self) -> Result<PageSnapshot, Error>;
+
+    /// Ends the session and releases its resources.
+    async fn close(self: Box<Self>) -> Result<(), Error>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BackendKind {
+    Selenium,
+    Http,
+    Mock,
+}
+
+impl FromStr for BackendKind {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "selenium" => Ok(Self::Selenium),
+            "http" => Ok(Self::Http),
+            "mock" => Ok(Self::Mock),
+            _ => Err(Error::msg(format!("Unknown booking backend: {}", s))),
+        }
+    }
+}
+
+impl Display for BackendKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Self::Selenium => "selenium",
+            Self::Http => "http",
+            Self::Mock => "mock",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Builds the backend chosen in `config`; a Selenium backend waits for `driver`
+/// when it is managed by the bot.
+pub fn build_backend(
+    config: &Config,
+    driver: Option<Arc<DriverSupervisor>>,
+) -> Arc<dyn BookingBackend> {
+    let sessions = Arc::new(SessionRegistry::new());
+    let stops = StopCatalogue::new(&config.base_url)
+        .with_ttl(config.stops_ttl)
+        .with_snapshot(&config.stops_snapshot);
+    match config.backend {
+        BackendKind::Selenium => {
+            let backend = SeleniumBackend::new(&config.base_url)
+                .with_browser(config.browser.clone())
+                .with_sessions(sessions)
+                .with_stops(stops)
+                .with_diagnostics(&config.diagnostics_dir);
+            match driver {
+                Some(driver) => Arc::new(backend.with_driver(driver)),
+                None => Arc::new(backend),
+            }
+        }
+        BackendKind::Http => Arc::new(
+            HttpBackend::new(&config.base_url)
+                .with_sessions(sessions)
+                .with_stops(stops)
+                .with_diagnostics(&config.diagnostics_dir),
+        ),
+        BackendKind::Mock => Arc::new(MockBackend::new()),
+    }
+}
+
+/// Returns the names of the stops of a route, checking that a line links them.
+pub async fn check_route(
+    backend: &dyn BookingBackend,
+    from_id: u32,
+    to_id: u32,
+) -> Result<(String, String), BookingError> {
+    let cities = backend
+        .get_cities()
+        .await
+        .map_err(BookingError::CityLookup)?;
+    let city_from = validate_city_id(&cities, from_id)
+        .map_err(BookingError::CityLookup)?
+        .to_string();
+
+    let destinations = backend
+        .get_destinations(from_id)
+        .await
+        .map_err(BookingError::CityLookup)?;
+    let city_to = match validate_city_id(&destinations, to_id) {
+        Ok(city_to) => city_to.to_string(),
+        Err(_) => {
+            return Err(BookingError::InvalidRoute {
+                from: city_from,
+                to_id,
+            });
+        }
+    };
+
+    Ok((city_from, city_to))
+}
+
+/// Lists the runs of a trip without booking anything.
+pub async fn fetch_departures(
+    backend: &dyn BookingBackend,
+    query: &SearchQuery,
+) -> Result<Vec<Departure>, Error> {
+    let mut session = ManagedSession::open(backend).await?;
+    let departures = session.session().search(query).await;
+    session.close().await?;
+    departures
+}
+
+/// Books a ticket for `passengers` through `backend`; the first one is the buyer.
+pub async fn book_ticket(
+    backend: &dyn BookingBackend,
+    passengers: &[Passenger],
+    query: &SearchQuery,
+) -> Result<BookingReceipt, BookingError> {
+    book_trip(backend, passengers, std::slice::from_ref(query)).await
+}
+
+/// Books every leg of a trip for `passengers` in a single cart and purchase.
+pub async fn book_trip(
+    backend: &dyn BookingBackend,
+    passengers: &[Passenger],
+    legs: &[SearchQuery],
+) -> Result<BookingReceipt, BookingError> {
+    let (session, trips) = start_booking(backend, passengers, legs, None).await?;
+    finish_booking(backend, session, passengers, trips).await
+}
+
+/// Books like [`book_trip`], but gets ready before `opens_at`, when booking opens,
+/// and starts searching at that very instant.
+///
+/// The stops are checked and the site is loaded in a new session beforehand, so
+/// that none of it holds up the booking once the window is open.
+pub async fn race_trip(
+    backend: &dyn BookingBackend,
+    passengers: &[Passenger],
+    legs: &[SearchQuery],
+    opens_at: DateTime<Utc>,
+) -> Result<BookingReceipt, BookingError> {
+    race_log(opens_at, "getting ready");
+    let (session, trips) = start_booking(backend, passengers, legs, Some(opens_at)).await?;
+    let result = finish_booking(backend, session, passengers, trips).await;
+    race_log(
+        opens_at,
+        match result {
+            Ok(_) => "booked",
+            Err(_) => "booking failed",
+        },
+    );
+    result
+}
+
+/// Books like [`race_trip`] while `opens_at` is still ahead, so that no search
+/// runs before booking opens, and like [`book_trip`] once it has passed.
+pub async fn book_when_open(
+    backend: &dyn BookingBackend,
+    passengers: &[Passenger],
+    legs: &[SearchQuery],
+    opens_at: Option<DateTime<Utc>>,
+) -> Result<BookingReceipt, BookingError> {
+    match opens_at.filter(|opens_at| Utc::now() < *opens_at) {
+        Some(opens_at) => race_trip(backend, passengers, legs, opens_at).await,
+        None => book_trip(backend, passengers, legs).await,
+    }
+}
+
+/// Logs a step of a race with its time from the opening, in milliseconds.
+fn race_log(opens_at: DateTime<Utc>, step: &str) {
+    let offset = (Utc::now() - opens_at).num_milliseconds();
+    println!("Race: {} at {:+}ms", step, offset);
+}
+
+/// Confirms the purchase of the trips in the cart of `session`.
+async fn finish_booking(
+    backend: &dyn BookingBackend,
+    mut session: ManagedSession,
+    passengers: &[Passenger],
+    trips: Vec<ReceiptTrip>,
+) -> Result<BookingReceipt, BookingError> {
+    let confirmation = match confirm_purchase(session.session(), passengers).await {
+        Ok(confirmation) => confirmation,
+        Err((step, error)) => return Err(abandon(session, backend, step, None, error).await),
+    };
+    // The ticket is booked by now, a session left open is not worth failing over
+    close_session(session).await;
+
+    Ok(BookingReceipt {
+        code: confirmation.code,
+        trips,
+        passengers: passengers
+            .iter()
+            .map(|passenger| passenger.to_string())
+            .collect(),
+        price: confirmation.price,
+        status: confirmation.status,
+        email: passengers[0].user.get_email(),
+    })
+}
+
+/// Walks a booking up to the purchase summary and stops there, before "Conferma
+/// acquisto", reporting what would have been submitted.
+pub async fn preview_trip(
+    backend: &dyn BookingBackend,
+    passengers: &[Passenger],
+    legs: &[SearchQuery],
+) -> Result<BookingPreview, BookingError> {
+    let (mut session, trips) = start_booking(backend, passengers, legs, None).await?;
+    let summary = match session.session().review().await {
+        Ok(summary) => summary,
+        Err(error) => return Err(abandon(session, backend, "review", None, error).await),
+    };
+    close_session(session).await;
+    println!("Dry run stopped before confirming the purchase");
+
+    Ok(BookingPreview {
+        trips,
+        passengers: passengers
+            .iter()
+            .map(|passenger| passenger.to_string())
+            .collect(),
+        fields: passengers
+            .iter()
+            .enumerate()
+            .flat_map(|(index, passenger)| passenger.form_fields(index))
+            .collect(),
+        summary,
+    })
+}
+
+/// Checks the route of every leg, then fills the cart and the passenger data in a
+/// new session, once booking opens at `opens_at` when given.
+async fn start_booking(
+    backend: &dyn BookingBackend,
+    passengers: &[Passenger],
+    legs: &[SearchQuery],
+    opens_at: Option<DateTime<Utc>>,
+) -> Result<(ManagedSession, Vec<ReceiptTrip>), BookingError> {
+    if passengers.is_empty() {
+        return Err(BookingError::NoPassengers);
+    }
+
+    // Validate the route of every leg
+    let mut route_names = Vec::new();
+    for leg in legs {
+        let (city_from, city_to) = check_route(backend, leg.from_id, leg.to_id).await?;
+        println!(
+            "Departing from {} to {} on {} ({} backend)",
+            city_from,
+            city_to,
+            leg.date,
+            backend.name()
+        );
+        route_names.push((city_from, city_to));
+    }
+
+    let mut session = ManagedSession::open(backend)
+        .await
+        .map_err(|error| backend.open_error(error))?;
+    if let Some(opens_at) = opens_at {
+        if let Err(error) = session.session().prepare().await {
+            return Err(abandon(session, backend, "prepare", None, error).await);
+        }
+        race_log(opens_at, "session ready");
+        tokio::time::sleep((opens_at - Utc::now()).to_std().unwrap_or_default()).await;
+        race_log(opens_at, "searching");
+    }
+    match fill_cart(session.session(), passengers, legs, route_names).await {
+        Ok(trips) => Ok((session, trips)),
+        Err((step, run, error)) => Err(abandon(session, backend, step, run, error).await),
+    }
+}
+
+/// Keeps the page the booking stopped on, while booking `run` if one was picked,
+/// before closing its session.
+async fn abandon(
+    mut session: ManagedSession,
+    backend: &dyn BookingBackend,
+    step: &'static str,
+    run: Option<usize>,
+    error: Error,
+) -> BookingError {
+    let failure = diagnose(
+        session.session(),
+        backend.diagnostics_dir(),
+        step,
+        run,
+        error,
+    )
+    .await;
+    close_session(session).await;
+    failure.into()
+}
+
+async fn close_session(session: ManagedSession) {
+    if let Err(e) = session.close().await {
+        println!("Failed to close the booking session: {}", e);
+    }
+}
+
+/// Adds every leg to the cart of `session` and fills the passenger data, returning
+/// the trips in the cart or the step that failed and the run it was booking.
+async fn fill_cart(
+    session: &mut dyn BookingSession,
+    passengers: &[Passenger],
+    legs: &[SearchQuery],
+    route_names: Vec<(String, String)>,
+) -> Result<Vec<ReceiptTrip>, (&'static str, Option<usize>, Error)> {
+    let mut trips = Vec::new();
+    for (leg, (city_from, city_to)) in legs.iter().zip(route_names) {
+        let departures = session
+            .search(&leg.for_passengers(passengers))
+            .await
+            .map_err(|e| ("search", None, e))?;
+        println!("Loaded {} departures", departures.len());
+
+        let departure = select_departure(&departures, leg.departure.as_ref())
+            .map_err(|e| ("select departure", None, e))?;
+        session
+            .add_to_cart(departure)
+            .await
+            .map_err(|e| ("add to cart", Some(departure.index), e))?;
+        println!("Submitted booking form for the {} run", departure.time);
+
+        trips.push(ReceiptTrip {
+            from: city_from,
+            to: city_to,
+            date: leg.date.clone(),
+            departure: departure.time.format("%H:%M").to_string(),
+        });
+    }
+
+    session
+        .fill_passenger_data(passengers)
+        .await
+        .map_err(|e| ("fill passenger data", None, e))?;
+    Ok(trips)
+}
+
+/// Confirms the purchase in `session` and reads the confirmation page.
+async fn confirm_purchase(
+    session: &mut dyn BookingSession,
+    passengers: &[Passenger],
+) -> Result<Confirmation, (&'static str, Error)> {
+    let page = session.confirm().await.map_err(|e| ("confirm", e))?;
+    println!(
+        "Submitted final booking form for {} passenger(s)",
+        passengers.len()
+    );
+    parse_confirmation(&page).map_err(|e| ("confirm", e))
+}
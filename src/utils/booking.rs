@@ -1,10 +1,25 @@
-use crate::user::User;
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use crate::user::Passenger;
+use crate::utils::backend::{BookingBackend, BookingSession, SearchQuery};
+use crate::utils::booking_error::BookingError;
+use crate::utils::departures::{BOOK_BUTTON, BOOK_BUTTON_SELECTOR, Departure, parse_departures};
+use crate::utils::diagnostics::PageSnapshot;
+use crate::utils::driver::DriverSupervisor;
+use crate::utils::sessions::{SessionLease, SessionRegistry};
+use crate::utils::stops::StopCatalogue;
+use crate::utils::webdriver::BrowserConfig;
+use async_trait::async_trait;
 use color_eyre::eyre::Error;
 use reqwest::Client;
 use serde::Deserialize;
-use serde_json::Value;
 use thirtyfour::prelude::*;
 
+pub const BASE_URL: &str = "https://marcheroma.contram.it";
+
 #[derive(Debug, Deserialize)]
 struct Fermata {
     #[serde(rename = "nome")]
@@ -13,28 +28,38 @@ struct Fermata {
     id: u32,
 }
 
-pub async fn get_cities() -> Result<Vec<(String, u32)>, Error> {
-    let api_url = "https://marcheroma.contram.it/api/fermata/partenza";
+/// Fetches the departure stops as `(name, id)` pairs sorted by ID.
+///
+/// Backends read them through a [`StopCatalogue`], which caches them.
+pub async fn get_cities(base_url: &str) -> Result<Vec<(String, u32)>, Error> {
+    let api_url = format!("{}/api/fermata/partenza", base_url);
     let client = Client::new();
-    let response = client.get(api_url).send().await?;
-
-    let cities = match response.json::<Vec<Fermata>>().await {
-        Ok(json) => json.into_iter().map(|f| (f.name, f.id)).collect(),
-        Err(_) => {
-            println!("Error fetching cities, using default cities");
-            vec![
-                ("Camerino".to_string(), 24),
-                ("Ancona Piazza Cavour".to_string(), 38),
-                ("Ancona Stazione F.S.".to_string(), 39),
-                ("Civitanova Marche Via Sonnino".to_string(), 42),
-                ("Porto San Giorgio".to_string(), 53),
-            ]
-        }
-    };
+    let response = client.get(api_url).send().await?.error_for_status()?;
 
-    let mut sorted_cities = cities;
-    sorted_cities.sort_by_key(|&(_, id)| id);
-    Ok(sorted_cities)
+    let mut cities: Vec<(String, u32)> = response
+        .json::<Vec<Fermata>>()
+        .await?
+        .into_iter()
+        .map(|f| (f.name, f.id))
+        .collect();
+    cities.sort_by_key(|&(_, id)| id);
+    Ok(cities)
+}
+
+/// Returns the stops reachable from `from_id` as `(name, id)` pairs sorted by ID.
+pub async fn get_destinations(base_url: &str, from_id: u32) -> Result<Vec<(String, u32)>, Error> {
+    let api_url = format!("{}/api/fermata/arrivo/{}", base_url, from_id);
+    let client = Client::new();
+    let response = client.get(api_url).send().await?.error_for_status()?;
+
+    let mut destinations: Vec<(String, u32)> = response
+        .json::<Vec<Fermata>>()
+        .await?
+        .into_iter()
+        .map(|f| (f.name, f.id))
+        .collect();
+    destinations.sort_by_key(|&(_, id)| id);
+    Ok(destinations)
 }
 
 pub fn validate_city_id(cities: &[(String, u32)], target_id: u32) -> Result<&str, Error> {
@@ -55,15 +80,10 @@ pub async fn find_and_wait(driver: &WebDriver, by: By, text: String) -> Result<W
     Ok(element)
 }
 
-pub async fn fill_form_fields(driver: &WebDriver, user: &User) -> Result<(), Error> {
-    let person_value = serde_json::to_value(user)?;
-    if let Value::Object(map) = person_value {
-        for (field, value) in map {
-            if let Value::String(s) = value {
-                fill_field(driver, &field, &s).await?;
-            } else {
-                return Err(Error::msg(format!("Field {} is not a string", field)));
-            }
+pub async fn fill_form_fields(driver: &WebDriver, passengers: &[Passenger]) -> Result<(), Error> {
+    for (index, passenger) in passengers.iter().enumerate() {
+        for (field, value) in passenger.form_fields(index) {
+            fill_field(driver, &field, &value).await?;
         }
     }
     Ok(())
@@ -75,76 +95,215 @@ pub async fn fill_field(driver: &WebDriver, field: &str, value: &str) -> Result<
     Ok(())
 }
 
-pub async fn book_ticket(
-    user: &User,
-    from_id: u32,
-    to_id: u32,
-    date: String,
-    is_headless: Option<bool>,
-) -> Result<String, Error> {
-    // Fetch cities and validate IDs
-    let cities = get_cities()
-        .await
-        .expect("❌ Failed to fetch cities. Please try again later.");
-    let city_from = validate_city_id(&cities, from_id).expect("❌ Departure city not found");
-    let city_to = validate_city_id(&cities, to_id).expect("❌ Arrival city not found");
-    println!("Departing from {} to {} on {}", city_from, city_to, date);
-
-    // Initialize WebDriver
-    let mut caps = DesiredCapabilities::firefox();
-    if is_headless.unwrap_or(true) {
-        caps.set_headless()?;
-        println!("Headless mode enabled");
-    }
-
-    let driver = WebDriver::new("http://localhost:4444", caps).await?;
-
-    // Build and visit URL
-    let url = format!(
-        "https://marcheroma.contram.it/home/Ricerca?PartenzaID={}&DestinazioneID={}&DataPartenza={}&NumeroStudenti=1&NumeroAdulti=0",
-        from_id, to_id, date
-    );
-    driver.goto(&url).await?;
-    println!("Loaded URL: {}", url);
-
-    // Wait for the booking button to be clickable and click it
-    let btn_submit = find_and_wait(&driver, By::Tag("button"), "Prenota".to_string()).await?;
-    btn_submit.click().await?;
-    println!("Submitted booking form");
-
-    // Explicit wait for navigation to cart
-    driver
-        .goto("https://marcheroma.contram.it/Home/RitornaCarrello?")
+/// Drives a browser through a WebDriver server, headless Firefox on
+/// `localhost:4444` unless set otherwise with [`SeleniumBackend::with_browser`].
+pub struct SeleniumBackend {
+    base_url: String,
+    browser: BrowserConfig,
+    driver: Option<Arc<DriverSupervisor>>,
+    sessions: Option<Arc<SessionRegistry>>,
+    stops: StopCatalogue,
+    diagnostics_dir: Option<PathBuf>,
+}
+
+impl SeleniumBackend {
+    pub fn new(base_url: &str) -> Self {
+        Self {
+            base_url: base_url.to_string(),
+            browser: BrowserConfig::default(),
+            driver: None,
+            sessions: None,
+            stops: StopCatalogue::new(base_url),
+            diagnostics_dir: None,
+        }
+    }
+
+    pub fn with_browser(self, browser: BrowserConfig) -> Self {
+        Self { browser, ..self }
+    }
+
+    /// Waits for `driver` to be healthy before opening each session.
+    pub fn with_driver(self, driver: Arc<DriverSupervisor>) -> Self {
+        Self {
+            driver: Some(driver),
+            ..self
+        }
+    }
+
+    /// Lists the open browser sessions in `sessions`.
+    pub fn with_sessions(self, sessions: Arc<SessionRegistry>) -> Self {
+        Self {
+            sessions: Some(sessions),
+            ..self
+        }
+    }
+
+    pub fn with_stops(self, stops: StopCatalogue) -> Self {
+        Self { stops, ..self }
+    }
+
+    /// Saves the pages of failed bookings under `dir`.
+    pub fn with_diagnostics(self, dir: impl Into<PathBuf>) -> Self {
+        Self {
+            diagnostics_dir: Some(dir.into()),
+            ..self
+        }
+    }
+}
+
+#[async_trait]
+impl BookingBackend for SeleniumBackend {
+    fn name(&self) -> &'static str {
+        "selenium"
+    }
+
+    async fn get_cities(&self) -> Result<Vec<(String, u32)>, Error> {
+        self.stops.cities().await
+    }
+
+    async fn refresh_cities(&self) -> Result<Vec<(String, u32)>, Error> {
+        self.stops.refresh().await
+    }
+
+    fn diagnostics_dir(&self) -> Option<&Path> {
+        self.diagnostics_dir.as_deref()
+    }
+
+    fn sessions(&self) -> Option<&Arc<SessionRegistry>> {
+        self.sessions.as_ref()
+    }
+
+    fn open_error(&self, error: Error) -> BookingError {
+        BookingError::WebDriverUnavailable(error)
+    }
+
+    async fn get_destinations(&self, from_id: u32) -> Result<Vec<(String, u32)>, Error> {
+        get_destinations(&self.base_url, from_id).await
+    }
+
+    async fn open_session(&self) -> Result<Box<dyn BookingSession>, Error> {
+        if let Some(driver) = &self.driver {
+            driver.wait_until_healthy().await?;
+        }
+        let driver = self.browser.connect().await?;
+        Ok(Box::new(SeleniumSession {
+            lease: self
+                .sessions
+                .as_ref()
+                .map(|sessions| sessions.register(self.name(), Some(driver.clone()))),
+            driver,
+            base_url: self.base_url.clone(),
+        }))
+    }
+}
+
+pub struct SeleniumSession {
+    driver: WebDriver,
+    base_url: String,
+    /// Keeps the session listed until it is dropped.
+    lease: Option<SessionLease>,
+}
+
+#[async_trait]
+impl BookingSession for SeleniumSession {
+    async fn prepare(&mut self) -> Result<(), Error> {
+        self.driver.goto(&self.base_url).await?;
+        Ok(())
+    }
+
+    async fn search(&mut self, query: &SearchQuery) -> Result<Vec<Departure>, Error> {
+        let url = format!("{}/{}", self.base_url, query.path());
+        self.driver.goto(&url).await?;
+        println!("Loaded URL: {}", url);
+
+        // Give the results time to render before reading them, the booking buttons
+        // being any of BOOK_BUTTON_SELECTOR
+        self.driver
+            .query(By::Tag("button"))
+            .with_text(BOOK_BUTTON)
+            .or(By::Css("input[type=submit]"))
+            .with_value(BOOK_BUTTON)
+            .exists()
+            .await?;
+        Ok(parse_departures(&self.driver.source().await?))
+    }
+
+    async fn add_to_cart(&mut self, departure: &Departure) -> Result<(), Error> {
+        // Click the booking button of the chosen run, counting buttons the way
+        // parse_departures does
+        let mut buttons = Vec::new();
+        for element in self.driver.find_all(By::Css(BOOK_BUTTON_SELECTOR)).await? {
+            let label = match element.tag_name().await?.eq_ignore_ascii_case("input") {
+                true => element.value().await?.unwrap_or_default(),
+                false => element.text().await?,
+            };
+            if label.trim() == BOOK_BUTTON {
+                buttons.push(element);
+            }
+        }
+        let btn_submit = buttons
+            .into_iter()
+            .nth(departure.index)
+            .ok_or_else(|| Error::msg(format!("Button \"{}\" not found", BOOK_BUTTON)))?;
+        btn_submit.scroll_into_view().await?;
+        btn_submit.click().await?;
+
+        // Explicit wait for navigation to cart
+        self.driver
+            .goto(&format!("{}/Home/RitornaCarrello?", self.base_url))
+            .await?;
+        println!("Navigated to cart");
+        Ok(())
+    }
+
+    async fn fill_passenger_data(&mut self, passengers: &[Passenger]) -> Result<(), Error> {
+        fill_form_fields(&self.driver, passengers).await?;
+
+        let btn_submit = find_and_wait(
+            &self.driver,
+            By::Tag("button"),
+            "Procedi all'acquisto".to_string(),
+        )
         .await?;
-    println!("Navigated to cart");
-
-    // Fill form fields
-    fill_form_fields(&driver, user).await?;
-
-    // Final submission
-    let btn_submit = find_and_wait(
-        &driver,
-        By::Tag("button"),
-        "Procedi all'acquisto".to_string(),
-    )
-    .await?;
-    btn_submit.click().await?;
-
-    // Confirm purchase
-    let btn_confirm =
-        find_and_wait(&driver, By::Tag("button"), "Conferma acquisto".to_string()).await?;
-    btn_confirm.click().await?;
-    println!(
-        "Submitted final booking form, an email will be sent to: {}",
-        user.get_email()
-    );
-
-    driver.quit().await?;
-    Ok(format!(
-        "Ticket booked from {} to {} on {}\nAn email will be sent to: {}",
-        city_from,
-        city_to,
-        date,
-        user.get_email()
-    ))
+        btn_submit.click().await?;
+        Ok(())
+    }
+
+    async fn confirm(&mut self) -> Result<String, Error> {
+        let btn_confirm = find_and_wait(
+            &self.driver,
+            By::Tag("button"),
+            "Conferma acquisto".to_string(),
+        )
+        .await?;
+        btn_confirm.click().await?;
+
+        // The button goes away with the summary page once the next one loads
+        btn_confirm.wait_until().stale().await?;
+        Ok(self.driver.source().await?)
+    }
+
+    async fn review(&mut self) -> Result<PageSnapshot, Error> {
+        find_and_wait(
+            &self.driver,
+            By::Tag("button"),
+            "Conferma acquisto".to_string(),
+        )
+        .await?;
+        self.snapshot().await
+    }
+
+    async fn snapshot(&mut self) -> Result<PageSnapshot, Error> {
+        Ok(PageSnapshot {
+            html: self.driver.source().await?,
+            screenshot: Some(self.driver.screenshot_as_png().await?),
+        })
+    }
+
+    async fn close(self: Box<Self>) -> Result<(), Error> {
+        let Self { driver, lease, .. } = *self;
+        let result = driver.quit().await;
+        drop(lease);
+        Ok(result?)
+    }
 }
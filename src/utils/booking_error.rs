@@ -0,0 +1,87 @@
+use std::fmt::{Display, Formatter};
+
+use color_eyre::eyre::Error;
+
+use crate::utils::diagnostics::{BookingFailure, FailureKind};
+
+/// Why a booking did not go through.
+#[derive(Debug)]
+pub enum BookingError {
+    NoPassengers,
+    /// The stops could not be fetched, or a stop is not among them.
+    CityLookup(Error),
+    /// No line links the departure stop to the destination.
+    InvalidRoute {
+        from: String,
+        to_id: u32,
+    },
+    /// The browser session could not be opened.
+    WebDriverUnavailable(Error),
+    /// A page lacked an element the step relies on.
+    ElementNotFound(BookingFailure),
+    /// The trip has no runs or no seats left.
+    SoldOut(BookingFailure),
+    /// "Conferma acquisto" went unanswered, so the ticket may or may not be booked.
+    ConfirmationMissing(BookingFailure),
+    /// Any other failure of a booking step.
+    Step(BookingFailure),
+}
+
+impl BookingError {
+    /// The failed step and the page it stopped on, if the booking got that far.
+    pub fn failure(&self) -> Option<&BookingFailure> {
+        match self {
+            Self::ElementNotFound(failure)
+            | Self::SoldOut(failure)
+            | Self::ConfirmationMissing(failure)
+            | Self::Step(failure) => Some(failure),
+            _ => None,
+        }
+    }
+}
+
+impl From<BookingFailure> for BookingError {
+    fn from(failure: BookingFailure) -> Self {
+        match failure.kind {
+            FailureKind::SoldOut => Self::SoldOut(failure),
+            FailureKind::SiteChanged => Self::ElementNotFound(failure),
+            // Nothing on the page explains why the purchase was not confirmed
+            FailureKind::Unknown if failure.step == "confirm" => Self::ConfirmationMissing(failure),
+            _ => Self::Step(failure),
+        }
+    }
+}
+
+impl Display for BookingError {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match self {
+            Self::NoPassengers => write!(f, "No passengers to book"),
+            Self::CityLookup(error) => write!(f, "City lookup failed: {}", error),
+            Self::InvalidRoute { from, to_id } => {
+                write!(f, "No line from {} to stop {}", from, to_id)
+            }
+            Self::WebDriverUnavailable(error) => write!(f, "WebDriver unavailable: {}", error),
+            Self::ElementNotFound(failure) => write!(
+                f,
+                "Page element not found at step \"{}\": {}",
+                failure.step, failure.error
+            ),
+            Self::SoldOut(failure) => write!(f, "Sold out: {}", failure.error),
+            Self::ConfirmationMissing(failure) => write!(
+                f,
+                "No purchase confirmation, the ticket may not have been booked: {}",
+                failure.error
+            ),
+            Self::Step(failure) => write!(f, "{}", failure),
+        }
+    }
+}
+
+impl std::error::Error for BookingError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::CityLookup(error) | Self::WebDriverUnavailable(error) => Some(error.as_ref()),
+            _ => self.failure().map(|failure| failure as _),
+        }
+    }
+}
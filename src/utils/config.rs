@@ -0,0 +1,323 @@
+use std::{env, path::PathBuf, time::Duration};
+
+use color_eyre::eyre::Error;
+
+use crate::utils::backend::BackendKind;
+use crate::utils::booking::BASE_URL;
+use crate::utils::driver::DriverConfig;
+use crate::utils::jobs::MissedJobPolicy;
+use crate::utils::mail::MailConfig;
+use crate::utils::retry::RetryPolicy;
+use crate::utils::sessions::DEFAULT_SESSION_MAX_AGE;
+use crate::utils::stops::DEFAULT_STOPS_TTL;
+use crate::utils::watch::WatchConfig;
+use crate::utils::webdriver::BrowserConfig;
+
+/// Bot settings read from the environment at startup.
+///
+/// | Variable                  | Default                          |
+/// |---------------------------|----------------------------------|
+/// | `BOOKING_BACKEND`         | `selenium`                       |
+/// | `CONTRAM_BASE_URL`        | `https://marcheroma.contram.it`  |
+/// | `STOPS_TTL_MINUTES`       | `1440`                           |
+/// | `STOPS_SNAPSHOT`          | `stops.json`                     |
+/// | `ADMIN_USERS`             | none                             |
+/// | `DIAGNOSTICS_DIR`         | `diagnostics`                    |
+/// | `SESSION_MAX_AGE_MINUTES` | `15`                             |
+/// | `JOBS_FILE`               | `jobs.json`                      |
+/// | `MISSED_JOBS`             | `run`                            |
+/// | `RECURRING_FILE`          | `recurring.json`                 |
+/// | `RECEIPTS_FILE`           | `receipts.json`                  |
+/// | `RACE_LEAD_SECONDS`       | none                             |
+///
+/// `MISSED_JOBS` says what happens to bookings that fell due while the bot was
+/// down: `run` books them at startup, `report` only tells their users.
+///
+/// When `RACE_LEAD_SECONDS` is set, scheduled bookings open their session and load
+/// the site that many seconds early, then search the moment booking opens. The lead
+/// must be shorter than `SESSION_MAX_AGE_MINUTES`, or the waiting session is reaped.
+///
+/// The Selenium backend drives a browser through a WebDriver server:
+///
+/// | Variable                       | Default                 |
+/// |--------------------------------|-------------------------|
+/// | `WEBDRIVER_URL`                | `http://localhost:4444` |
+/// | `WEBDRIVER_BROWSER`            | `firefox`               |
+/// | `WEBDRIVER_HEADLESS`           | `true`                  |
+/// | `WEBDRIVER_PAGE_LOAD_SECONDS`  | `30`                    |
+/// | `WEBDRIVER_ELEMENT_SECONDS`    | `10`                    |
+/// | `WEBDRIVER_ARGS`               | none                    |
+/// | `WEBDRIVER_BINARY`             | none                    |
+///
+/// `WEBDRIVER_BROWSER` is `firefox` or `chrome`, and `WEBDRIVER_ARGS` lists extra
+/// browser arguments separated by spaces. When `WEBDRIVER_BINARY` names a
+/// geckodriver or chromedriver executable, the bot runs it on the port of
+/// `WEBDRIVER_URL` and restarts it when it crashes.
+///
+/// Failed bookings are tried again according to:
+///
+/// | Variable                      | Default                |
+/// |-------------------------------|------------------------|
+/// | `BOOKING_ATTEMPTS`            | `3`                    |
+/// | `BOOKING_BACKOFF_SECONDS`     | `5`                    |
+/// | `BOOKING_MAX_BACKOFF_SECONDS` | `60`                   |
+/// | `BOOKING_DEADLINE_SECONDS`    | `300`                  |
+/// | `BOOKING_RETRY_ON`            | `site-changed,unknown` |
+///
+/// `BOOKING_RETRY_ON` lists any of `sold-out`, `site-changed`, `validation` and
+/// `unknown`.
+///
+/// `/watch` searches for free seats every `WATCH_INTERVAL_SECONDS` (default `120`),
+/// with at least `WATCH_RATE_LIMIT_SECONDS` (default `10`) between any two searches.
+///
+/// Ticket emails are fetched from a shared mailbox only when `IMAP_HOST` is set:
+///
+/// | Variable            | Default         |
+/// |---------------------|-----------------|
+/// | `IMAP_HOST`         | none            |
+/// | `IMAP_PORT`         | `993`           |
+/// | `IMAP_TLS`          | `true`          |
+/// | `IMAP_USERNAME`     | none            |
+/// | `IMAP_PASSWORD`     | none            |
+/// | `IMAP_MAILBOX`      | `INBOX`         |
+/// | `IMAP_POLL_SECONDS` | `60`            |
+/// | `IMAP_ADDRESS`      | `IMAP_USERNAME` |
+///
+/// Bookings made while the mailbox is watched give `IMAP_ADDRESS` to Contram as
+/// the buyer email, so the ticket emails are delivered to the mailbox rather than
+/// to the user. The tickets still awaited are kept in `pending_tickets.json`, next
+/// to `JOBS_FILE`.
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub backend: BackendKind,
+    /// Root of the Contram website, without a trailing slash.
+    pub base_url: String,
+    /// How long the departure stops are cached.
+    pub stops_ttl: Duration,
+    /// File the departure stops are saved to, for when the site is down.
+    pub stops_snapshot: PathBuf,
+    /// Telegram usernames allowed to run admin commands, comma-separated.
+    pub admins: Vec<String>,
+    /// Directory where the pages of failed bookings are saved.
+    pub diagnostics_dir: PathBuf,
+    /// How long a booking session may stay open before it is closed.
+    pub session_max_age: Duration,
+    /// File the scheduled bookings are kept in.
+    pub jobs_file: PathBuf,
+    pub missed_jobs: MissedJobPolicy,
+    /// File the recurring booking rules are kept in.
+    pub recurring_file: PathBuf,
+    /// File the receipts of the bookings made are kept in.
+    pub receipts_file: PathBuf,
+    /// How long before booking opens a scheduled booking gets ready, if it races.
+    pub race_lead: Option<Duration>,
+    pub browser: BrowserConfig,
+    /// WebDriver binary the bot runs itself, if any.
+    pub driver: Option<DriverConfig>,
+    pub retry: RetryPolicy,
+    pub watch: WatchConfig,
+    pub mail: Option<MailConfig>,
+}
+
+impl Config {
+    pub fn from_env() -> Result<Self, Error> {
+        let backend = match env::var("BOOKING_BACKEND") {
+            Ok(value) => value.parse()?,
+            Err(_) => BackendKind::Selenium,
+        };
+
+        let base_url = env::var("CONTRAM_BASE_URL")
+            .unwrap_or_else(|_| BASE_URL.to_string())
+            .trim_end_matches('/')
+            .to_string();
+
+        let stops_ttl = match env::var("STOPS_TTL_MINUTES") {
+            Ok(value) => Duration::from_secs(
+                value
+                    .parse::<u64>()
+                    .map_err(|_| Error::msg(format!("Invalid STOPS_TTL_MINUTES: {}", value)))?
+                    * 60,
+            ),
+            Err(_) => DEFAULT_STOPS_TTL,
+        };
+
+        let stops_snapshot =
+            PathBuf::from(env::var("STOPS_SNAPSHOT").unwrap_or_else(|_| "stops.json".to_string()));
+
+        let admins = env::var("ADMIN_USERS")
+            .unwrap_or_default()
+            .split(',')
+            .map(|username| username.trim().trim_start_matches('@').to_string())
+            .filter(|username| !username.is_empty())
+            .collect();
+
+        let diagnostics_dir = PathBuf::from(
+            env::var("DIAGNOSTICS_DIR").unwrap_or_else(|_| "diagnostics".to_string()),
+        );
+
+        let session_max_age = match env::var("SESSION_MAX_AGE_MINUTES") {
+            Ok(value) => Duration::from_secs(
+                value.parse::<u64>().map_err(|_| {
+                    Error::msg(format!("Invalid SESSION_MAX_AGE_MINUTES: {}", value))
+                })? * 60,
+            ),
+            Err(_) => DEFAULT_SESSION_MAX_AGE,
+        };
+
+        let jobs_file =
+            PathBuf::from(env::var("JOBS_FILE").unwrap_or_else(|_| "jobs.json".to_string()));
+        let recurring_file = PathBuf::from(
+            env::var("RECURRING_FILE").unwrap_or_else(|_| "recurring.json".to_string()),
+        );
+        let receipts_file = PathBuf::from(
+            env::var("RECEIPTS_FILE").unwrap_or_else(|_| "receipts.json".to_string()),
+        );
+        let missed_jobs = match env::var("MISSED_JOBS") {
+            Ok(value) => value.parse()?,
+            Err(_) => MissedJobPolicy::Run,
+        };
+        let race_lead = match env::var("RACE_LEAD_SECONDS") {
+            Ok(value) => Some(Duration::from_secs(value.parse().map_err(|_| {
+                Error::msg(format!("Invalid RACE_LEAD_SECONDS: {}", value))
+            })?)),
+            Err(_) => None,
+        };
+        // The reaper would close the session while it waits for booking to open
+        if let Some(lead) = race_lead
+            && lead >= session_max_age
+        {
+            return Err(Error::msg(format!(
+                "RACE_LEAD_SECONDS ({}) must be shorter than SESSION_MAX_AGE_MINUTES ({} minutes)",
+                lead.as_secs(),
+                session_max_age.as_secs() / 60
+            )));
+        }
+
+        let browser = browser_config()?;
+        let driver = match env::var("WEBDRIVER_BINARY") {
+            Ok(binary) => Some(DriverConfig::new(binary, &browser.url)?),
+            Err(_) => None,
+        };
+        let retry = retry_policy()?;
+        let watch = watch_config()?;
+
+        let mail = match env::var("IMAP_HOST") {
+            Ok(host) => Some(mail_config(host)?),
+            Err(_) => None,
+        };
+
+        Ok(Self {
+            backend,
+            base_url,
+            stops_ttl,
+            stops_snapshot,
+            admins,
+            diagnostics_dir,
+            session_max_age,
+            jobs_file,
+            missed_jobs,
+            recurring_file,
+            receipts_file,
+            race_lead,
+            browser,
+            driver,
+            retry,
+            watch,
+            mail,
+        })
+    }
+
+    pub fn is_admin(&self, username: &str) -> bool {
+        self.admins.iter().any(|admin| admin == username)
+    }
+}
+
+/// Reads a number from the variable `name`, or `default` when it is unset.
+fn env_number(name: &str, default: u64) -> Result<u64, Error> {
+    match env::var(name) {
+        Ok(value) => value
+            .parse::<u64>()
+            .map_err(|_| Error::msg(format!("Invalid {}: {}", name, value))),
+        Err(_) => Ok(default),
+    }
+}
+
+fn browser_config() -> Result<BrowserConfig, Error> {
+    let default = BrowserConfig::default();
+    let seconds = |name: &str, default: Duration| {
+        env_number(name, default.as_secs()).map(Duration::from_secs)
+    };
+
+    Ok(BrowserConfig {
+        url: env::var("WEBDRIVER_URL").unwrap_or(default.url),
+        browser: match env::var("WEBDRIVER_BROWSER") {
+            Ok(value) => value.parse()?,
+            Err(_) => default.browser,
+        },
+        headless: env::var("WEBDRIVER_HEADLESS").map_or(default.headless, |value| value != "false"),
+        page_load_timeout: seconds("WEBDRIVER_PAGE_LOAD_SECONDS", default.page_load_timeout)?,
+        element_timeout: seconds("WEBDRIVER_ELEMENT_SECONDS", default.element_timeout)?,
+        args: env::var("WEBDRIVER_ARGS")
+            .unwrap_or_default()
+            .split_whitespace()
+            .map(str::to_string)
+            .collect(),
+    })
+}
+
+fn retry_policy() -> Result<RetryPolicy, Error> {
+    let default = RetryPolicy::default();
+    let seconds = |name: &str, default: Duration| {
+        env_number(name, default.as_secs()).map(Duration::from_secs)
+    };
+
+    let retry_on = match env::var("BOOKING_RETRY_ON") {
+        Ok(value) => value
+            .split(',')
+            .filter(|kind| !kind.trim().is_empty())
+            .map(str::parse)
+            .collect::<Result<_, _>>()?,
+        Err(_) => default.retry_on,
+    };
+
+    Ok(RetryPolicy {
+        max_attempts: u32::try_from(env_number("BOOKING_ATTEMPTS", default.max_attempts.into())?)
+            .map_err(|_| Error::msg("Invalid BOOKING_ATTEMPTS"))?
+            .max(1),
+        backoff: seconds("BOOKING_BACKOFF_SECONDS", default.backoff)?,
+        max_backoff: seconds("BOOKING_MAX_BACKOFF_SECONDS", default.max_backoff)?,
+        deadline: seconds("BOOKING_DEADLINE_SECONDS", default.deadline)?,
+        retry_on,
+    })
+}
+
+fn watch_config() -> Result<WatchConfig, Error> {
+    let default = WatchConfig::default();
+    Ok(WatchConfig {
+        interval: Duration::from_secs(
+            env_number("WATCH_INTERVAL_SECONDS", default.interval.as_secs())?.max(1),
+        ),
+        rate_limit: Duration::from_secs(env_number(
+            "WATCH_RATE_LIMIT_SECONDS",
+            default.rate_limit.as_secs(),
+        )?),
+    })
+}
+
+fn mail_config(host: String) -> Result<MailConfig, Error> {
+    let required = |name: &str| {
+        env::var(name).map_err(|_| Error::msg(format!("{} is required with IMAP_HOST", name)))
+    };
+
+    Ok(MailConfig {
+        host,
+        port: u16::try_from(env_number("IMAP_PORT", 993)?)
+            .map_err(|_| Error::msg("Invalid IMAP_PORT"))?,
+        tls: env::var("IMAP_TLS").map_or(true, |value| value != "false"),
+        address: env::var("IMAP_ADDRESS").or_else(|_| required("IMAP_USERNAME"))?,
+        username: required("IMAP_USERNAME")?,
+        password: required("IMAP_PASSWORD")?,
+        mailbox: env::var("IMAP_MAILBOX").unwrap_or_else(|_| "INBOX".to_string()),
+        poll_interval: Duration::from_secs(env_number("IMAP_POLL_SECONDS", 60)?),
+    })
+}
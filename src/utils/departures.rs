@@ -0,0 +1,216 @@
+use std::fmt::{Display, Formatter};
+
+use chrono::NaiveTime;
+use color_eyre::eyre::Error;
+use regex::Regex;
+use scraper::{ElementRef, Html, Selector};
+
+/// Label of the button that books a run on the search results page.
+pub const BOOK_BUTTON: &str = "Prenota";
+/// Labels of a run with no seats left, in lower case.
+const SOLD_OUT_LABELS: [&str; 3] = ["esaurit", "sold out", "nessun posto"];
+/// Elements that may be a [`BOOK_BUTTON`], labelled by their text or their value.
+pub const BOOK_BUTTON_SELECTOR: &str = "button, input[type=submit]";
+
+/// A run listed on the search results page.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Departure {
+    /// Position of the run's "Prenota" button among those on the page.
+    pub index: usize,
+    pub time: NaiveTime,
+    pub arrival_time: Option<NaiveTime>,
+    /// Price in euros.
+    pub price: Option<f64>,
+    /// Free seats, when the site shows them.
+    pub seats: Option<u32>,
+    /// Whether the run is labelled as sold out, whatever its seats read.
+    pub sold_out: bool,
+}
+
+impl Display for Departure {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.time.format("%H:%M"))?;
+        if let Some(arrival_time) = self.arrival_time {
+            write!(f, " → {}", arrival_time.format("%H:%M"))?;
+        }
+        if let Some(price) = self.price {
+            write!(f, "  {:.2} €", price)?;
+        }
+        match self.seats {
+            Some(seats) => write!(f, "  {} seats", seats)?,
+            None if self.sold_out => write!(f, "  sold out")?,
+            None => {}
+        }
+        Ok(())
+    }
+}
+
+/// Parses every bookable run on the search results page, in page order.
+///
+/// Each run is the largest element around its "Prenota" button that holds no other
+/// such button; its first two times are the departure and the arrival.
+pub fn parse_departures(html: &str) -> Vec<Departure> {
+    let document = Html::parse_document(html);
+    let button_selector = Selector::parse(BOOK_BUTTON_SELECTOR).unwrap();
+    let time_regex = Regex::new(r"\b([01]?\d|2[0-3]):([0-5]\d)\b").unwrap();
+    // Labels before the number are tried first, so that "07:55 € 9,50" is not
+    // read as 55 € and "9,50 € Posti: 12" as 50 seats
+    let price_regexes = [
+        Regex::new(r"€\s*(\d+(?:[.,]\d{1,2})?)").unwrap(),
+        Regex::new(r"(\d+(?:[.,]\d{1,2})?)\s*€").unwrap(),
+    ];
+    let seats_regexes = [
+        Regex::new(r"(?i)posti[^0-9]{0,20}(\d+)").unwrap(),
+        Regex::new(r"(?i)(?:^|[^\d,.])(\d+)\s+posti").unwrap(),
+    ];
+
+    let is_book_button = |button: &ElementRef| {
+        let label = match button.value().name() {
+            "input" => button.value().attr("value").unwrap_or_default().to_string(),
+            _ => button.text().collect::<String>(),
+        };
+        label.trim() == BOOK_BUTTON
+    };
+    let count_buttons = |element: &ElementRef| {
+        element
+            .select(&button_selector)
+            .filter(|button| is_book_button(button))
+            .count()
+    };
+
+    document
+        .select(&button_selector)
+        .filter(|button| is_book_button(button))
+        .enumerate()
+        .filter_map(|(index, button)| {
+            let mut run = button;
+            while let Some(parent) = run.parent().and_then(ElementRef::wrap) {
+                if count_buttons(&parent) > 1 || parent.value().name() == "body" {
+                    break;
+                }
+                run = parent;
+            }
+
+            let text = run.text().collect::<Vec<_>>().join(" ");
+            let mut times = time_regex.captures_iter(&text).filter_map(|captures| {
+                NaiveTime::from_hms_opt(captures[1].parse().ok()?, captures[2].parse().ok()?, 0)
+            });
+            let time = times.next()?;
+            let arrival_time = times.next();
+
+            let price = price_regexes
+                .iter()
+                .find_map(|regex| regex.captures(&text))
+                .and_then(|captures| captures[1].replace(',', ".").parse().ok());
+            let seats = seats_regexes
+                .iter()
+                .find_map(|regex| regex.captures(&text))
+                .and_then(|captures| captures[1].parse().ok());
+            let lower_text = text.to_lowercase();
+            let sold_out = SOLD_OUT_LABELS
+                .iter()
+                .any(|label| lower_text.contains(label));
+
+            Some(Departure {
+                index,
+                time,
+                arrival_time,
+                price,
+                seats,
+                sold_out,
+            })
+        })
+        .collect()
+}
+
+/// Which run of the day to book.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DepartureFilter {
+    /// The run leaving at exactly this time.
+    At(NaiveTime),
+    /// The first run leaving within this window, bounds included.
+    Between(NaiveTime, NaiveTime),
+}
+
+impl DepartureFilter {
+    /// Parses `HH:MM` or `HH:MM-HH:MM`.
+    pub fn parse(value: &str) -> Result<Self, Error> {
+        let parse_time = |time: &str| {
+            NaiveTime::parse_from_str(time.trim(), "%H:%M")
+                .map_err(|_| Error::msg(format!("Invalid time: {} (use HH:MM)", time.trim())))
+        };
+
+        match value.split_once('-') {
+            Some((start, end)) => {
+                let (start, end) = (parse_time(start)?, parse_time(end)?);
+                if start > end {
+                    return Err(Error::msg(format!("Invalid time window: {}", value)));
+                }
+                Ok(Self::Between(start, end))
+            }
+            None => Ok(Self::At(parse_time(value)?)),
+        }
+    }
+
+    pub fn matches(&self, departure: &Departure) -> bool {
+        match *self {
+            Self::At(time) => departure.time == time,
+            Self::Between(start, end) => (start..=end).contains(&departure.time),
+        }
+    }
+}
+
+impl Display for DepartureFilter {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match self {
+            Self::At(time) => write!(f, "{}", time.format("%H:%M")),
+            Self::Between(start, end) => {
+                write!(f, "{}-{}", start.format("%H:%M"), end.format("%H:%M"))
+            }
+        }
+    }
+}
+
+/// Picks the run to book: the first one matching `filter`, or the first of the day
+/// when there is no filter.
+pub fn select_departure<'a>(
+    departures: &'a [Departure],
+    filter: Option<&DepartureFilter>,
+) -> Result<&'a Departure, Error> {
+    if departures.is_empty() {
+        return Err(Error::msg("No trips available on this date"));
+    }
+
+    match filter {
+        None => Ok(&departures[0]),
+        Some(filter) => departures
+            .iter()
+            .find(|departure| filter.matches(departure))
+            .ok_or_else(|| {
+                let available = departures
+                    .iter()
+                    .map(|departure| departure.time.format("%H:%M").to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                Error::msg(format!(
+                    "No departure at {} (available: {})",
+                    filter, available
+                ))
+            }),
+    }
+}
+
+/// The first run matching `filter`, or of the day without one, that still has a
+/// free seat; runs not showing their seats count as free unless labelled sold out.
+pub fn open_departure<'a>(
+    departures: &'a [Departure],
+    filter: Option<&DepartureFilter>,
+) -> Option<&'a Departure> {
+    departures.iter().find(|departure| {
+        let free = match departure.seats {
+            Some(seats) => seats > 0,
+            None => !departure.sold_out,
+        };
+        free && filter.is_none_or(|filter| filter.matches(departure))
+    })
+}
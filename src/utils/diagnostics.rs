@@ -0,0 +1,196 @@
+use std::{
+    fmt::{Display, Formatter},
+    fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use chrono::Local;
+use color_eyre::eyre::Error;
+
+use crate::utils::backend::BookingSession;
+use crate::utils::departures::parse_departures;
+
+/// Text the site shows when a run has no seats left or there are no runs at all.
+const SOLD_OUT_MARKERS: [&str; 3] = ["nessuna corsa", "esaurit", "sold out"];
+/// Steps that fail on the search results, where a sold-out label may be about any
+/// of the runs listed.
+const RESULTS_STEPS: [&str; 3] = ["search", "select departure", "add to cart"];
+/// Classes ASP.NET MVC puts on forms rejected by server-side validation.
+const VALIDATION_MARKERS: [&str; 3] = [
+    "validation-summary-errors",
+    "field-validation-error",
+    "input-validation-error",
+];
+
+/// The page a session stopped on.
+pub struct PageSnapshot {
+    pub html: String,
+    /// PNG screenshot, when the session has a browser to take one.
+    pub screenshot: Option<Vec<u8>>,
+}
+
+/// What a failed booking ran into, guessed from the error and the page it stopped on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FailureKind {
+    SoldOut,
+    /// The page lacks an element the booking relies on.
+    SiteChanged,
+    /// The site rejected the submitted form.
+    ValidationError,
+    Unknown,
+}
+
+impl Display for FailureKind {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        let description = match self {
+            Self::SoldOut => "sold out",
+            Self::SiteChanged => "site changed",
+            Self::ValidationError => "form validation error",
+            Self::Unknown => "unknown error",
+        };
+        write!(f, "{}", description)
+    }
+}
+
+impl FromStr for FailureKind {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "sold-out" => Ok(Self::SoldOut),
+            "site-changed" => Ok(Self::SiteChanged),
+            "validation" => Ok(Self::ValidationError),
+            "unknown" => Ok(Self::Unknown),
+            _ => Err(Error::msg(format!("Unknown failure kind: {}", s))),
+        }
+    }
+}
+
+/// Guesses why booking `step` failed with `error` on the page `html`, `run` being
+/// the index of the run it was booking, if one was picked.
+pub fn classify(error: &Error, step: &str, html: &str, run: Option<usize>) -> FailureKind {
+    let page = html.to_lowercase();
+    let message = error.to_string().to_lowercase();
+    let page_sold_out = SOLD_OUT_MARKERS.iter().any(|marker| page.contains(marker));
+
+    // On the search results only the run being booked counts, or the whole page
+    // when it lists no runs at all
+    let sold_out = match (RESULTS_STEPS.contains(&step), run) {
+        (true, Some(index)) => parse_departures(html)
+            .iter()
+            .find(|departure| departure.index == index)
+            .is_some_and(|departure| departure.sold_out || departure.seats == Some(0)),
+        (true, None) => message.contains("no trips available") && page_sold_out,
+        (false, _) => page_sold_out,
+    };
+
+    if sold_out {
+        FailureKind::SoldOut
+    } else if VALIDATION_MARKERS
+        .iter()
+        .any(|marker| page.contains(marker))
+    {
+        FailureKind::ValidationError
+    } else if ["not found", "no such element", "no trips available"]
+        .iter()
+        .any(|marker| message.contains(marker))
+    {
+        FailureKind::SiteChanged
+    } else {
+        FailureKind::Unknown
+    }
+}
+
+/// A booking step that failed, with the files saved to diagnose it.
+#[derive(Debug)]
+pub struct BookingFailure {
+    pub step: &'static str,
+    pub kind: FailureKind,
+    pub error: Error,
+    pub screenshot: Option<PathBuf>,
+    pub page: Option<PathBuf>,
+}
+
+impl Display for BookingFailure {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "Booking failed at step \"{}\" ({}): {}",
+            self.step, self.kind, self.error
+        )
+    }
+}
+
+impl std::error::Error for BookingFailure {}
+
+/// Captures the page `session` stopped on after `step` failed while booking `run`,
+/// saving it under a new directory inside `dir` when one is given.
+pub async fn diagnose(
+    session: &mut dyn BookingSession,
+    dir: Option<&Path>,
+    step: &'static str,
+    run: Option<usize>,
+    error: Error,
+) -> BookingFailure {
+    let snapshot = match session.snapshot().await {
+        Ok(snapshot) => Some(snapshot),
+        Err(e) => {
+            println!("Failed to capture the page: {}", e);
+            None
+        }
+    };
+    let kind = classify(
+        &error,
+        step,
+        snapshot.as_ref().map_or("", |snapshot| &snapshot.html),
+        run,
+    );
+    println!("Booking failed at step {} ({}): {}", step, kind, error);
+
+    let (screenshot, page) = match (dir, &snapshot) {
+        (Some(dir), Some(snapshot)) => match save_snapshot(dir, step, snapshot) {
+            Ok(paths) => paths,
+            Err(e) => {
+                println!("Failed to save diagnostics: {}", e);
+                (None, None)
+            }
+        },
+        _ => (None, None),
+    };
+
+    BookingFailure {
+        step,
+        kind,
+        error,
+        screenshot,
+        page,
+    }
+}
+
+fn save_snapshot(
+    dir: &Path,
+    step: &str,
+    snapshot: &PageSnapshot,
+) -> Result<(Option<PathBuf>, Option<PathBuf>), Error> {
+    let booking_dir = dir.join(format!(
+        "{}-{}",
+        Local::now().format("%Y%m%d-%H%M%S%.3f"),
+        step.replace(' ', "-")
+    ));
+    fs::create_dir_all(&booking_dir)?;
+
+    let page = booking_dir.join("page.html");
+    fs::write(&page, &snapshot.html)?;
+
+    let screenshot = match &snapshot.screenshot {
+        Some(png) => {
+            let path = booking_dir.join("screenshot.png");
+            fs::write(&path, png)?;
+            Some(path)
+        }
+        None => None,
+    };
+    println!("Saved diagnostics to {}", booking_dir.display());
+    Ok((screenshot, Some(page)))
+}
@@ -0,0 +1,253 @@
+use std::{
+    path::PathBuf,
+    process::Stdio,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use color_eyre::eyre::Error;
+use reqwest::{Client, Url};
+use serde::Deserialize;
+use tokio::{
+    process::{Child, Command},
+    sync::{Mutex, watch},
+};
+
+/// How long a stopping driver is given to exit before it is killed.
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(5);
+/// How often a driver that is not ready yet is checked again.
+const STARTUP_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// How the WebDriver binary (geckodriver or chromedriver) is run and watched.
+#[derive(Debug, Clone)]
+pub struct DriverConfig {
+    pub binary: PathBuf,
+    pub args: Vec<String>,
+    /// Root of the server the binary starts, polled at `/status`.
+    pub url: String,
+    /// How often a running driver is checked.
+    pub health_interval: Duration,
+    /// How long the driver may stay unready before it is restarted, and bookings
+    /// wait for it.
+    pub startup_timeout: Duration,
+}
+
+impl DriverConfig {
+    /// Runs `binary` on the port of `url`.
+    pub fn new(binary: impl Into<PathBuf>, url: &str) -> Result<Self, Error> {
+        let port = Url::parse(url)?
+            .port_or_known_default()
+            .ok_or_else(|| Error::msg(format!("No port in WebDriver URL: {}", url)))?;
+        Ok(Self {
+            binary: binary.into(),
+            args: vec![format!("--port={}", port)],
+            url: url.trim_end_matches('/').to_string(),
+            health_interval: Duration::from_secs(10),
+            startup_timeout: Duration::from_secs(30),
+        })
+    }
+}
+
+#[derive(Deserialize)]
+struct Status {
+    value: StatusValue,
+}
+
+#[derive(Deserialize)]
+struct StatusValue {
+    ready: bool,
+}
+
+/// Keeps a WebDriver binary running: starts it, checks its `/status`, restarts it
+/// when it exits or stops answering, and stops it on [`DriverSupervisor::shutdown`].
+pub struct DriverSupervisor {
+    config: DriverConfig,
+    client: Client,
+    child: Mutex<Option<Child>>,
+    healthy: watch::Sender<bool>,
+    restarts: AtomicUsize,
+    stopping: AtomicBool,
+}
+
+impl DriverSupervisor {
+    /// Spawns the driver and the task watching it.
+    pub async fn start(config: DriverConfig) -> Result<Arc<Self>, Error> {
+        let supervisor = Arc::new(Self {
+            client: Client::builder().timeout(Duration::from_secs(2)).build()?,
+            child: Mutex::new(None),
+            healthy: watch::Sender::new(false),
+            restarts: AtomicUsize::new(0),
+            stopping: AtomicBool::new(false),
+            config,
+        });
+        *supervisor.child.lock().await = Some(supervisor.spawn()?);
+
+        tokio::spawn(supervisor.clone().monitor());
+        Ok(supervisor)
+    }
+
+    /// How many times the driver was started again.
+    pub fn restarts(&self) -> usize {
+        self.restarts.load(Ordering::SeqCst)
+    }
+
+    /// Process ID of the running driver.
+    pub async fn pid(&self) -> Option<u32> {
+        self.child.lock().await.as_ref().and_then(Child::id)
+    }
+
+    /// Waits until the driver reports it is ready for new sessions.
+    pub async fn wait_until_healthy(&self) -> Result<(), Error> {
+        let mut healthy = self.healthy.subscribe();
+        match tokio::time::timeout(
+            self.config.startup_timeout,
+            healthy.wait_for(|healthy| *healthy),
+        )
+        .await
+        {
+            Ok(Ok(_)) => Ok(()),
+            _ => Err(Error::msg(format!(
+                "WebDriver at {} is not ready after {}s",
+                self.config.url,
+                self.config.startup_timeout.as_secs()
+            ))),
+        }
+    }
+
+    /// Stops the driver, giving it a few seconds to exit on its own.
+    pub async fn shutdown(&self) {
+        self.stopping.store(true, Ordering::SeqCst);
+        self.healthy.send_replace(false);
+        if let Some(child) = self.child.lock().await.take() {
+            stop(child).await;
+        }
+    }
+
+    fn spawn(&self) -> Result<Child, Error> {
+        let child = Command::new(&self.config.binary)
+            .args(&self.config.args)
+            .stdin(Stdio::null())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|e| {
+                Error::msg(format!(
+                    "Failed to start {}: {}",
+                    self.config.binary.display(),
+                    e
+                ))
+            })?;
+        println!(
+            "Started {} (pid {})",
+            self.config.binary.display(),
+            child.id().unwrap_or_default()
+        );
+        Ok(child)
+    }
+
+    /// Replaces the driver with a new process, returning whether it started.
+    async fn restart(&self) -> bool {
+        let mut child = self.child.lock().await;
+        if self.stopping.load(Ordering::SeqCst) {
+            return false;
+        }
+        if let Some(old) = child.take() {
+            stop(old).await;
+        }
+        self.restarts.fetch_add(1, Ordering::SeqCst);
+        *child = match self.spawn() {
+            Ok(new) => Some(new),
+            Err(e) => {
+                println!("{}", e);
+                None
+            }
+        };
+        child.is_some()
+    }
+
+    /// Why the driver is not running, if it is not.
+    async fn exited(&self) -> Option<String> {
+        match self.child.lock().await.as_mut().map(Child::try_wait) {
+            None => Some("not running".to_string()),
+            Some(Ok(None)) => None,
+            Some(Ok(Some(status))) => Some(status.to_string()),
+            Some(Err(e)) => Some(e.to_string()),
+        }
+    }
+
+    async fn is_ready(&self) -> bool {
+        let response = self
+            .client
+            .get(format!("{}/status", self.config.url))
+            .send()
+            .await;
+        match response {
+            Ok(response) => response
+                .json::<Status>()
+                .await
+                .is_ok_and(|status| status.value.ready),
+            Err(_) => false,
+        }
+    }
+
+    async fn monitor(self: Arc<Self>) {
+        let mut unready_since: Option<Instant> = None;
+        let mut spawn_failed = false;
+        while !self.stopping.load(Ordering::SeqCst) {
+            let exited = self.exited().await;
+            let ready = exited.is_none() && self.is_ready().await;
+            if self.stopping.load(Ordering::SeqCst) {
+                break;
+            }
+            if self.healthy.send_replace(ready) != ready {
+                println!("WebDriver is {}", if ready { "ready" } else { "not ready" });
+            }
+
+            if let Some(reason) = exited {
+                println!("WebDriver exited ({}), restarting it", reason);
+                spawn_failed = !self.restart().await;
+                unready_since = None;
+            } else if ready {
+                unready_since = None;
+            } else {
+                let since = *unready_since.get_or_insert_with(Instant::now);
+                if since.elapsed() >= self.config.startup_timeout {
+                    println!(
+                        "WebDriver not ready for {}s, restarting it",
+                        since.elapsed().as_secs()
+                    );
+                    spawn_failed = !self.restart().await;
+                    unready_since = None;
+                }
+            }
+
+            // A binary that cannot be started is not tried again right away
+            let interval = if ready || spawn_failed {
+                self.config.health_interval
+            } else {
+                STARTUP_POLL_INTERVAL.min(self.config.health_interval)
+            };
+            tokio::time::sleep(interval).await;
+        }
+    }
+}
+
+/// Asks `child` to terminate, killing it if it is still running after the grace
+/// period.
+async fn stop(mut child: Child) {
+    #[cfg(unix)]
+    if let Some(pid) = child.id() {
+        // SAFETY: plain syscall on the PID of a child that has not been reaped yet
+        unsafe {
+            libc::kill(pid as libc::pid_t, libc::SIGTERM);
+        }
+        if let Ok(Ok(_)) = tokio::time::timeout(SHUTDOWN_GRACE, child.wait()).await {
+            return;
+        }
+    }
+    if let Err(e) = child.kill().await {
+        println!("Failed to stop the WebDriver: {}", e);
+    }
+}
@@ -0,0 +1,338 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use async_trait::async_trait;
+use color_eyre::eyre::Error;
+use reqwest::{Client, Url};
+use scraper::{ElementRef, Html, Selector};
+
+use crate::user::Passenger;
+use crate::utils::backend::{BookingBackend, BookingSession, SearchQuery};
+use crate::utils::booking::get_destinations;
+use crate::utils::departures::{BOOK_BUTTON, BOOK_BUTTON_SELECTOR, Departure, parse_departures};
+use crate::utils::diagnostics::PageSnapshot;
+use crate::utils::sessions::{SessionLease, SessionRegistry};
+use crate::utils::stops::StopCatalogue;
+
+/// An HTML form as found on a Contram page, ready to be submitted.
+#[derive(Debug, Clone)]
+pub struct Form {
+    pub action: Url,
+    pub method: String,
+    pub fields: Vec<(String, String)>,
+}
+
+impl Form {
+    /// Sets `name` to `value`, replacing an existing field with the same name.
+    pub fn set(&mut self, name: &str, value: &str) {
+        match self.fields.iter_mut().find(|(field, _)| field == name) {
+            Some((_, v)) => *v = value.to_string(),
+            None => self.fields.push((name.to_string(), value.to_string())),
+        }
+    }
+}
+
+/// Returns every form on the page whose submit button reads `button_text`.
+pub fn find_forms(html: &str, page_url: &Url, button_text: &str) -> Vec<Form> {
+    let document = Html::parse_document(html);
+    let form_selector = Selector::parse("form").unwrap();
+    let button_selector = Selector::parse(BOOK_BUTTON_SELECTOR).unwrap();
+
+    document
+        .select(&form_selector)
+        .filter(|form| {
+            form.select(&button_selector)
+                .any(|button| button_label(&button) == button_text)
+        })
+        .filter_map(|form| parse_form(&form, page_url))
+        .collect()
+}
+
+/// Returns the first form on the page whose submit button reads `button_text`.
+pub fn find_form(html: &str, page_url: &Url, button_text: &str) -> Result<Form, Error> {
+    find_forms(html, page_url, button_text)
+        .into_iter()
+        .next()
+        .ok_or_else(|| Error::msg(format!("Button \"{}\" not found", button_text)))
+}
+
+/// Returns the form that the booking button of run `index` submits, counting the
+/// buttons the way [`parse_departures`] does.
+///
+/// A button with a name sends it along with its value, as a browser would.
+pub fn find_book_form(html: &str, page_url: &Url, index: usize) -> Result<Form, Error> {
+    let document = Html::parse_document(html);
+    let button_selector = Selector::parse(BOOK_BUTTON_SELECTOR).unwrap();
+
+    let button = document
+        .select(&button_selector)
+        .filter(|button| button_label(button) == BOOK_BUTTON)
+        .nth(index)
+        .ok_or_else(|| Error::msg(format!("Button \"{}\" not found", BOOK_BUTTON)))?;
+    let mut form = button
+        .ancestors()
+        .filter_map(ElementRef::wrap)
+        .find(|element| element.value().name() == "form")
+        .and_then(|form| parse_form(&form, page_url))
+        .ok_or_else(|| Error::msg(format!("Button \"{}\" is not in a form", BOOK_BUTTON)))?;
+    if let Some(name) = button.value().attr("name") {
+        form.set(name, button.value().attr("value").unwrap_or_default());
+    }
+    Ok(form)
+}
+
+fn button_label(button: &ElementRef) -> String {
+    match button.value().name() {
+        "input" => button
+            .value()
+            .attr("value")
+            .unwrap_or_default()
+            .trim()
+            .to_string(),
+        _ => button.text().collect::<String>().trim().to_string(),
+    }
+}
+
+fn parse_form(form: &ElementRef, page_url: &Url) -> Option<Form> {
+    let input_selector = Selector::parse("input[name], select[name], textarea[name]").unwrap();
+    let option_selector = Selector::parse("option").unwrap();
+
+    let action = match form.value().attr("action") {
+        Some(action) if !action.is_empty() => page_url.join(action).ok()?,
+        _ => page_url.clone(),
+    };
+    let method = form
+        .value()
+        .attr("method")
+        .unwrap_or("get")
+        .to_ascii_lowercase();
+
+    let fields = form
+        .select(&input_selector)
+        .filter_map(|input| {
+            let element = input.value();
+            let name = element.attr("name")?.to_string();
+            let value = match element.name() {
+                "select" => input
+                    .select(&option_selector)
+                    .find(|option| option.value().attr("selected").is_some())
+                    .or_else(|| input.select(&option_selector).next())
+                    .and_then(|option| option.value().attr("value"))
+                    .unwrap_or_default()
+                    .to_string(),
+                "textarea" => input.text().collect(),
+                _ => {
+                    let kind = element.attr("type").unwrap_or("text");
+                    if matches!(kind, "submit" | "button" | "image" | "reset") {
+                        return None;
+                    }
+                    if matches!(kind, "checkbox" | "radio") && element.attr("checked").is_none() {
+                        return None;
+                    }
+                    element.attr("value").unwrap_or_default().to_string()
+                }
+            };
+            Some((name, value))
+        })
+        .collect();
+
+    Some(Form {
+        action,
+        method,
+        fields,
+    })
+}
+
+/// Booking session against the Contram website that only uses plain HTTP requests.
+///
+/// Cookies (including the anti-forgery cookie) are kept in the client's jar, while
+/// the matching `__RequestVerificationToken` field travels with each parsed form.
+pub struct HttpSession {
+    client: Client,
+    base_url: Url,
+    page_url: Url,
+    page: String,
+    /// Keeps the session listed until it is dropped.
+    lease: Option<SessionLease>,
+}
+
+impl HttpSession {
+    pub fn new(base_url: &str) -> Result<Self, Error> {
+        let client = Client::builder()
+            .cookie_store(true)
+            .user_agent(concat!(
+                env!("CARGO_PKG_NAME"),
+                "/",
+                env!("CARGO_PKG_VERSION")
+            ))
+            .build()?;
+        let base_url = Url::parse(base_url)?;
+
+        Ok(Self {
+            client,
+            page_url: base_url.clone(),
+            base_url,
+            page: String::new(),
+            lease: None,
+        })
+    }
+
+    /// Loads a page relative to the base URL and makes it the current page.
+    pub async fn goto(&mut self, path: &str) -> Result<&str, Error> {
+        let url = self.base_url.join(path)?;
+        let response = self.client.get(url).send().await?.error_for_status()?;
+        self.page_url = response.url().clone();
+        self.page = response.text().await?;
+        Ok(&self.page)
+    }
+
+    /// Submits a form and makes the response the current page.
+    pub async fn submit(&mut self, form: &Form) -> Result<&str, Error> {
+        let request = match form.method.as_str() {
+            "post" => self.client.post(form.action.clone()).form(&form.fields),
+            _ => self.client.get(form.action.clone()).query(&form.fields),
+        };
+        let response = request.send().await?.error_for_status()?;
+        self.page_url = response.url().clone();
+        self.page = response.text().await?;
+        Ok(&self.page)
+    }
+
+    /// Finds the form submitted by the button reading `button_text` on the current page.
+    pub fn form(&self, button_text: &str) -> Result<Form, Error> {
+        find_form(&self.page, &self.page_url, button_text)
+    }
+}
+
+/// Books tickets with plain HTTP requests, without a browser.
+pub struct HttpBackend {
+    base_url: String,
+    sessions: Option<Arc<SessionRegistry>>,
+    stops: StopCatalogue,
+    diagnostics_dir: Option<PathBuf>,
+}
+
+impl HttpBackend {
+    pub fn new(base_url: &str) -> Self {
+        Self {
+            base_url: base_url.to_string(),
+            sessions: None,
+            stops: StopCatalogue::new(base_url),
+            diagnostics_dir: None,
+        }
+    }
+
+    /// Lists the open sessions in `sessions`.
+    pub fn with_sessions(self, sessions: Arc<SessionRegistry>) -> Self {
+        Self {
+            sessions: Some(sessions),
+            ..self
+        }
+    }
+
+    pub fn with_stops(self, stops: StopCatalogue) -> Self {
+        Self { stops, ..self }
+    }
+
+    /// Saves the pages of failed bookings under `dir`.
+    pub fn with_diagnostics(self, dir: impl Into<PathBuf>) -> Self {
+        Self {
+            diagnostics_dir: Some(dir.into()),
+            ..self
+        }
+    }
+}
+
+#[async_trait]
+impl BookingBackend for HttpBackend {
+    fn name(&self) -> &'static str {
+        "http"
+    }
+
+    async fn get_cities(&self) -> Result<Vec<(String, u32)>, Error> {
+        self.stops.cities().await
+    }
+
+    async fn refresh_cities(&self) -> Result<Vec<(String, u32)>, Error> {
+        self.stops.refresh().await
+    }
+
+    fn diagnostics_dir(&self) -> Option<&Path> {
+        self.diagnostics_dir.as_deref()
+    }
+
+    fn sessions(&self) -> Option<&Arc<SessionRegistry>> {
+        self.sessions.as_ref()
+    }
+
+    async fn get_destinations(&self, from_id: u32) -> Result<Vec<(String, u32)>, Error> {
+        get_destinations(&self.base_url, from_id).await
+    }
+
+    async fn open_session(&self) -> Result<Box<dyn BookingSession>, Error> {
+        let mut session = HttpSession::new(&self.base_url)?;
+        session.lease = self
+            .sessions
+            .as_ref()
+            .map(|sessions| sessions.register(self.name(), None));
+        Ok(Box::new(session))
+    }
+}
+
+#[async_trait]
+impl BookingSession for HttpSession {
+    async fn prepare(&mut self) -> Result<(), Error> {
+        self.goto("").await?;
+        Ok(())
+    }
+
+    async fn search(&mut self, query: &SearchQuery) -> Result<Vec<Departure>, Error> {
+        let url = query.path();
+        let page = self.goto(&url).await?;
+        println!("Loaded URL: {}", url);
+        Ok(parse_departures(page))
+    }
+
+    async fn add_to_cart(&mut self, departure: &Departure) -> Result<(), Error> {
+        let booking_form = find_book_form(&self.page, &self.page_url, departure.index)?;
+        self.submit(&booking_form).await?;
+
+        self.goto("Home/RitornaCarrello?").await?;
+        println!("Navigated to cart");
+        Ok(())
+    }
+
+    async fn fill_passenger_data(&mut self, passengers: &[Passenger]) -> Result<(), Error> {
+        let mut checkout_form = self.form("Procedi all'acquisto")?;
+        for (index, passenger) in passengers.iter().enumerate() {
+            for (field, value) in passenger.form_fields(index) {
+                checkout_form.set(&field, &value);
+            }
+        }
+        self.submit(&checkout_form).await?;
+        Ok(())
+    }
+
+    async fn confirm(&mut self) -> Result<String, Error> {
+        let confirm_form = self.form("Conferma acquisto")?;
+        Ok(self.submit(&confirm_form).await?.to_string())
+    }
+
+    async fn review(&mut self) -> Result<PageSnapshot, Error> {
+        self.form("Conferma acquisto")?;
+        self.snapshot().await
+    }
+
+    async fn snapshot(&mut self) -> Result<PageSnapshot, Error> {
+        Ok(PageSnapshot {
+            html: self.page.clone(),
+            screenshot: None,
+        })
+    }
+
+    async fn close(self: Box<Self>) -> Result<(), Error> {
+        Ok(())
+    }
+}
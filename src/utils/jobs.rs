@@ -0,0 +1,368 @@
+use std::{
+    fmt::{Display, Formatter},
+    fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::Mutex,
+};
+
+use chrono::{DateTime, Days, NaiveDate, TimeZone, Utc};
+use chrono_tz::{Europe::Rome, Tz};
+use color_eyre::eyre::Error;
+use serde::{Deserialize, Serialize};
+
+use crate::utils::backend::SearchQuery;
+use crate::utils::departures::DepartureFilter;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum JobStatus {
+    /// Waiting for its due time.
+    Pending,
+    /// Booking right now.
+    Running,
+    Booked,
+    Failed,
+    /// Was due while the bot was down, or stopped by a restart.
+    Missed,
+    /// Called off by its user before it ran.
+    Cancelled,
+}
+
+impl Display for JobStatus {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        let name = match self {
+            Self::Pending => "pending",
+            Self::Running => "running",
+            Self::Booked => "booked",
+            Self::Failed => "failed",
+            Self::Missed => "missed",
+            Self::Cancelled => "cancelled",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// What to do with jobs that fell due while the bot was not running.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MissedJobPolicy {
+    /// Book right away.
+    Run,
+    /// Tell the user that the booking did not happen.
+    Report,
+}
+
+impl FromStr for MissedJobPolicy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "run" => Ok(Self::Run),
+            "report" => Ok(Self::Report),
+            _ => Err(Error::msg(format!("Unknown missed job policy: {}", s))),
+        }
+    }
+}
+
+/// A scheduled booking, kept on disk until it has run.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BookingJob {
+    pub id: u64,
+    pub chat_id: i64,
+    /// Registered user the ticket is booked for.
+    pub username: String,
+    pub from_id: u32,
+    pub to_id: u32,
+    /// Names of the stops, as `from → to`.
+    pub route: String,
+    /// Travel date as `YYYY-MM-DD`.
+    pub date: String,
+    /// Run to book, as `HH:MM` or `HH:MM-HH:MM`.
+    pub departure: Option<String>,
+    /// Date of the way back, booked in the same cart, as `YYYY-MM-DD`.
+    #[serde(default)]
+    pub return_date: Option<String>,
+    /// Passengers travelling with the user, as given to `/bookgroup`.
+    #[serde(default)]
+    pub companions: Vec<String>,
+    /// Job booking the outbound trip, when this one books the way back on its own.
+    #[serde(default)]
+    pub outbound_id: Option<u64>,
+    /// When booking for the date opens.
+    pub due: DateTime<Utc>,
+    pub status: JobStatus,
+    /// Booking code, or why the job did not book.
+    pub note: Option<String>,
+    pub created: DateTime<Utc>,
+}
+
+impl BookingJob {
+    pub fn new(
+        chat_id: i64,
+        username: &str,
+        route: String,
+        query: &SearchQuery,
+        due: DateTime<Utc>,
+    ) -> Self {
+        Self {
+            id: 0,
+            chat_id,
+            username: username.to_string(),
+            from_id: query.from_id,
+            to_id: query.to_id,
+            route,
+            date: query.date.clone(),
+            departure: query.departure.map(|departure| departure.to_string()),
+            return_date: None,
+            companions: Vec::new(),
+            outbound_id: None,
+            due,
+            status: JobStatus::Pending,
+            note: None,
+            created: Utc::now(),
+        }
+    }
+
+    /// Books the way back on `date` along with the outbound trip.
+    pub fn with_return_date(mut self, date: Option<String>) -> Self {
+        self.return_date = date;
+        self
+    }
+
+    pub fn with_companions(mut self, companions: Vec<String>) -> Self {
+        self.companions = companions;
+        self
+    }
+
+    /// Books the way back of the trip whose outbound job is `id`.
+    pub fn with_outbound(mut self, id: u64) -> Self {
+        self.outbound_id = Some(id);
+        self
+    }
+
+    /// The search the job books from.
+    pub fn query(&self) -> Result<SearchQuery, Error> {
+        let departure = self
+            .departure
+            .as_deref()
+            .map(DepartureFilter::parse)
+            .transpose()?;
+        Ok(SearchQuery::new(self.from_id, self.to_id, self.date.clone()).with_departure(departure))
+    }
+
+    /// The searches of every leg the job books, the way back last.
+    pub fn legs(&self) -> Result<Vec<SearchQuery>, Error> {
+        let mut legs = vec![self.query()?];
+        if let Some(return_date) = &self.return_date {
+            legs.push(SearchQuery::new(
+                self.to_id,
+                self.from_id,
+                return_date.clone(),
+            ));
+        }
+        Ok(legs)
+    }
+}
+
+impl Display for BookingJob {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        write!(f, "#{} {} on {}", self.id, self.route, self.date)?;
+        if let Some(departure) = &self.departure {
+            write!(f, " at {}", departure)?;
+        }
+        if let Some(return_date) = &self.return_date {
+            write!(f, " and back on {}", return_date)?;
+        }
+        if !self.companions.is_empty() {
+            write!(f, " with {} more passenger(s)", self.companions.len())?;
+        }
+        if let Some(outbound_id) = self.outbound_id {
+            write!(f, " (way back of #{})", outbound_id)?;
+        }
+        write!(
+            f,
+            ", {} (due {})",
+            self.status,
+            self.due.with_timezone(&Rome).format("%Y-%m-%d %H:%M")
+        )?;
+        if let Some(note) = &self.note {
+            write!(f, ": {}", note)?;
+        }
+        Ok(())
+    }
+}
+
+/// Jobs left over from the previous run of the bot.
+#[derive(Debug, Default)]
+pub struct RecoveredJobs {
+    /// To be scheduled again, at once when already due.
+    pub rearm: Vec<BookingJob>,
+    /// Marked as missed, for their users to be told.
+    pub missed: Vec<BookingJob>,
+}
+
+/// Booking jobs saved to a JSON file on every change, so that a restart does not
+/// lose them.
+pub struct JobStore {
+    path: PathBuf,
+    jobs: Mutex<Vec<BookingJob>>,
+}
+
+impl JobStore {
+    /// Loads the jobs saved at `path`, starting empty when there is no such file.
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self, Error> {
+        let path = path.into();
+        let jobs = match fs::read_to_string(&path) {
+            Ok(contents) if !contents.trim().is_empty() => serde_json::from_str(&contents)?,
+            _ => Vec::new(),
+        };
+        Ok(Self {
+            path,
+            jobs: Mutex::new(jobs),
+        })
+    }
+
+    /// Saves `job` under a new ID and returns it as stored.
+    pub fn add(&self, mut job: BookingJob) -> Result<BookingJob, Error> {
+        let mut jobs = self.jobs.lock().unwrap();
+        job.id = jobs.iter().map(|job| job.id).max().unwrap_or(0) + 1;
+        jobs.push(job.clone());
+        save(&self.path, &jobs)?;
+        Ok(job)
+    }
+
+    pub fn get(&self, id: u64) -> Option<BookingJob> {
+        self.jobs
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|job| job.id == id)
+            .cloned()
+    }
+
+    /// Job `id` and the other leg of its return trip, if booked as a job of its
+    /// own, outbound first.
+    pub fn trip(&self, id: u64) -> Vec<BookingJob> {
+        let jobs = self.jobs.lock().unwrap();
+        let Some(job) = jobs.iter().find(|job| job.id == id) else {
+            return Vec::new();
+        };
+        let outbound_id = job.outbound_id.unwrap_or(id);
+        jobs.iter()
+            .filter(|job| job.id == outbound_id || job.outbound_id == Some(outbound_id))
+            .cloned()
+            .collect()
+    }
+
+    /// Every job, oldest first.
+    pub fn list(&self) -> Vec<BookingJob> {
+        self.jobs.lock().unwrap().clone()
+    }
+
+    /// Moves job `id` to `status`, noting what came of it.
+    pub fn set_status(
+        &self,
+        id: u64,
+        status: JobStatus,
+        note: Option<String>,
+    ) -> Result<(), Error> {
+        let mut jobs = self.jobs.lock().unwrap();
+        let job = jobs
+            .iter_mut()
+            .find(|job| job.id == id)
+            .ok_or_else(|| Error::msg(format!("No job #{}", id)))?;
+        job.status = status;
+        job.note = note;
+        save(&self.path, &jobs)
+    }
+
+    /// Moves job `id` to the travel `date`, due at `due`, pending again.
+    ///
+    /// A job that books the way back in the same cart can only move to a date
+    /// whose booking opens once the way back is bookable too.
+    pub fn reschedule(
+        &self,
+        id: u64,
+        date: String,
+        due: DateTime<Utc>,
+    ) -> Result<BookingJob, Error> {
+        let mut jobs = self.jobs.lock().unwrap();
+        let job = jobs
+            .iter_mut()
+            .find(|job| job.id == id)
+            .ok_or_else(|| Error::msg(format!("No job #{}", id)))?;
+        if let Some(return_date) = &job.return_date {
+            if *return_date < date {
+                return Err(Error::msg(format!(
+                    "the way back on {} would come before {}",
+                    return_date, date
+                )));
+            }
+            let return_due =
+                booking_open_datetime(NaiveDate::parse_from_str(return_date, "%Y-%m-%d")?);
+            if return_due > due.max(Utc::now()) {
+                return Err(Error::msg(format!(
+                    "booking for the way back on {} only opens on {}, cancel and book it again with /bookreturn",
+                    return_date,
+                    return_due.format("%Y-%m-%d %H:%M")
+                )));
+            }
+        }
+        job.date = date;
+        job.due = due;
+        job.status = JobStatus::Pending;
+        job.note = None;
+        let job = job.clone();
+        save(&self.path, &jobs)?;
+        Ok(job)
+    }
+
+    /// Sorts out the jobs that had not finished when the bot stopped.
+    ///
+    /// Pending jobs are scheduled again; those already due run at once under
+    /// [`MissedJobPolicy::Run`] and are marked missed otherwise. Jobs cut off while
+    /// booking are always marked missed, since the ticket may have been bought.
+    pub fn recover(
+        &self,
+        now: DateTime<Utc>,
+        policy: MissedJobPolicy,
+    ) -> Result<RecoveredJobs, Error> {
+        let mut jobs = self.jobs.lock().unwrap();
+        let mut recovered = RecoveredJobs::default();
+        for job in jobs.iter_mut() {
+            match job.status {
+                JobStatus::Pending if job.due > now || policy == MissedJobPolicy::Run => {
+                    recovered.rearm.push(job.clone());
+                }
+                JobStatus::Pending => {
+                    job.status = JobStatus::Missed;
+                    job.note = Some("the bot was offline when booking opened".to_string());
+                    recovered.missed.push(job.clone());
+                }
+                JobStatus::Running => {
+                    job.status = JobStatus::Missed;
+                    job.note = Some("interrupted by a restart, check your email".to_string());
+                    recovered.missed.push(job.clone());
+                }
+                _ => {}
+            }
+        }
+        save(&self.path, &jobs)?;
+        Ok(recovered)
+    }
+}
+
+/// Booking for a trip opens 7 days before, at midnight Rome time.
+pub fn booking_open_datetime(date: NaiveDate) -> DateTime<Tz> {
+    let booking_open_date = date.checked_sub_days(Days::new(7)).unwrap();
+    Rome.from_local_datetime(&booking_open_date.and_hms_opt(0, 0, 0).unwrap())
+        .unwrap()
+}
+
+/// Writes `items` next to `path` first, so that a crash never leaves half a file.
+pub(crate) fn save<T: Serialize>(path: &Path, items: &[T]) -> Result<(), Error> {
+    let temp_path = path.with_extension("json.tmp");
+    fs::write(&temp_path, serde_json::to_string_pretty(items)?)?;
+    fs::rename(&temp_path, path)?;
+    Ok(())
+}
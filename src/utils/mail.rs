@@ -0,0 +1,304 @@
+use std::{collections::HashSet, fmt::Debug, fs, path::PathBuf, sync::Mutex, time::Duration};
+
+use async_imap::{Client, types::Fetch};
+use chrono::{DateTime, NaiveDate, Utc};
+use color_eyre::eyre::Error;
+use futures::TryStreamExt;
+use mail_parser::{MessageParser, MimeHeaders};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::TcpStream,
+};
+use tokio_native_tls::{TlsConnector, native_tls};
+
+use crate::user::Passenger;
+use crate::utils::jobs::save;
+use crate::utils::receipt::{BookingReceipt, ReceiptTrip};
+use crate::utils::stops::normalize_stop_name;
+
+/// How long a booking waits for its ticket email before it is forgotten.
+pub const PENDING_TICKET_TTL: Duration = Duration::from_secs(2 * 24 * 60 * 60);
+
+/// Shared mailbox the Contram ticket emails are delivered to.
+#[derive(Debug, Clone)]
+pub struct MailConfig {
+    pub host: String,
+    pub port: u16,
+    pub tls: bool,
+    pub username: String,
+    pub password: String,
+    pub mailbox: String,
+    pub poll_interval: Duration,
+    /// Address of the mailbox, given to Contram as the buyer email of every
+    /// booking so that the tickets arrive there.
+    pub address: String,
+}
+
+/// A booked ticket whose email has not arrived yet.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PendingTicket {
+    /// Telegram chat the ticket goes to.
+    pub chat_id: i64,
+    pub trips: Vec<ReceiptTrip>,
+    /// First and last name of each passenger.
+    pub names: Vec<(String, String)>,
+    registered_at: DateTime<Utc>,
+}
+
+impl PendingTicket {
+    pub fn new(chat_id: i64, receipt: &BookingReceipt, passengers: &[Passenger]) -> Self {
+        Self {
+            chat_id,
+            trips: receipt.trips.clone(),
+            names: passengers
+                .iter()
+                .map(|passenger| {
+                    (
+                        passenger.user.get_first_name(),
+                        passenger.user.get_last_name(),
+                    )
+                })
+                .collect(),
+            registered_at: Utc::now(),
+        }
+    }
+
+    /// Whether `mail` is the ticket of this booking: it names a passenger and both
+    /// stops and the date of one of the trips.
+    pub fn matches(&self, mail: &TicketMail) -> bool {
+        let text = normalize_stop_name(&format!("{} {}", mail.subject, mail.text));
+        let words: HashSet<&str> = text.split(' ').collect();
+        let padded = format!(" {} ", text);
+
+        // Short words such as the "F.S." of a station name are often left out
+        let mentions = |name: &str| {
+            let name = normalize_stop_name(name);
+            let long_words: Vec<&str> = name.split(' ').filter(|word| word.len() >= 3).collect();
+            let needed = if long_words.is_empty() {
+                name.split(' ').collect()
+            } else {
+                long_words
+            };
+            !name.is_empty() && needed.iter().all(|word| words.contains(word))
+        };
+        let mentions_date = |date: &str| {
+            let Ok(date) = NaiveDate::parse_from_str(date, "%Y-%m-%d") else {
+                return false;
+            };
+            ["%Y %m %d", "%d %m %Y", "%-d %-m %Y"]
+                .iter()
+                .any(|format| padded.contains(&format!(" {} ", date.format(format))))
+        };
+
+        let trip_matches = self
+            .trips
+            .iter()
+            .any(|trip| mentions(&trip.from) && mentions(&trip.to) && mentions_date(&trip.date));
+        let name_matches = self
+            .names
+            .iter()
+            .any(|(first_name, last_name)| mentions(first_name) && mentions(last_name));
+        trip_matches && name_matches
+    }
+}
+
+/// An email with at least one PDF attached.
+#[derive(Debug, Clone)]
+pub struct TicketMail {
+    pub uid: u32,
+    pub subject: String,
+    pub text: String,
+    pub pdfs: Vec<TicketPdf>,
+}
+
+#[derive(Debug, Clone)]
+pub struct TicketPdf {
+    pub file_name: String,
+    pub contents: Vec<u8>,
+}
+
+/// Parses a raw email, keeping it only if it carries a PDF.
+pub fn parse_ticket_mail(uid: u32, raw: &[u8]) -> Option<TicketMail> {
+    let message = MessageParser::default().parse(raw)?;
+
+    let pdfs: Vec<TicketPdf> = message
+        .attachments()
+        .filter(|part| {
+            part.is_content_type("application", "pdf")
+                || part
+                    .attachment_name()
+                    .is_some_and(|name| name.to_lowercase().ends_with(".pdf"))
+        })
+        .map(|part| TicketPdf {
+            file_name: part
+                .attachment_name()
+                .unwrap_or("biglietto.pdf")
+                .to_string(),
+            contents: part.contents().to_vec(),
+        })
+        .collect();
+    if pdfs.is_empty() {
+        return None;
+    }
+
+    let text = (0..message.text_body_count())
+        .filter_map(|index| message.body_text(index))
+        .collect::<Vec<_>>()
+        .join("\n");
+    Some(TicketMail {
+        uid,
+        subject: message.subject().unwrap_or_default().to_string(),
+        text,
+        pdfs,
+    })
+}
+
+/// Polls the shared booking mailbox for the tickets of pending bookings.
+///
+/// The bookings waiting for their ticket are saved to a JSON file on every change,
+/// so that a restart does not drop them.
+pub struct MailIngest {
+    config: MailConfig,
+    path: PathBuf,
+    pending: Mutex<Vec<PendingTicket>>,
+}
+
+impl MailIngest {
+    /// Loads the tickets still awaited from `path`, starting empty when there is
+    /// no such file.
+    pub fn open(config: MailConfig, path: impl Into<PathBuf>) -> Result<Self, Error> {
+        let path = path.into();
+        let pending = match fs::read_to_string(&path) {
+            Ok(contents) if !contents.trim().is_empty() => serde_json::from_str(&contents)?,
+            _ => Vec::new(),
+        };
+        Ok(Self {
+            config,
+            path,
+            pending: Mutex::new(pending),
+        })
+    }
+
+    pub fn poll_interval(&self) -> Duration {
+        self.config.poll_interval
+    }
+
+    /// Address the ticket emails are to be sent to.
+    pub fn address(&self) -> &str {
+        &self.config.address
+    }
+
+    /// Waits for the ticket email of a booking.
+    pub fn expect(&self, ticket: PendingTicket) -> Result<(), Error> {
+        let mut pending = self.pending.lock().unwrap();
+        pending.push(ticket);
+        save(&self.path, &pending)
+    }
+
+    /// Tickets still awaited, oldest first.
+    pub fn pending(&self) -> Vec<PendingTicket> {
+        self.pending.lock().unwrap().clone()
+    }
+
+    /// Fetches the unread emails and pairs each ticket with the booking it belongs
+    /// to. Matched emails are flagged as read and their bookings stop waiting.
+    pub async fn poll(&self) -> Result<Vec<(PendingTicket, TicketMail)>, Error> {
+        {
+            let mut pending = self.pending.lock().unwrap();
+            let count = pending.len();
+            pending.retain(|ticket| {
+                (Utc::now() - ticket.registered_at)
+                    .to_std()
+                    .is_ok_and(|age| age < PENDING_TICKET_TTL)
+            });
+            if pending.len() < count {
+                save(&self.path, &pending)?;
+            }
+            if pending.is_empty() {
+                return Ok(Vec::new());
+            }
+        }
+
+        let tcp = TcpStream::connect((self.config.host.as_str(), self.config.port)).await?;
+        if self.config.tls {
+            let connector = TlsConnector::from(native_tls::TlsConnector::new()?);
+            let stream = connector.connect(&self.config.host, tcp).await?;
+            self.poll_stream(stream).await
+        } else {
+            self.poll_stream(tcp).await
+        }
+    }
+
+    async fn poll_stream<T>(&self, stream: T) -> Result<Vec<(PendingTicket, TicketMail)>, Error>
+    where
+        T: AsyncRead + AsyncWrite + Unpin + Debug + Send,
+    {
+        let mut client = Client::new(stream);
+        client
+            .read_response()
+            .await?
+            .ok_or_else(|| Error::msg("The IMAP server closed the connection"))?;
+        let mut session = client
+            .login(&self.config.username, &self.config.password)
+            .await
+            .map_err(|(e, _)| e)?;
+        session.select(&self.config.mailbox).await?;
+
+        let uids = session.uid_search("UNSEEN").await?;
+        let mut matched = Vec::new();
+        if !uids.is_empty() {
+            let uid_set = uids
+                .iter()
+                .map(|uid| uid.to_string())
+                .collect::<Vec<_>>()
+                .join(",");
+            let fetches: Vec<Fetch> = session
+                .uid_fetch(&uid_set, "(UID BODY.PEEK[])")
+                .await?
+                .try_collect()
+                .await?;
+
+            for fetch in &fetches {
+                let (Some(uid), Some(body)) = (fetch.uid, fetch.body()) else {
+                    continue;
+                };
+                let Some(mail) = parse_ticket_mail(uid, body) else {
+                    continue;
+                };
+                let mut pending = self.pending.lock().unwrap();
+                if let Some(index) = pending.iter().position(|ticket| ticket.matches(&mail)) {
+                    matched.push((pending.remove(index), mail));
+                }
+            }
+
+            if !matched.is_empty()
+                && let Err(e) = save(&self.path, &self.pending.lock().unwrap())
+            {
+                println!("Failed to save the pending tickets: {}", e);
+            }
+        }
+
+        // The matched tickets are off the pending list by now, so they are handed
+        // over even when their emails cannot be marked as read
+        let finished: Result<(), Error> = async {
+            for (_, mail) in &matched {
+                session
+                    .uid_store(mail.uid.to_string(), "+FLAGS (\\Seen)")
+                    .await?
+                    .try_collect::<Vec<_>>()
+                    .await?;
+            }
+            session.logout().await?;
+            Ok(())
+        }
+        .await;
+        if let Err(e) = finished {
+            if matched.is_empty() {
+                return Err(e);
+            }
+            println!("Failed to mark the ticket emails as read: {}", e);
+        }
+        Ok(matched)
+    }
+}
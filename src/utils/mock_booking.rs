@@ -0,0 +1,145 @@
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use chrono::NaiveTime;
+use color_eyre::eyre::Error;
+
+use crate::user::Passenger;
+use crate::utils::backend::{BookingBackend, BookingSession, SearchQuery};
+use crate::utils::departures::Departure;
+use crate::utils::diagnostics::PageSnapshot;
+
+const MOCK_CONFIRMATION: &str = "<html><body><h2>Acquisto completato</h2>\
+    <dl><dt>Codice prenotazione</dt><dd>MOCK-0001</dd><dt>Totale</dt><dd>€ 9,50</dd></dl>\
+    </body></html>";
+
+/// In-memory backend that never touches the network, for trying out the bot.
+///
+/// Every step a session goes through is appended to `steps`, and `fail_at` makes
+/// the step with that name fail.
+#[derive(Default)]
+pub struct MockBackend {
+    pub steps: Arc<Mutex<Vec<String>>>,
+    pub fail_at: Option<&'static str>,
+}
+
+impl MockBackend {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl BookingBackend for MockBackend {
+    fn name(&self) -> &'static str {
+        "mock"
+    }
+
+    async fn get_cities(&self) -> Result<Vec<(String, u32)>, Error> {
+        Ok(vec![
+            ("Camerino".to_string(), 24),
+            ("Ancona Piazza Cavour".to_string(), 38),
+            ("Ancona Stazione F.S.".to_string(), 39),
+        ])
+    }
+
+    async fn get_destinations(&self, from_id: u32) -> Result<Vec<(String, u32)>, Error> {
+        let mut destinations = self.get_cities().await?;
+        destinations.retain(|&(_, id)| id != from_id);
+        Ok(destinations)
+    }
+
+    async fn open_session(&self) -> Result<Box<dyn BookingSession>, Error> {
+        if self.fail_at == Some("open") {
+            return Err(Error::msg("Mock failure at step open"));
+        }
+        Ok(Box::new(MockSession {
+            steps: self.steps.clone(),
+            fail_at: self.fail_at,
+        }))
+    }
+}
+
+pub struct MockSession {
+    steps: Arc<Mutex<Vec<String>>>,
+    fail_at: Option<&'static str>,
+}
+
+impl MockSession {
+    fn record(&self, step: &'static str, detail: String) -> Result<(), Error> {
+        self.steps
+            .lock()
+            .unwrap()
+            .push(format!("{} {}", step, detail).trim_end().to_string());
+        match self.fail_at {
+            Some(failing) if failing == step => {
+                Err(Error::msg(format!("Mock failure at step {}", step)))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[async_trait]
+impl BookingSession for MockSession {
+    async fn prepare(&mut self) -> Result<(), Error> {
+        self.record("prepare", String::new())
+    }
+
+    async fn search(&mut self, query: &SearchQuery) -> Result<Vec<Departure>, Error> {
+        self.record("search", query.path())?;
+        Ok(["06:10", "13:40", "17:30"]
+            .into_iter()
+            .enumerate()
+            .map(|(index, time)| Departure {
+                index,
+                time: NaiveTime::parse_from_str(time, "%H:%M").unwrap(),
+                arrival_time: None,
+                price: Some(9.5),
+                seats: Some(10),
+                sold_out: false,
+            })
+            .collect())
+    }
+
+    async fn add_to_cart(&mut self, departure: &Departure) -> Result<(), Error> {
+        self.record("add_to_cart", departure.time.format("%H:%M").to_string())
+    }
+
+    async fn fill_passenger_data(&mut self, passengers: &[Passenger]) -> Result<(), Error> {
+        let names = passengers
+            .iter()
+            .map(|passenger| {
+                format!(
+                    "{} {}",
+                    passenger.user.get_first_name(),
+                    passenger.user.get_last_name()
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+        self.record("fill_passenger_data", names)
+    }
+
+    async fn confirm(&mut self) -> Result<String, Error> {
+        self.record("confirm", String::new())?;
+        Ok(MOCK_CONFIRMATION.to_string())
+    }
+
+    async fn review(&mut self) -> Result<PageSnapshot, Error> {
+        self.record("review", String::new())?;
+        self.snapshot().await
+    }
+
+    async fn snapshot(&mut self) -> Result<PageSnapshot, Error> {
+        let steps = self.steps.lock().unwrap().join("\n");
+        Ok(PageSnapshot {
+            html: format!("<html><body><pre>{}</pre></body></html>", steps),
+            screenshot: None,
+        })
+    }
+
+    async fn close(self: Box<Self>) -> Result<(), Error> {
+        self.record("close", String::new())
+    }
+}
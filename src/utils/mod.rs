@@ -1,3 +1,21 @@
+pub mod backend;
 pub mod booking;
+pub mod booking_error;
+pub mod config;
+pub mod departures;
+pub mod diagnostics;
+pub mod driver;
 pub mod file_manager;
+pub mod http_booking;
+pub mod jobs;
+pub mod mail;
+pub mod mock_booking;
+pub mod receipt;
+pub mod recurring;
+pub mod retry;
+pub mod scheduler;
+pub mod sessions;
 pub mod sticker;
+pub mod stops;
+pub mod watch;
+pub mod webdriver;
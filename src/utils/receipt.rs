@@ -0,0 +1,236 @@
+use std::{
+    fmt::{Display, Formatter},
+    fs,
+    path::PathBuf,
+    sync::Mutex,
+};
+
+use chrono::Local;
+use color_eyre::eyre::Error;
+use regex::Regex;
+use scraper::{Html, Selector};
+use serde::{Deserialize, Serialize};
+
+use crate::utils::diagnostics::PageSnapshot;
+use crate::utils::jobs::save;
+
+/// Headings the site shows once a purchase has gone through.
+const CONFIRMATION_MARKERS: [&str; 3] = [
+    "acquisto completato",
+    "prenotazione confermata",
+    "pagamento completato",
+];
+
+/// A leg of a confirmed booking.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ReceiptTrip {
+    pub from: String,
+    pub to: String,
+    pub date: String,
+    /// Departure time as `HH:MM`.
+    pub departure: String,
+}
+
+/// What was bought, as shown to the user and kept in `RECEIPTS_FILE`.
+///
+/// Trips and passengers are the ones the bot booked; code, price and status come
+/// from the confirmation page.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BookingReceipt {
+    pub code: Option<String>,
+    pub trips: Vec<ReceiptTrip>,
+    pub passengers: Vec<String>,
+    /// Total in euros.
+    pub price: Option<f64>,
+    pub status: String,
+    /// Where the ticket is sent.
+    pub email: String,
+}
+
+impl Display for BookingReceipt {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        for trip in &self.trips {
+            writeln!(
+                f,
+                "Ticket booked from {} to {} on {} at {}",
+                trip.from, trip.to, trip.date, trip.departure
+            )?;
+        }
+        if let Some(code) = &self.code {
+            writeln!(f, "Booking code: {}", code)?;
+        }
+        writeln!(f, "Status: {}", self.status)?;
+        if let Some(price) = self.price {
+            writeln!(f, "Total: {:.2} €", price)?;
+        }
+        match &self.passengers[..] {
+            [_] => write!(f, "An email will be sent to: {}", self.email),
+            passengers => write!(
+                f,
+                "Passengers:\n{}",
+                passengers
+                    .iter()
+                    .map(|passenger| format!("- {}", passenger))
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            ),
+        }
+    }
+}
+
+/// What a dry run would have bought, stopping on the purchase summary.
+pub struct BookingPreview {
+    pub trips: Vec<ReceiptTrip>,
+    pub passengers: Vec<String>,
+    /// Passenger form fields as submitted, in order.
+    pub fields: Vec<(String, String)>,
+    /// The page with the "Conferma acquisto" button that was not clicked.
+    pub summary: PageSnapshot,
+}
+
+impl Display for BookingPreview {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        writeln!(f, "Dry run, nothing was bought.")?;
+        for trip in &self.trips {
+            writeln!(
+                f,
+                "Would book from {} to {} on {} at {}",
+                trip.from, trip.to, trip.date, trip.departure
+            )?;
+        }
+        writeln!(f, "Passengers:")?;
+        for passenger in &self.passengers {
+            writeln!(f, "- {}", passenger)?;
+        }
+        write!(
+            f,
+            "Form data:\n{}",
+            self.fields
+                .iter()
+                .map(|(name, value)| format!("{} = {}", name, value))
+                .collect::<Vec<_>>()
+                .join("\n")
+        )
+    }
+}
+
+/// Details read from the page shown after "Conferma acquisto".
+#[derive(Debug, Clone, PartialEq)]
+pub struct Confirmation {
+    pub code: Option<String>,
+    pub price: Option<f64>,
+    pub status: String,
+}
+
+/// Parses the confirmation page, failing when it does not say that the purchase
+/// went through.
+///
+/// Details are read as label/value pairs, either "Label: value" on one line or a
+/// label followed by its value, as in `<dt>`/`<dd>` lists and tables.
+pub fn parse_confirmation(html: &str) -> Result<Confirmation, Error> {
+    let document = Html::parse_document(html);
+    let body_selector = Selector::parse("body").unwrap();
+    let body = document
+        .select(&body_selector)
+        .next()
+        .unwrap_or(document.root_element());
+    let lines: Vec<String> = body
+        .text()
+        .map(|text| text.split_whitespace().collect::<Vec<_>>().join(" "))
+        .filter(|text| !text.is_empty())
+        .collect();
+
+    let Some(marker) = lines.iter().find(|line| {
+        let line = line.to_lowercase();
+        CONFIRMATION_MARKERS
+            .iter()
+            .any(|marker| line.contains(marker))
+    }) else {
+        return Err(Error::msg(
+            "The page shown after \"Conferma acquisto\" does not confirm the purchase",
+        ));
+    };
+
+    let value_of = |labels: &[&str]| {
+        lines.iter().enumerate().find_map(|(index, line)| {
+            let (label, value) = match line.split_once(':') {
+                Some((label, value)) if !value.trim().is_empty() => (label, Some(value.trim())),
+                Some((label, _)) => (label, None),
+                None => (line.as_str(), None),
+            };
+            let label = label.trim().to_lowercase();
+            if !labels.iter().any(|wanted| label == *wanted) {
+                return None;
+            }
+            value
+                .or_else(|| lines.get(index + 1).map(String::as_str))
+                .map(str::to_string)
+        })
+    };
+
+    let price_regex = Regex::new(r"(\d+(?:[.,]\d{1,2})?)").unwrap();
+    let price = value_of(&["totale", "importo", "prezzo", "totale pagato"]).and_then(|value| {
+        price_regex
+            .captures(&value)
+            .and_then(|captures| captures[1].replace(',', ".").parse().ok())
+    });
+
+    Ok(Confirmation {
+        code: value_of(&[
+            "codice prenotazione",
+            "codice biglietto",
+            "codice acquisto",
+            "numero prenotazione",
+            "codice",
+        ]),
+        price,
+        status: value_of(&["stato", "stato prenotazione"]).unwrap_or_else(|| marker.clone()),
+    })
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StoredReceipt {
+    pub username: String,
+    pub booked_at: String,
+    pub receipt: BookingReceipt,
+}
+
+/// Receipts of every booking made, saved to a JSON file as each one is added.
+///
+/// Bookings falling due at the same time finish together, so the receipts are
+/// added one at a time and never written over one another.
+pub struct ReceiptStore {
+    path: PathBuf,
+    receipts: Mutex<Vec<StoredReceipt>>,
+}
+
+impl ReceiptStore {
+    /// Loads the receipts saved at `path`, starting empty when there is no such file.
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self, Error> {
+        let path = path.into();
+        let receipts = match fs::read_to_string(&path) {
+            Ok(contents) if !contents.trim().is_empty() => serde_json::from_str(&contents)?,
+            _ => Vec::new(),
+        };
+        Ok(Self {
+            path,
+            receipts: Mutex::new(receipts),
+        })
+    }
+
+    /// Appends `receipt` to the receipts of `username`.
+    pub fn add(&self, username: &str, receipt: &BookingReceipt) -> Result<(), Error> {
+        let mut receipts = self.receipts.lock().unwrap();
+        receipts.push(StoredReceipt {
+            username: username.to_string(),
+            booked_at: Local::now().to_rfc3339(),
+            receipt: receipt.clone(),
+        });
+        save(&self.path, &receipts)
+    }
+
+    /// Every receipt, oldest first.
+    pub fn list(&self) -> Vec<StoredReceipt> {
+        self.receipts.lock().unwrap().clone()
+    }
+}
@@ -0,0 +1,221 @@
+use std::{
+    fmt::{Display, Formatter},
+    fs,
+    path::PathBuf,
+    sync::Mutex,
+};
+
+use chrono::{DateTime, Datelike, Days, NaiveDate, Utc, Weekday};
+use color_eyre::eyre::Error;
+use serde::{Deserialize, Serialize};
+
+use crate::utils::backend::SearchQuery;
+use crate::utils::departures::DepartureFilter;
+use crate::utils::jobs::save;
+
+/// Parses weekdays such as `mon,wed`, `mon-fri` or `tuesday`, in week order.
+pub fn parse_weekdays(s: &str) -> Result<Vec<Weekday>, Error> {
+    let parse = |day: &str| {
+        day.trim()
+            .parse::<Weekday>()
+            .map_err(|_| Error::msg(format!("Unknown weekday: {}", day)))
+    };
+
+    let mut weekdays = Vec::new();
+    for part in s.split(',').filter(|part| !part.trim().is_empty()) {
+        match part.split_once('-') {
+            Some((first, last)) => {
+                let (mut day, last) = (parse(first)?, parse(last)?);
+                weekdays.push(day);
+                while day != last {
+                    day = day.succ();
+                    weekdays.push(day);
+                }
+            }
+            None => weekdays.push(parse(part)?),
+        }
+    }
+    if weekdays.is_empty() {
+        return Err(Error::msg("No weekdays given"));
+    }
+    weekdays.sort_by_key(|day| day.num_days_from_monday());
+    weekdays.dedup();
+    Ok(weekdays)
+}
+
+/// A route booked every week on the same weekdays.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RecurringRule {
+    pub id: u64,
+    pub chat_id: i64,
+    /// Registered user the tickets are booked for.
+    pub username: String,
+    pub from_id: u32,
+    pub to_id: u32,
+    /// Names of the stops, as `from → to`.
+    pub route: String,
+    pub weekdays: Vec<Weekday>,
+    /// Run to book, as `HH:MM` or `HH:MM-HH:MM`.
+    pub departure: Option<String>,
+    /// Last travel date to book, if the rule ends.
+    pub until: Option<NaiveDate>,
+    pub paused: bool,
+    /// Last travel date a booking was scheduled for.
+    pub scheduled_until: Option<NaiveDate>,
+    pub created: DateTime<Utc>,
+}
+
+impl RecurringRule {
+    pub fn new(
+        chat_id: i64,
+        username: &str,
+        route: String,
+        (from_id, to_id): (u32, u32),
+        weekdays: Vec<Weekday>,
+    ) -> Self {
+        Self {
+            id: 0,
+            chat_id,
+            username: username.to_string(),
+            from_id,
+            to_id,
+            route,
+            weekdays,
+            departure: None,
+            until: None,
+            paused: false,
+            scheduled_until: None,
+            created: Utc::now(),
+        }
+    }
+
+    pub fn with_departure(mut self, departure: Option<DepartureFilter>) -> Self {
+        self.departure = departure.map(|departure| departure.to_string());
+        self
+    }
+
+    pub fn with_until(mut self, until: Option<NaiveDate>) -> Self {
+        self.until = until;
+        self
+    }
+
+    /// Travel dates from `first` to `last` still to be booked, none while paused.
+    pub fn dates(&self, first: NaiveDate, last: NaiveDate) -> Vec<NaiveDate> {
+        if self.paused {
+            return Vec::new();
+        }
+        let first = match self.scheduled_until {
+            Some(scheduled) => first.max(scheduled + Days::new(1)),
+            None => first,
+        };
+        let last = self.until.map_or(last, |until| last.min(until));
+        first
+            .iter_days()
+            .take_while(|date| *date <= last)
+            .filter(|date| self.weekdays.contains(&date.weekday()))
+            .collect()
+    }
+
+    /// The search booking the trip on `date`.
+    pub fn query(&self, date: NaiveDate) -> Result<SearchQuery, Error> {
+        let departure = self
+            .departure
+            .as_deref()
+            .map(DepartureFilter::parse)
+            .transpose()?;
+        Ok(SearchQuery::new(
+            self.from_id,
+            self.to_id,
+            date.format("%Y-%m-%d").to_string(),
+        )
+        .with_departure(departure))
+    }
+}
+
+impl Display for RecurringRule {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        let weekdays = self
+            .weekdays
+            .iter()
+            .map(|day| day.to_string())
+            .collect::<Vec<String>>()
+            .join(", ");
+        write!(f, "#{} {} every {}", self.id, self.route, weekdays)?;
+        if let Some(departure) = &self.departure {
+            write!(f, " at {}", departure)?;
+        }
+        if let Some(until) = self.until {
+            write!(f, " until {}", until)?;
+        }
+        if self.paused {
+            write!(f, " (paused)")?;
+        }
+        Ok(())
+    }
+}
+
+/// Recurring booking rules saved to a JSON file on every change.
+pub struct RecurringStore {
+    path: PathBuf,
+    rules: Mutex<Vec<RecurringRule>>,
+}
+
+impl RecurringStore {
+    /// Loads the rules saved at `path`, starting empty when there is no such file.
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self, Error> {
+        let path = path.into();
+        let rules = match fs::read_to_string(&path) {
+            Ok(contents) if !contents.trim().is_empty() => serde_json::from_str(&contents)?,
+            _ => Vec::new(),
+        };
+        Ok(Self {
+            path,
+            rules: Mutex::new(rules),
+        })
+    }
+
+    /// Saves `rule` under a new ID and returns it as stored.
+    pub fn add(&self, mut rule: RecurringRule) -> Result<RecurringRule, Error> {
+        let mut rules = self.rules.lock().unwrap();
+        rule.id = rules.iter().map(|rule| rule.id).max().unwrap_or(0) + 1;
+        rules.push(rule.clone());
+        save(&self.path, &rules)?;
+        Ok(rule)
+    }
+
+    /// Every rule, oldest first.
+    pub fn list(&self) -> Vec<RecurringRule> {
+        self.rules.lock().unwrap().clone()
+    }
+
+    /// Pauses or resumes rule `id` of `chat_id`, returning it if there is one.
+    pub fn set_paused(
+        &self,
+        chat_id: i64,
+        id: u64,
+        paused: bool,
+    ) -> Result<Option<RecurringRule>, Error> {
+        let mut rules = self.rules.lock().unwrap();
+        let Some(rule) = rules
+            .iter_mut()
+            .find(|rule| rule.id == id && rule.chat_id == chat_id)
+        else {
+            return Ok(None);
+        };
+        rule.paused = paused;
+        let rule = rule.clone();
+        save(&self.path, &rules)?;
+        Ok(Some(rule))
+    }
+
+    /// Notes that rule `id` has its bookings scheduled up to `date`.
+    pub fn mark_scheduled(&self, id: u64, date: NaiveDate) -> Result<(), Error> {
+        let mut rules = self.rules.lock().unwrap();
+        let rule = rules
+            .iter_mut()
+            .find(|rule| rule.id == id)
+            .ok_or_else(|| Error::msg(format!("No recurring booking #{}", id)))?;
+        rule.scheduled_until = Some(date);
+        save(&self.path, &rules)
+    }
+}
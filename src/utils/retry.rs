@@ -0,0 +1,198 @@
+use std::{
+    fmt::{Display, Formatter},
+    future::Future,
+    time::{Duration, Instant},
+};
+
+use color_eyre::eyre::Error;
+
+use crate::utils::booking_error::BookingError;
+use crate::utils::diagnostics::FailureKind;
+
+/// How often and for how long a failed booking is tried again.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// Attempts in total, the first one included.
+    pub max_attempts: u32,
+    /// Wait before the second attempt, doubled after every further failure.
+    pub backoff: Duration,
+    pub max_backoff: Duration,
+    /// No attempt is started once this much time has passed since the first one.
+    pub deadline: Duration,
+    /// Failures worth another attempt.
+    pub retry_on: Vec<FailureKind>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            backoff: Duration::from_secs(5),
+            max_backoff: Duration::from_secs(60),
+            deadline: Duration::from_secs(5 * 60),
+            retry_on: vec![FailureKind::SiteChanged, FailureKind::Unknown],
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Wait before the attempt following the failed attempt number `attempt`.
+    pub fn delay_after(&self, attempt: u32) -> Duration {
+        self.backoff
+            .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+            .min(self.max_backoff)
+    }
+
+    /// Whether a booking that failed with `error` may be tried again.
+    ///
+    /// A failure after "Conferma acquisto" is never retried, as the purchase may
+    /// have gone through, and neither is a route no line serves. Other errors
+    /// outside the booking steps, such as a WebDriver that cannot be reached,
+    /// count as unknown.
+    pub fn is_retryable(&self, error: &Error) -> bool {
+        let Some(booking_error) = error.downcast_ref::<BookingError>() else {
+            return self.retry_on.contains(&FailureKind::Unknown);
+        };
+        match booking_error {
+            BookingError::NoPassengers
+            | BookingError::InvalidRoute { .. }
+            | BookingError::ConfirmationMissing(_) => false,
+            _ => match booking_error.failure() {
+                Some(failure) if failure.step == "confirm" => false,
+                Some(failure) => self.retry_on.contains(&failure.kind),
+                None => self.retry_on.contains(&FailureKind::Unknown),
+            },
+        }
+    }
+}
+
+/// Sent between attempts, to tell the user what failed and what happens next.
+#[derive(Debug, Clone)]
+pub struct RetryNotice {
+    pub attempt: u32,
+    pub max_attempts: u32,
+    pub error: String,
+    pub delay: Duration,
+}
+
+impl Display for RetryNotice {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "⚠️ Attempt {}/{} failed: {}\nTrying again in {}s...",
+            self.attempt,
+            self.max_attempts,
+            self.error,
+            self.delay.as_secs()
+        )
+    }
+}
+
+/// Every attempt of a booking that was given up on, wrapped around its last error.
+#[derive(Debug)]
+pub struct RetrySummary {
+    pub errors: Vec<String>,
+    pub elapsed: Duration,
+    /// Why no further attempt was made.
+    pub reason: &'static str,
+}
+
+impl Display for RetrySummary {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "Gave up after {} attempts in {}s ({}):",
+            self.errors.len(),
+            self.elapsed.as_secs(),
+            self.reason
+        )?;
+        for (index, error) in self.errors.iter().enumerate() {
+            write!(f, "\n{}. {}", index + 1, error)?;
+        }
+        Ok(())
+    }
+}
+
+/// Runs `attempt` until it succeeds or `policy` gives up, calling `notify` before
+/// waiting for the next attempt.
+///
+/// A failure on the first attempt that is not retried is returned as is; after
+/// several attempts the last error is wrapped in a [`RetrySummary`].
+pub async fn with_retry<T, A, AFut, N, NFut>(
+    policy: &RetryPolicy,
+    attempt: A,
+    notify: N,
+) -> Result<T, Error>
+where
+    A: FnMut() -> AFut,
+    AFut: Future<Output = Result<T, Error>>,
+    N: FnMut(RetryNotice) -> NFut,
+    NFut: Future<Output = ()>,
+{
+    with_retry_from(policy, Instant::now(), attempt, notify).await
+}
+
+/// Retries like [`with_retry`], counting the deadline of `policy` from `started`,
+/// which may be ahead when the first attempt waits for booking to open.
+pub async fn with_retry_from<T, A, AFut, N, NFut>(
+    policy: &RetryPolicy,
+    started: Instant,
+    mut attempt: A,
+    mut notify: N,
+) -> Result<T, Error>
+where
+    A: FnMut() -> AFut,
+    AFut: Future<Output = Result<T, Error>>,
+    N: FnMut(RetryNotice) -> NFut,
+    NFut: Future<Output = ()>,
+{
+    let mut errors = Vec::new();
+    loop {
+        let error = match attempt().await {
+            Ok(value) => {
+                if !errors.is_empty() {
+                    println!("Booked after {} attempts", errors.len() + 1);
+                }
+                return Ok(value);
+            }
+            Err(e) => e,
+        };
+        errors.push(error.to_string());
+        let attempts = errors.len() as u32;
+        let delay = policy.delay_after(attempts);
+
+        let reason = if !policy.is_retryable(&error) {
+            "not retryable"
+        } else if attempts >= policy.max_attempts {
+            "no attempts left"
+        } else if started.elapsed() + delay >= policy.deadline {
+            "deadline reached"
+        } else {
+            println!(
+                "Attempt {}/{} failed, retrying in {}s: {}",
+                attempts,
+                policy.max_attempts,
+                delay.as_secs(),
+                error
+            );
+            notify(RetryNotice {
+                attempt: attempts,
+                max_attempts: policy.max_attempts,
+                error: error.to_string(),
+                delay,
+            })
+            .await;
+            tokio::time::sleep(delay).await;
+            continue;
+        };
+
+        if attempts == 1 {
+            return Err(error);
+        }
+        return Err(error.wrap_err(RetrySummary {
+            errors,
+            elapsed: started.elapsed(),
+            reason,
+        }));
+    }
+}
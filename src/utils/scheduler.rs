@@ -0,0 +1,101 @@
+use std::{collections::BTreeMap, sync::Arc, sync::Mutex, time::Duration};
+
+use chrono::{DateTime, Utc};
+use futures::future::BoxFuture;
+use tokio::sync::Notify;
+
+/// Longest single sleep, so that changes to the system clock are noticed.
+const MAX_SLEEP: Duration = Duration::from_secs(60);
+
+type Runner<T> = Box<dyn Fn(T) -> BoxFuture<'static, ()> + Send + Sync>;
+
+/// Runs jobs at their due time on tokio timers.
+///
+/// Pending jobs wait in a queue watched by a single task, which sleeps until the
+/// earliest one is due and then runs it in a task of its own, so that waiting jobs
+/// cost neither a thread nor a task each.
+pub struct Scheduler<T> {
+    queue: Mutex<BTreeMap<(DateTime<Utc>, u64), T>>,
+    changed: Notify,
+    run: Runner<T>,
+}
+
+impl<T: Send + 'static> Scheduler<T> {
+    /// Starts the task running every job with `run`.
+    pub fn start<F>(run: F) -> Arc<Self>
+    where
+        F: Fn(T) -> BoxFuture<'static, ()> + Send + Sync + 'static,
+    {
+        let scheduler = Arc::new(Self {
+            queue: Mutex::new(BTreeMap::new()),
+            changed: Notify::new(),
+            run: Box::new(run),
+        });
+        tokio::spawn(scheduler.clone().dispatch());
+        scheduler
+    }
+
+    /// Queues job `id` to run at `due`, at once when that is already past.
+    pub fn schedule(&self, id: u64, due: DateTime<Utc>, job: T) {
+        self.queue.lock().unwrap().insert((due, id), job);
+        self.changed.notify_one();
+    }
+
+    /// Takes job `id` off the queue, returning it if it had not run yet.
+    pub fn cancel(&self, id: u64) -> Option<T> {
+        let mut queue = self.queue.lock().unwrap();
+        let key = *queue.keys().find(|(_, queued)| *queued == id)?;
+        let job = queue.remove(&key);
+        self.changed.notify_one();
+        job
+    }
+
+    /// When job `id` is due, if it is queued.
+    pub fn due(&self, id: u64) -> Option<DateTime<Utc>> {
+        self.queue
+            .lock()
+            .unwrap()
+            .keys()
+            .find(|(_, queued)| *queued == id)
+            .map(|(due, _)| *due)
+    }
+
+    /// Number of jobs waiting to run.
+    pub fn pending(&self) -> usize {
+        self.queue.lock().unwrap().len()
+    }
+
+    async fn dispatch(self: Arc<Self>) {
+        loop {
+            let next = self
+                .queue
+                .lock()
+                .unwrap()
+                .keys()
+                .next()
+                .map(|(due, _)| *due);
+            let Some(next) = next else {
+                self.changed.notified().await;
+                continue;
+            };
+
+            let wait = (next - Utc::now()).to_std().unwrap_or_default();
+            if !wait.is_zero() {
+                tokio::select! {
+                    _ = tokio::time::sleep(wait.min(MAX_SLEEP)) => {}
+                    _ = self.changed.notified() => {}
+                }
+                continue;
+            }
+
+            let due = {
+                let mut queue = self.queue.lock().unwrap();
+                let later = queue.split_off(&(Utc::now(), u64::MAX));
+                std::mem::replace(&mut *queue, later)
+            };
+            for job in due.into_values() {
+                tokio::spawn((self.run)(job));
+            }
+        }
+    }
+}
@@ -0,0 +1,192 @@
+use std::{
+    collections::HashSet,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use chrono::{DateTime, Local};
+use color_eyre::eyre::Error;
+use reqwest::Client;
+use serde_json::Value;
+use thirtyfour::WebDriver;
+
+/// How long a session may stay open before the reaper closes it.
+pub const DEFAULT_SESSION_MAX_AGE: Duration = Duration::from_secs(15 * 60);
+
+struct TrackedSession {
+    id: u64,
+    backend: &'static str,
+    opened_at: Instant,
+    started: DateTime<Local>,
+    /// Handle to quit the browser session with, for WebDriver sessions.
+    driver: Option<WebDriver>,
+}
+
+/// An open session as listed by `/sessions`.
+#[derive(Debug, Clone)]
+pub struct SessionInfo {
+    pub id: u64,
+    pub backend: &'static str,
+    /// Session ID on the WebDriver server.
+    pub webdriver_id: Option<String>,
+    pub started: DateTime<Local>,
+    pub age: Duration,
+}
+
+/// Booking sessions currently open, so that none outlives its booking for long.
+#[derive(Default)]
+pub struct SessionRegistry {
+    next_id: AtomicU64,
+    sessions: Mutex<Vec<TrackedSession>>,
+}
+
+impl SessionRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Tracks a new session until the returned lease is dropped.
+    pub fn register(
+        self: &Arc<Self>,
+        backend: &'static str,
+        driver: Option<WebDriver>,
+    ) -> SessionLease {
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst) + 1;
+        self.sessions.lock().unwrap().push(TrackedSession {
+            id,
+            backend,
+            opened_at: Instant::now(),
+            started: Local::now(),
+            driver,
+        });
+        SessionLease {
+            id,
+            registry: self.clone(),
+        }
+    }
+
+    /// Open sessions, oldest first.
+    pub fn list(&self) -> Vec<SessionInfo> {
+        self.sessions
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|session| SessionInfo {
+                id: session.id,
+                backend: session.backend,
+                webdriver_id: session
+                    .driver
+                    .as_ref()
+                    .map(|driver| driver.session_id().to_string()),
+                started: session.started,
+                age: session.opened_at.elapsed(),
+            })
+            .collect()
+    }
+
+    /// Quits the sessions open for longer than `max_age`, returning how many.
+    ///
+    /// The booking still holding a reaped session fails at its next step.
+    pub async fn reap(&self, max_age: Duration) -> usize {
+        let expired: Vec<TrackedSession> = {
+            let mut sessions = self.sessions.lock().unwrap();
+            let (expired, open) = sessions
+                .drain(..)
+                .partition(|session| session.opened_at.elapsed() >= max_age);
+            *sessions = open;
+            expired
+        };
+
+        for session in &expired {
+            println!(
+                "Closing {} session #{} open for {}s",
+                session.backend,
+                session.id,
+                session.opened_at.elapsed().as_secs()
+            );
+            if let Some(driver) = session.driver.clone()
+                && let Err(e) = driver.quit().await
+            {
+                println!("Failed to quit session #{}: {}", session.id, e);
+            }
+        }
+        expired.len()
+    }
+
+    /// Closes the sessions the WebDriver server at `url` reports that are not
+    /// tracked here, such as those a crashed bot left open, returning how many.
+    ///
+    /// Sessions are listed through `/sessions` (chromedriver) and the nodes of
+    /// `/status` (Selenium Grid); servers listing neither are left alone.
+    pub async fn close_orphans(&self, url: &str) -> Result<usize, Error> {
+        let url = url.trim_end_matches('/');
+        let client = Client::builder().timeout(Duration::from_secs(10)).build()?;
+        let get = |path: &str| {
+            let request = client.get(format!("{}/{}", url, path));
+            async move { request.send().await.ok()?.json::<Value>().await.ok() }
+        };
+
+        let mut reported = Vec::new();
+        if let Some(sessions) = get("sessions").await {
+            reported.extend(
+                sessions["value"]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|session| session["id"].as_str().map(str::to_string)),
+            );
+        }
+        if let Some(status) = get("status").await {
+            reported.extend(
+                status["value"]["nodes"]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .flat_map(|node| node["slots"].as_array().into_iter().flatten())
+                    .filter_map(|slot| slot["session"]["sessionId"].as_str().map(str::to_string)),
+            );
+        }
+
+        let tracked: HashSet<String> = self
+            .list()
+            .into_iter()
+            .filter_map(|session| session.webdriver_id)
+            .collect();
+        let mut closed = 0;
+        for id in reported.iter().filter(|id| !tracked.contains(*id)) {
+            println!("Closing WebDriver session {} left by a previous run", id);
+            let response = client
+                .delete(format!("{}/session/{}", url, id))
+                .send()
+                .await
+                .and_then(|response| response.error_for_status());
+            match response {
+                Ok(_) => closed += 1,
+                Err(e) => println!("Failed to close WebDriver session {}: {}", id, e),
+            }
+        }
+        Ok(closed)
+    }
+
+    fn remove(&self, id: u64) {
+        self.sessions
+            .lock()
+            .unwrap()
+            .retain(|session| session.id != id);
+    }
+}
+
+/// Keeps a session listed in its [`SessionRegistry`] for as long as it is held.
+pub struct SessionLease {
+    id: u64,
+    registry: Arc<SessionRegistry>,
+}
+
+impl Drop for SessionLease {
+    fn drop(&mut self) {
+        self.registry.remove(self.id);
+    }
+}
@@ -3,11 +3,11 @@ use std::collections::HashMap;
 use teloxide::{
     Bot,
     prelude::{OnError, Request, Requester},
-    types::{InputFile, Message},
+    types::{ChatId, InputFile},
 };
 
-pub async fn send_cached_sticker(bot: Bot, msg: Message, sticker_id: String) {
-    bot.send_sticker(msg.chat.id, InputFile::file_id(sticker_id))
+pub async fn send_cached_sticker(bot: Bot, chat_id: ChatId, sticker_id: String) {
+    bot.send_sticker(chat_id, InputFile::file_id(sticker_id))
         .send()
         .await
         .log_on_error()
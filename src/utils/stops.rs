@@ -0,0 +1,253 @@
+use std::{
+    fs,
+    path::PathBuf,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use color_eyre::eyre::Error;
+use serde::{Deserialize, Serialize};
+
+use crate::utils::booking::get_cities;
+
+/// How long the departure stops are kept before being fetched again.
+pub const DEFAULT_STOPS_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+#[derive(Serialize, Deserialize)]
+struct Stop {
+    name: String,
+    id: u32,
+}
+
+struct CachedStops {
+    fetched_at: Instant,
+    stops: Vec<(String, u32)>,
+}
+
+/// Departure stops of the Contram website, cached in memory for a TTL.
+///
+/// Every successful fetch is saved to the snapshot file, which is used when the
+/// site cannot be reached and nothing fresher is in memory.
+pub struct StopCatalogue {
+    base_url: String,
+    ttl: Duration,
+    snapshot_path: Option<PathBuf>,
+    cache: Mutex<Option<CachedStops>>,
+}
+
+impl StopCatalogue {
+    pub fn new(base_url: &str) -> Self {
+        Self {
+            base_url: base_url.to_string(),
+            ttl: DEFAULT_STOPS_TTL,
+            snapshot_path: None,
+            cache: Mutex::new(None),
+        }
+    }
+
+    pub fn with_ttl(self, ttl: Duration) -> Self {
+        Self { ttl, ..self }
+    }
+
+    pub fn with_snapshot(self, path: impl Into<PathBuf>) -> Self {
+        Self {
+            snapshot_path: Some(path.into()),
+            ..self
+        }
+    }
+
+    /// Returns the stops as `(name, id)` pairs sorted by ID, fetching them again
+    /// once the cached ones are older than the TTL.
+    pub async fn cities(&self) -> Result<Vec<(String, u32)>, Error> {
+        let cached = self
+            .cache
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|cached| (cached.fetched_at.elapsed() < self.ttl, cached.stops.clone()));
+        if let Some((true, stops)) = cached {
+            return Ok(stops);
+        }
+
+        match self.refresh().await {
+            Ok(stops) => Ok(stops),
+            Err(e) => {
+                if let Some((_, stops)) = cached {
+                    println!("Error fetching cities, using cached cities: {}", e);
+                    return Ok(stops);
+                }
+                match self.load_snapshot() {
+                    Some(stops) => {
+                        println!("Error fetching cities, using saved cities: {}", e);
+                        Ok(stops)
+                    }
+                    None => Err(e),
+                }
+            }
+        }
+    }
+
+    /// Fetches the stops from the site, replacing the cached ones and the snapshot.
+    pub async fn refresh(&self) -> Result<Vec<(String, u32)>, Error> {
+        let stops = get_cities(&self.base_url).await?;
+        if stops.is_empty() {
+            return Err(Error::msg("The site returned no stops"));
+        }
+
+        *self.cache.lock().unwrap() = Some(CachedStops {
+            fetched_at: Instant::now(),
+            stops: stops.clone(),
+        });
+        if let Err(e) = self.save_snapshot(&stops) {
+            println!("Failed to save cities: {}", e);
+        }
+        Ok(stops)
+    }
+
+    fn load_snapshot(&self) -> Option<Vec<(String, u32)>> {
+        let contents = fs::read_to_string(self.snapshot_path.as_ref()?).ok()?;
+        let snapshot: Vec<Stop> = serde_json::from_str(&contents).ok()?;
+        let mut stops: Vec<(String, u32)> = snapshot
+            .into_iter()
+            .map(|stop| (stop.name, stop.id))
+            .collect();
+        stops.sort_by_key(|&(_, id)| id);
+        Some(stops)
+    }
+
+    fn save_snapshot(&self, stops: &[(String, u32)]) -> Result<(), Error> {
+        let Some(path) = &self.snapshot_path else {
+            return Ok(());
+        };
+        let snapshot: Vec<Stop> = stops
+            .iter()
+            .map(|(name, id)| Stop {
+                name: name.clone(),
+                id: *id,
+            })
+            .collect();
+        fs::write(path, serde_json::to_string_pretty(&snapshot)?)?;
+        Ok(())
+    }
+}
+
+/// Outcome of looking up a stop by ID or name.
+#[derive(Debug, Clone, PartialEq)]
+pub enum StopMatch {
+    Found(String, u32),
+    /// Several stops fit the name equally well.
+    Ambiguous(Vec<(String, u32)>),
+    NotFound,
+}
+
+/// Lowercases `name`, strips accents and turns any run of separators into a single
+/// space, so that "Sant'Elpidio" and "sant-elpidio" compare equal.
+pub fn normalize_stop_name(name: &str) -> String {
+    name.to_lowercase()
+        .chars()
+        .map(|c| match c {
+            'à' | 'á' | 'â' | 'ä' | 'ã' | 'å' => 'a',
+            'è' | 'é' | 'ê' | 'ë' => 'e',
+            'ì' | 'í' | 'î' | 'ï' => 'i',
+            'ò' | 'ó' | 'ô' | 'ö' | 'õ' => 'o',
+            'ù' | 'ú' | 'û' | 'ü' => 'u',
+            'ç' => 'c',
+            'ñ' => 'n',
+            c if c.is_alphanumeric() => c,
+            _ => ' ',
+        })
+        .collect::<String>()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Looks up a stop in `stops` by ID or by name.
+///
+/// Names are tried in order as an exact match, as word prefixes ("anc staz" for
+/// "Ancona Stazione F.S.") and as a close spelling of the name or of its first
+/// words, allowing about one typo every four letters.
+pub fn find_stop(stops: &[(String, u32)], query: &str) -> StopMatch {
+    let found = |(name, id): &(String, u32)| StopMatch::Found(name.clone(), *id);
+    let pick = |candidates: Vec<&(String, u32)>| match candidates[..] {
+        [] => StopMatch::NotFound,
+        [stop] => found(stop),
+        _ => StopMatch::Ambiguous(candidates.into_iter().cloned().collect()),
+    };
+
+    if let Ok(id) = query.trim().parse::<u32>() {
+        return pick(
+            stops
+                .iter()
+                .filter(|&&(_, stop_id)| stop_id == id)
+                .collect(),
+        );
+    }
+
+    let query = normalize_stop_name(query);
+    if query.is_empty() {
+        return StopMatch::NotFound;
+    }
+    let names: Vec<String> = stops
+        .iter()
+        .map(|(name, _)| normalize_stop_name(name))
+        .collect();
+
+    if let Some(index) = names.iter().position(|name| *name == query) {
+        return found(&stops[index]);
+    }
+
+    let query_words: Vec<&str> = query.split(' ').collect();
+    let prefixed: Vec<&(String, u32)> = stops
+        .iter()
+        .zip(&names)
+        .filter(|(_, name)| {
+            query_words
+                .iter()
+                .all(|word| name.split(' ').any(|name_word| name_word.starts_with(word)))
+        })
+        .map(|(stop, _)| stop)
+        .collect();
+    if !prefixed.is_empty() {
+        return pick(prefixed);
+    }
+
+    let max_distance = (query.chars().count() / 4).max(1);
+    let distances: Vec<usize> = names
+        .iter()
+        .map(|name| {
+            let leading_words = name
+                .split(' ')
+                .take(query_words.len())
+                .collect::<Vec<_>>()
+                .join(" ");
+            edit_distance(&query, name).min(edit_distance(&query, &leading_words))
+        })
+        .collect();
+    match distances.iter().min() {
+        Some(&best) if best <= max_distance => pick(
+            stops
+                .iter()
+                .zip(&distances)
+                .filter(|&(_, &distance)| distance == best)
+                .map(|(stop, _)| stop)
+                .collect(),
+        ),
+        _ => StopMatch::NotFound,
+    }
+}
+
+/// Levenshtein distance between two strings, counted in characters.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, a_char) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a_char != *b_char);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
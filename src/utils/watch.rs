@@ -0,0 +1,185 @@
+use std::{
+    fmt::{Display, Formatter},
+    str::FromStr,
+    sync::{
+        Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+
+use chrono::{DateTime, Local};
+use color_eyre::eyre::Error;
+use tokio::{task::AbortHandle, time::Instant};
+
+use crate::utils::backend::{BookingBackend, SearchQuery, fetch_departures};
+use crate::utils::departures::{Departure, open_departure};
+
+/// How often a watch searches and how far apart the searches of all watches are.
+#[derive(Debug, Clone)]
+pub struct WatchConfig {
+    pub interval: Duration,
+    /// Shortest time between two searches, whichever watch runs them.
+    pub rate_limit: Duration,
+}
+
+impl Default for WatchConfig {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(120),
+            rate_limit: Duration::from_secs(10),
+        }
+    }
+}
+
+/// What to do once a seat frees up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchAction {
+    Book,
+    Notify,
+}
+
+impl FromStr for WatchAction {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "book" => Ok(Self::Book),
+            "notify" => Ok(Self::Notify),
+            _ => Err(Error::msg(format!("Unknown watch action: {}", s))),
+        }
+    }
+}
+
+impl Display for WatchAction {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        let name = match self {
+            Self::Book => "book",
+            Self::Notify => "notify",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// A run someone is waiting for a seat on.
+#[derive(Debug, Clone)]
+pub struct Watch {
+    pub id: u64,
+    pub chat_id: i64,
+    pub username: String,
+    pub query: SearchQuery,
+    /// Names of the stops, as `from → to`.
+    pub route: String,
+    pub action: WatchAction,
+    pub started: DateTime<Local>,
+}
+
+impl Display for Watch {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        write!(f, "#{} {} on {}", self.id, self.route, self.query.date)?;
+        if let Some(departure) = &self.query.departure {
+            write!(f, " at {}", departure)?;
+        }
+        write!(
+            f,
+            " ({}, since {})",
+            self.action,
+            self.started.format("%Y-%m-%d %H:%M")
+        )
+    }
+}
+
+/// Spaces out searches so that the site is not hammered by many watches at once.
+pub struct RateLimiter {
+    gap: Duration,
+    next: tokio::sync::Mutex<Instant>,
+}
+
+impl RateLimiter {
+    pub fn new(gap: Duration) -> Self {
+        Self {
+            gap,
+            next: tokio::sync::Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Waits for the turn of the caller, at least `gap` after the previous one.
+    pub async fn wait(&self) {
+        let mut next = self.next.lock().await;
+        tokio::time::sleep_until(*next).await;
+        *next = Instant::now() + self.gap;
+    }
+}
+
+/// Watches in progress, each polled by its own task.
+pub struct WatchList {
+    config: WatchConfig,
+    limiter: RateLimiter,
+    next_id: AtomicU64,
+    watches: Mutex<Vec<(Watch, AbortHandle)>>,
+}
+
+impl WatchList {
+    pub fn new(config: WatchConfig) -> Self {
+        Self {
+            limiter: RateLimiter::new(config.rate_limit),
+            config,
+            next_id: AtomicU64::new(0),
+            watches: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Reserves the ID of a new watch.
+    pub fn next_id(&self) -> u64 {
+        self.next_id.fetch_add(1, Ordering::SeqCst) + 1
+    }
+
+    /// Lists `watch` until its task ends or the watch is cancelled.
+    pub fn insert(&self, watch: Watch, task: AbortHandle) {
+        self.watches.lock().unwrap().push((watch, task));
+    }
+
+    /// Watches of `chat_id` still running, oldest first.
+    pub fn list(&self, chat_id: i64) -> Vec<Watch> {
+        let mut watches = self.watches.lock().unwrap();
+        watches.retain(|(_, task)| !task.is_finished());
+        watches
+            .iter()
+            .filter(|(watch, _)| watch.chat_id == chat_id)
+            .map(|(watch, _)| watch.clone())
+            .collect()
+    }
+
+    /// Stops watch `id` of `chat_id`, returning it if there was one.
+    pub fn cancel(&self, chat_id: i64, id: u64) -> Option<Watch> {
+        let mut watches = self.watches.lock().unwrap();
+        let position = watches.iter().position(|(watch, task)| {
+            watch.id == id && watch.chat_id == chat_id && !task.is_finished()
+        })?;
+        let (watch, task) = watches.remove(position);
+        task.abort();
+        Some(watch)
+    }
+
+    /// Searches `query` every interval until one of its runs has a free seat.
+    ///
+    /// Failed searches are logged and tried again at the next interval.
+    pub async fn wait_for_seat(
+        &self,
+        backend: &dyn BookingBackend,
+        query: &SearchQuery,
+    ) -> Departure {
+        loop {
+            self.limiter.wait().await;
+            match fetch_departures(backend, query).await {
+                Ok(departures) => {
+                    if let Some(departure) = open_departure(&departures, query.departure.as_ref()) {
+                        return departure.clone();
+                    }
+                }
+                Err(e) => println!("Watch search failed: {}", e),
+            }
+            tokio::time::sleep(self.config.interval).await;
+        }
+    }
+}
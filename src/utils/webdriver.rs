@@ -0,0 +1,115 @@
+use std::{fmt::Display, str::FromStr, sync::Arc, time::Duration};
+
+use color_eyre::eyre::Error;
+use thirtyfour::{
+    common::config::WebDriverConfig, extensions::query::ElementPollerWithTimeout, prelude::*,
+};
+
+/// How often a missing element is looked for again until the element timeout.
+const ELEMENT_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Browser {
+    Firefox,
+    Chrome,
+}
+
+impl FromStr for Browser {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "firefox" => Ok(Self::Firefox),
+            "chrome" | "chromium" => Ok(Self::Chrome),
+            _ => Err(Error::msg(format!("Unknown browser: {}", s))),
+        }
+    }
+}
+
+impl Display for Browser {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Self::Firefox => "firefox",
+            Self::Chrome => "chrome",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Where the WebDriver server is and how the browser it drives is set up.
+#[derive(Debug, Clone)]
+pub struct BrowserConfig {
+    pub url: String,
+    pub browser: Browser,
+    pub headless: bool,
+    /// How long a page may take to load.
+    pub page_load_timeout: Duration,
+    /// How long an element is waited for before a step fails.
+    pub element_timeout: Duration,
+    /// Extra command-line arguments of the browser.
+    pub args: Vec<String>,
+}
+
+impl Default for BrowserConfig {
+    fn default() -> Self {
+        Self {
+            url: "http://localhost:4444".to_string(),
+            browser: Browser::Firefox,
+            headless: true,
+            page_load_timeout: Duration::from_secs(30),
+            element_timeout: Duration::from_secs(10),
+            args: Vec::new(),
+        }
+    }
+}
+
+impl BrowserConfig {
+    /// Capabilities requesting the configured browser from the server.
+    pub fn capabilities(&self) -> Result<Capabilities, Error> {
+        Ok(match self.browser {
+            Browser::Firefox => {
+                let mut caps = DesiredCapabilities::firefox();
+                if self.headless {
+                    caps.set_headless()?;
+                }
+                for arg in &self.args {
+                    caps.add_arg(arg)?;
+                }
+                caps.into()
+            }
+            Browser::Chrome => {
+                let mut caps = DesiredCapabilities::chrome();
+                if self.headless {
+                    caps.set_headless()?;
+                }
+                for arg in &self.args {
+                    caps.add_arg(arg)?;
+                }
+                caps.into()
+            }
+        })
+    }
+
+    /// Starts a browser session on the WebDriver server.
+    pub async fn connect(&self) -> Result<WebDriver, Error> {
+        let config = WebDriverConfig::builder()
+            .poller(Arc::new(ElementPollerWithTimeout::new(
+                self.element_timeout,
+                ELEMENT_POLL_INTERVAL,
+            )))
+            .build()?;
+        let driver = WebDriver::new_with_config(&self.url, self.capabilities()?, config).await?;
+        println!(
+            "Started {} session on {}{}",
+            self.browser,
+            self.url,
+            if self.headless { " (headless)" } else { "" }
+        );
+
+        if let Err(e) = driver.set_page_load_timeout(self.page_load_timeout).await {
+            driver.quit().await?;
+            return Err(e.into());
+        }
+        Ok(driver)
+    }
+}
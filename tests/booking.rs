@@ -0,0 +1,590 @@
+//! End-to-end booking tests against the local Contram stand-in.
+//!
+//! The Selenium tests need a WebDriver server on `localhost:4444` and are ignored
+//! by default; run them with `cargo test -- --ignored`.
+
+mod common;
+
+use std::sync::atomic::Ordering;
+
+use color_eyre::eyre::Error;
+use common::{ContramStub, temp_path, test_passengers, test_user};
+use contram_ticket_automated::User;
+use contram_ticket_automated::user::Passenger;
+use contram_ticket_automated::utils::{
+    backend::{SearchQuery, book_ticket, book_trip, book_when_open, preview_trip, race_trip},
+    booking::{SeleniumBackend, get_cities, get_destinations, validate_city_id},
+    booking_error::BookingError,
+    departures::DepartureFilter,
+    diagnostics::FailureKind,
+    http_booking::HttpBackend,
+    mock_booking::MockBackend,
+    retry::{RetryPolicy, with_retry},
+    webdriver::BrowserConfig,
+};
+
+const DATE: &str = "2026-11-02";
+
+#[tokio::test]
+async fn get_cities_returns_stops_sorted_by_id() {
+    let stub = ContramStub::start().await;
+
+    let cities = get_cities(&stub.base_url).await.unwrap();
+
+    let ids: Vec<u32> = cities.iter().map(|(_, id)| *id).collect();
+    assert_eq!(ids, vec![24, 38, 39, 42, 53]);
+    assert_eq!(validate_city_id(&cities, 24).unwrap(), "Camerino");
+    assert!(validate_city_id(&cities, 99).is_err());
+}
+
+#[tokio::test]
+async fn get_destinations_returns_stops_served_from_departure() {
+    let stub = ContramStub::start().await;
+
+    let destinations = get_destinations(&stub.base_url, 24).await.unwrap();
+
+    let ids: Vec<u32> = destinations.iter().map(|(_, id)| *id).collect();
+    assert_eq!(ids, vec![38, 39]);
+}
+
+#[tokio::test]
+async fn http_backend_books_ticket() {
+    let stub = ContramStub::start().await;
+    let backend = HttpBackend::new(&stub.base_url);
+
+    let receipt = book_ticket(
+        &backend,
+        &test_passengers(),
+        &SearchQuery::new(24, 38, DATE.to_string()),
+    )
+    .await
+    .unwrap();
+
+    assert!(
+        receipt
+            .to_string()
+            .contains("Ticket booked from Camerino to Ancona Piazza Cavour")
+    );
+    assert_eq!(receipt.code.as_deref(), Some("CTR-2026-004517"));
+    assert_eq!(receipt.price, Some(9.5));
+    assert_eq!(receipt.status, "Pagato");
+    assert_eq!(receipt.trips[0].departure, "06:10");
+    assert_eq!(receipt.email, "mario.rossi@studenti.unicam.it");
+
+    let searches = stub.searches();
+    assert_eq!(searches.len(), 1);
+    assert_eq!(searches[0].partenza_id, 24);
+    assert_eq!(searches[0].destinazione_id, 38);
+    assert_eq!(searches[0].data_partenza, DATE);
+
+    let added = stub.submissions("/Home/AggiungiCarrello");
+    assert_eq!(added.len(), 1);
+    assert_eq!(added[0].field("CorsaID"), Some("1001"));
+
+    let checkout = stub.submissions("/Home/Checkout");
+    assert_eq!(checkout.len(), 1);
+    assert_eq!(checkout[0].field("Nominativi[0].Nome"), Some("Mario"));
+    assert_eq!(checkout[0].field("Nominativi[0].Cognome"), Some("Rossi"));
+    assert_eq!(
+        checkout[0].field("EmailAcquirente"),
+        Some("mario.rossi@example.com")
+    );
+    assert_eq!(checkout[0].field("AccettoCondizioni"), Some("true"));
+
+    assert_eq!(stub.submissions("/Home/ConfermaAcquisto").len(), 1);
+}
+
+#[tokio::test]
+async fn http_backend_gives_buyer_email_override() {
+    let stub = ContramStub::start().await;
+    let backend = HttpBackend::new(&stub.base_url);
+    let mut passengers = test_passengers();
+    passengers[0].buyer_email = Some("biglietti@example.com".to_string());
+
+    book_ticket(
+        &backend,
+        &passengers,
+        &SearchQuery::new(24, 38, DATE.to_string()),
+    )
+    .await
+    .unwrap();
+
+    let checkout = stub.submissions("/Home/Checkout");
+    assert_eq!(
+        checkout[0].field("EmailAcquirente"),
+        Some("biglietti@example.com")
+    );
+    assert_eq!(
+        checkout[0].field("Nominativi[0].Email"),
+        Some("mario.rossi@studenti.unicam.it")
+    );
+}
+
+#[tokio::test]
+async fn http_backend_books_both_legs_in_one_cart() {
+    let stub = ContramStub::start().await;
+    let backend = HttpBackend::new(&stub.base_url);
+
+    let receipt = book_trip(
+        &backend,
+        &test_passengers(),
+        &[
+            SearchQuery::new(24, 38, DATE.to_string()),
+            SearchQuery::new(38, 24, "2026-11-04".to_string()),
+        ],
+    )
+    .await
+    .unwrap();
+
+    assert!(
+        receipt
+            .to_string()
+            .contains("from Camerino to Ancona Piazza Cavour on 2026-11-02")
+    );
+    assert!(
+        receipt
+            .to_string()
+            .contains("from Ancona Piazza Cavour to Camerino on 2026-11-04")
+    );
+
+    let searches = stub.searches();
+    assert_eq!(searches.len(), 2);
+    assert_eq!(searches[1].partenza_id, 38);
+    assert_eq!(searches[1].data_partenza, "2026-11-04");
+
+    assert_eq!(stub.submissions("/Home/AggiungiCarrello").len(), 2);
+    assert_eq!(stub.submissions("/Home/Checkout").len(), 1);
+    assert_eq!(stub.submissions("/Home/ConfermaAcquisto").len(), 1);
+}
+
+#[tokio::test]
+async fn http_backend_books_group() {
+    let stub = ContramStub::start().await;
+    let backend = HttpBackend::new(&stub.base_url);
+    let passengers = vec![
+        Passenger::student(test_user()),
+        Passenger::student(User::new(
+            "anna.bianchi@example.com".to_string(),
+            "Anna".to_string(),
+            "Bianchi".to_string(),
+            "anna.bianchi@studenti.unicam.it".to_string(),
+            "3337654321".to_string(),
+        )),
+        Passenger::adult(User::new(
+            "luca.verdi@example.com".to_string(),
+            "Luca".to_string(),
+            "Verdi".to_string(),
+            "luca.verdi@example.com".to_string(),
+            "3331112222".to_string(),
+        )),
+    ];
+
+    let receipt = book_ticket(
+        &backend,
+        &passengers,
+        &SearchQuery::new(24, 38, DATE.to_string()),
+    )
+    .await
+    .unwrap();
+
+    assert!(receipt.to_string().contains("Anna Bianchi (student)"));
+    assert!(receipt.to_string().contains("Luca Verdi (adult)"));
+
+    let searches = stub.searches();
+    assert_eq!(searches[0].numero_studenti, 2);
+    assert_eq!(searches[0].numero_adulti, 1);
+
+    let checkout = stub.submissions("/Home/Checkout");
+    assert_eq!(checkout.len(), 1);
+    assert_eq!(checkout[0].field("Nominativi[0].Nome"), Some("Mario"));
+    assert_eq!(checkout[0].field("Nominativi[1].Nome"), Some("Anna"));
+    assert_eq!(checkout[0].field("Nominativi[2].Cognome"), Some("Verdi"));
+    assert_eq!(
+        checkout[0].field("EmailAcquirente"),
+        Some("mario.rossi@example.com")
+    );
+    assert_eq!(stub.submissions("/Home/ConfermaAcquisto").len(), 1);
+}
+
+#[tokio::test]
+async fn http_backend_fails_without_trips() {
+    let stub = ContramStub::start().await;
+    stub.sell_out(DATE);
+    let backend = HttpBackend::new(&stub.base_url);
+
+    let error = book_ticket(
+        &backend,
+        &test_passengers(),
+        &SearchQuery::new(24, 38, DATE.to_string()),
+    )
+    .await
+    .unwrap_err();
+
+    assert!(matches!(error, BookingError::SoldOut(_)));
+    assert!(error.to_string().contains("No trips available"));
+    assert!(stub.submissions("/Home/AggiungiCarrello").is_empty());
+}
+
+#[tokio::test]
+async fn http_backend_saves_page_of_sold_out_search() {
+    let stub = ContramStub::start().await;
+    stub.sell_out(DATE);
+    let dir = temp_path("diagnostics-sold-out");
+    let backend = HttpBackend::new(&stub.base_url).with_diagnostics(&dir);
+
+    let error = book_ticket(
+        &backend,
+        &test_passengers(),
+        &SearchQuery::new(24, 38, DATE.to_string()),
+    )
+    .await
+    .unwrap_err();
+
+    let failure = error.failure().unwrap();
+    assert_eq!(failure.step, "select departure");
+    assert_eq!(failure.kind, FailureKind::SoldOut);
+    assert!(failure.screenshot.is_none());
+    let page = std::fs::read_to_string(failure.page.as_ref().unwrap()).unwrap();
+    assert!(page.contains("Nessuna corsa disponibile"));
+    assert!(failure.page.as_ref().unwrap().starts_with(&dir));
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn http_backend_reports_rejected_passenger_data() {
+    let stub = ContramStub::start().await;
+    let backend = HttpBackend::new(&stub.base_url);
+    let passengers = vec![Passenger::student(User::new(
+        "mario.rossi@example.com".to_string(),
+        "Mario".to_string(),
+        "Rossi".to_string(),
+        "mario.rossi@studenti.unicam.it".to_string(),
+        String::new(),
+    ))];
+
+    let error = book_ticket(
+        &backend,
+        &passengers,
+        &SearchQuery::new(24, 38, DATE.to_string()),
+    )
+    .await
+    .unwrap_err();
+
+    assert!(matches!(error, BookingError::Step(_)));
+    let failure = error.failure().unwrap();
+    assert_eq!(failure.step, "confirm");
+    assert_eq!(failure.kind, FailureKind::ValidationError);
+    // Without a diagnostics directory nothing is saved
+    assert!(failure.page.is_none());
+    assert!(stub.submissions("/Home/ConfermaAcquisto").is_empty());
+}
+
+#[tokio::test]
+async fn http_backend_fails_without_confirmation() {
+    let stub = ContramStub::start().await;
+    stub.reject_purchase();
+    let backend = HttpBackend::new(&stub.base_url);
+
+    let error = book_ticket(
+        &backend,
+        &test_passengers(),
+        &SearchQuery::new(24, 38, DATE.to_string()),
+    )
+    .await
+    .unwrap_err();
+
+    assert!(matches!(error, BookingError::ConfirmationMissing(_)));
+    assert_eq!(error.failure().unwrap().step, "confirm");
+    assert!(error.to_string().contains("No purchase confirmation"));
+}
+
+#[tokio::test]
+async fn booking_rejects_unknown_stop() {
+    let stub = ContramStub::start().await;
+    let backend = HttpBackend::new(&stub.base_url);
+
+    let result = book_ticket(
+        &backend,
+        &test_passengers(),
+        &SearchQuery::new(99, 38, DATE.to_string()),
+    )
+    .await;
+
+    assert!(matches!(result, Err(BookingError::CityLookup(_))));
+    assert!(stub.searches().is_empty());
+}
+
+#[tokio::test]
+async fn booking_rejects_unserved_route() {
+    let stub = ContramStub::start().await;
+    let backend = HttpBackend::new(&stub.base_url);
+
+    let error = book_ticket(
+        &backend,
+        &test_passengers(),
+        &SearchQuery::new(24, 53, DATE.to_string()),
+    )
+    .await
+    .unwrap_err();
+
+    assert!(matches!(
+        error,
+        BookingError::InvalidRoute { ref from, to_id: 53 } if from == "Camerino"
+    ));
+    assert_eq!(error.to_string(), "No line from Camerino to stop 53");
+    assert!(stub.searches().is_empty());
+}
+
+#[tokio::test]
+#[ignore = "needs a WebDriver server on localhost:4444"]
+async fn selenium_backend_books_ticket() {
+    let stub = ContramStub::start().await;
+    let backend = SeleniumBackend::new(&stub.base_url);
+
+    book_ticket(
+        &backend,
+        &test_passengers(),
+        &SearchQuery::new(24, 38, DATE.to_string()),
+    )
+    .await
+    .unwrap();
+
+    let checkout = stub.submissions("/Home/Checkout");
+    assert_eq!(checkout.len(), 1);
+    assert_eq!(checkout[0].field("Nominativi[0].Nome"), Some("Mario"));
+    assert_eq!(stub.submissions("/Home/ConfermaAcquisto").len(), 1);
+}
+
+#[tokio::test]
+#[ignore = "needs a WebDriver server on localhost:4444"]
+async fn selenium_backend_fails_without_trips() {
+    let stub = ContramStub::start().await;
+    stub.sell_out(DATE);
+    let backend = SeleniumBackend::new(&stub.base_url);
+
+    let result = book_ticket(
+        &backend,
+        &test_passengers(),
+        &SearchQuery::new(24, 38, DATE.to_string()),
+    )
+    .await;
+
+    assert!(result.is_err());
+    assert!(stub.submissions("/Home/AggiungiCarrello").is_empty());
+}
+
+#[tokio::test]
+async fn selenium_backend_reports_unreachable_webdriver() {
+    let stub = ContramStub::start().await;
+    // Nothing listens on port 1
+    let backend = SeleniumBackend::new(&stub.base_url).with_browser(BrowserConfig {
+        url: "http://127.0.0.1:1".to_string(),
+        ..BrowserConfig::default()
+    });
+
+    let error = book_ticket(
+        &backend,
+        &test_passengers(),
+        &SearchQuery::new(24, 38, DATE.to_string()),
+    )
+    .await
+    .unwrap_err();
+
+    assert!(matches!(error, BookingError::WebDriverUnavailable(_)));
+    assert!(stub.searches().is_empty());
+}
+
+#[test]
+fn browser_config_sets_up_chrome() {
+    let config = BrowserConfig {
+        browser: "chrome".parse().unwrap(),
+        args: vec!["--window-size=1280,800".to_string()],
+        ..BrowserConfig::default()
+    };
+
+    let capabilities = serde_json::to_value(config.capabilities().unwrap()).unwrap();
+
+    assert_eq!(capabilities["browserName"], "chrome");
+    let args = capabilities["goog:chromeOptions"]["args"]
+        .as_array()
+        .unwrap();
+    assert!(args.contains(&"--headless".into()));
+    assert!(args.contains(&"--window-size=1280,800".into()));
+}
+
+#[tokio::test]
+async fn http_backend_books_requested_departure() {
+    let stub = ContramStub::start().await;
+    let backend = HttpBackend::new(&stub.base_url);
+    let query = SearchQuery::new(24, 38, DATE.to_string())
+        .with_departure(Some(DepartureFilter::parse("17:30").unwrap()));
+
+    let receipt = book_ticket(&backend, &test_passengers(), &query)
+        .await
+        .unwrap();
+
+    assert!(receipt.to_string().contains("at 17:30"));
+    let added = stub.submissions("/Home/AggiungiCarrello");
+    assert_eq!(added[0].field("CorsaID"), Some("1003"));
+}
+
+#[tokio::test]
+async fn http_backend_rejects_missing_departure() {
+    let stub = ContramStub::start().await;
+    let backend = HttpBackend::new(&stub.base_url);
+    let query = SearchQuery::new(24, 38, DATE.to_string())
+        .with_departure(Some(DepartureFilter::parse("18:00-20:00").unwrap()));
+
+    let error = book_ticket(&backend, &test_passengers(), &query)
+        .await
+        .unwrap_err();
+
+    assert!(error.to_string().contains("No departure at 18:00-20:00"));
+    assert!(stub.submissions("/Home/AggiungiCarrello").is_empty());
+}
+
+#[tokio::test]
+async fn http_backend_dry_run_stops_before_confirming() {
+    let stub = ContramStub::start().await;
+    let backend = HttpBackend::new(&stub.base_url);
+
+    let preview = preview_trip(
+        &backend,
+        &test_passengers(),
+        &[SearchQuery::new(24, 38, DATE.to_string())],
+    )
+    .await
+    .unwrap();
+
+    let text = preview.to_string();
+    assert!(text.contains("Would book from Camerino to Ancona Piazza Cavour"));
+    assert!(text.contains("EmailAcquirente = mario.rossi@example.com"));
+    assert!(preview.summary.html.contains("Conferma acquisto"));
+    assert_eq!(stub.submissions("/Home/Checkout").len(), 1);
+    assert!(stub.submissions("/Home/ConfermaAcquisto").is_empty());
+}
+
+#[tokio::test]
+async fn mock_backend_dry_run_skips_confirm() {
+    let backend = MockBackend::new();
+
+    preview_trip(
+        &backend,
+        &test_passengers(),
+        &[SearchQuery::new(24, 38, DATE.to_string())],
+    )
+    .await
+    .unwrap();
+
+    let steps = backend.steps.lock().unwrap();
+    assert!(steps.iter().any(|step| step == "review"));
+    assert!(!steps.iter().any(|step| step == "confirm"));
+}
+
+#[tokio::test]
+async fn http_backend_race_books_once_open() {
+    let stub = ContramStub::start().await;
+    let backend = HttpBackend::new(&stub.base_url);
+    let started = std::time::Instant::now();
+
+    let receipt = race_trip(
+        &backend,
+        &test_passengers(),
+        &[SearchQuery::new(24, 38, DATE.to_string())],
+        chrono::Utc::now() + chrono::Duration::milliseconds(300),
+    )
+    .await
+    .unwrap();
+
+    assert!(started.elapsed() >= std::time::Duration::from_millis(250));
+    assert_eq!(receipt.code.as_deref(), Some("CTR-2026-004517"));
+    assert_eq!(stub.searches().len(), 1);
+}
+
+#[tokio::test]
+async fn mock_backend_race_prepares_before_searching() {
+    let backend = MockBackend::new();
+
+    race_trip(
+        &backend,
+        &test_passengers(),
+        &[SearchQuery::new(24, 38, DATE.to_string())],
+        chrono::Utc::now(),
+    )
+    .await
+    .unwrap();
+
+    let steps = backend.steps.lock().unwrap();
+    let prepare = steps.iter().position(|step| step == "prepare").unwrap();
+    let search = steps
+        .iter()
+        .position(|step| step.starts_with("search"))
+        .unwrap();
+    assert!(prepare < search);
+}
+
+#[tokio::test]
+async fn session_open_failure_is_a_booking_step_outside_selenium() {
+    let backend = MockBackend {
+        fail_at: Some("open"),
+        ..MockBackend::new()
+    };
+
+    let error = book_trip(
+        &backend,
+        &test_passengers(),
+        &[SearchQuery::new(24, 38, DATE.to_string())],
+    )
+    .await
+    .unwrap_err();
+
+    assert!(matches!(&error, BookingError::Step(failure) if failure.step == "open"));
+}
+
+#[tokio::test]
+async fn attempts_after_an_early_failure_still_wait_for_the_opening() {
+    let failing = MockBackend {
+        fail_at: Some("prepare"),
+        ..MockBackend::new()
+    };
+    let working = MockBackend::new();
+    let legs = [SearchQuery::new(24, 38, DATE.to_string())];
+    let passengers = test_passengers();
+    let opens_at = chrono::Utc::now() + chrono::Duration::milliseconds(300);
+    let started = std::time::Instant::now();
+    let attempts = std::sync::atomic::AtomicU32::new(0);
+    let policy = RetryPolicy {
+        backoff: std::time::Duration::ZERO,
+        ..RetryPolicy::default()
+    };
+
+    let receipt = with_retry(
+        &policy,
+        || async {
+            // The first attempt fails while getting ready, before booking opens
+            let backend = match attempts.fetch_add(1, Ordering::SeqCst) {
+                0 => &failing,
+                _ => &working,
+            };
+            book_when_open(backend, &passengers, &legs, Some(opens_at))
+                .await
+                .map_err(Error::from)
+        },
+        |_| async {},
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(attempts.load(Ordering::SeqCst), 2);
+    assert!(started.elapsed() >= std::time::Duration::from_millis(250));
+    assert!(receipt.code.is_some());
+    assert!(
+        !failing
+            .steps
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|step| step.starts_with("search"))
+    );
+}
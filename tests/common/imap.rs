@@ -0,0 +1,179 @@
+//! Minimal IMAP server holding a single mailbox, enough for the ticket mail ingest.
+
+use std::sync::{
+    Arc, Mutex,
+    atomic::{AtomicBool, Ordering},
+};
+
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+};
+
+pub const USERNAME: &str = "biglietti@example.com";
+pub const PASSWORD: &str = "secret";
+
+struct StoredMail {
+    uid: u32,
+    raw: Vec<u8>,
+    seen: bool,
+}
+
+pub struct ImapStub {
+    pub port: u16,
+    mails: Arc<Mutex<Vec<StoredMail>>>,
+    read_only: Arc<AtomicBool>,
+}
+
+impl ImapStub {
+    /// Starts the server on a random local port.
+    pub async fn start() -> Self {
+        let mails = Arc::new(Mutex::new(Vec::new()));
+        let read_only = Arc::new(AtomicBool::new(false));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let server_mails = mails.clone();
+        let server_read_only = read_only.clone();
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                tokio::spawn(serve(
+                    stream,
+                    server_mails.clone(),
+                    server_read_only.clone(),
+                ));
+            }
+        });
+
+        Self {
+            port,
+            mails,
+            read_only,
+        }
+    }
+
+    /// Makes the server refuse to change the flags of the emails.
+    pub fn refuse_flags(&self) {
+        self.read_only.store(true, Ordering::SeqCst);
+    }
+
+    /// Delivers an unread email, returning its UID.
+    pub fn deliver(&self, raw: &[u8]) -> u32 {
+        let mut mails = self.mails.lock().unwrap();
+        let uid = mails.len() as u32 + 1;
+        mails.push(StoredMail {
+            uid,
+            raw: raw.to_vec(),
+            seen: false,
+        });
+        uid
+    }
+
+    pub fn is_seen(&self, uid: u32) -> bool {
+        self.mails
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|mail| mail.uid == uid && mail.seen)
+    }
+}
+
+async fn serve(stream: TcpStream, mails: Arc<Mutex<Vec<StoredMail>>>, read_only: Arc<AtomicBool>) {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+    writer
+        .write_all(b"* OK IMAP stand-in ready\r\n")
+        .await
+        .unwrap();
+
+    while let Ok(Some(line)) = lines.next_line().await {
+        let mut words = line.splitn(3, ' ');
+        let tag = words.next().unwrap_or_default().to_string();
+        let command = words.next().unwrap_or_default().to_uppercase();
+        let args = words.next().unwrap_or_default().to_string();
+
+        let mut response = Vec::new();
+        match command.as_str() {
+            "CAPABILITY" => response.extend_from_slice(b"* CAPABILITY IMAP4rev1\r\n"),
+            "LOGIN" if args != format!("\"{}\" \"{}\"", USERNAME, PASSWORD) => {
+                let reply = format!("{} NO invalid credentials\r\n", tag);
+                writer.write_all(reply.as_bytes()).await.unwrap();
+                continue;
+            }
+            "SELECT" => {
+                let count = mails.lock().unwrap().len();
+                response.extend_from_slice(
+                    format!("* {} EXISTS\r\n* OK [UIDVALIDITY 1] UIDs valid\r\n", count).as_bytes(),
+                );
+            }
+            "UID" if args.starts_with("STORE") && read_only.load(Ordering::SeqCst) => {
+                let reply = format!("{} NO mailbox is read-only\r\n", tag);
+                writer.write_all(reply.as_bytes()).await.unwrap();
+                continue;
+            }
+            "UID" => {
+                let mut mails = mails.lock().unwrap();
+                if let Some(("SEARCH", _)) = args.split_once(' ') {
+                    let unseen: String = mails
+                        .iter()
+                        .filter(|mail| !mail.seen)
+                        .map(|mail| format!(" {}", mail.uid))
+                        .collect();
+                    response.extend_from_slice(format!("* SEARCH{}\r\n", unseen).as_bytes());
+                } else if let Some(("FETCH", rest)) = args.split_once(' ') {
+                    let uids = parse_uids(rest);
+                    for (index, mail) in mails.iter().enumerate() {
+                        if !uids.contains(&mail.uid) {
+                            continue;
+                        }
+                        response.extend_from_slice(
+                            format!(
+                                "* {} FETCH (UID {} BODY[] {{{}}}\r\n",
+                                index + 1,
+                                mail.uid,
+                                mail.raw.len()
+                            )
+                            .as_bytes(),
+                        );
+                        response.extend_from_slice(&mail.raw);
+                        response.extend_from_slice(b")\r\n");
+                    }
+                } else if let Some(("STORE", rest)) = args.split_once(' ') {
+                    let uids = parse_uids(rest);
+                    for (index, mail) in mails.iter_mut().enumerate() {
+                        if uids.contains(&mail.uid) {
+                            mail.seen = true;
+                            response.extend_from_slice(
+                                format!(
+                                    "* {} FETCH (UID {} FLAGS (\\Seen))\r\n",
+                                    index + 1,
+                                    mail.uid
+                                )
+                                .as_bytes(),
+                            );
+                        }
+                    }
+                }
+            }
+            "LOGOUT" => {
+                let reply = format!("* BYE logging out\r\n{} OK LOGOUT completed\r\n", tag);
+                writer.write_all(reply.as_bytes()).await.unwrap();
+                return;
+            }
+            _ => {}
+        }
+        response.extend_from_slice(format!("{} OK {} completed\r\n", tag, command).as_bytes());
+        writer.write_all(&response).await.unwrap();
+    }
+}
+
+/// Reads a UID set such as `1,3` from the start of `args`.
+fn parse_uids(args: &str) -> Vec<u32> {
+    args.split(' ')
+        .next()
+        .unwrap_or_default()
+        .split(',')
+        .filter_map(|uid| uid.parse().ok())
+        .collect()
+}
@@ -0,0 +1,328 @@
+//! Local stand-in for marcheroma.contram.it serving recorded copies of its pages.
+#![allow(dead_code)]
+
+use std::{
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use axum::{
+    Form, Json, Router,
+    extract::{Path, Query, State},
+    http::{HeaderMap, StatusCode, header},
+    response::{Html, IntoResponse, Redirect, Response},
+    routing::{get, post},
+};
+use contram_ticket_automated::{User, user::Passenger};
+use serde::Deserialize;
+use serde_json::Value;
+use tokio::net::TcpListener;
+
+pub mod imap;
+
+const TOKEN_COOKIE: &str = "__RequestVerificationToken=cookie-token";
+const TOKEN_FIELD: &str = "form-token";
+
+const FERMATE_PARTENZA: &str = include_str!("../fixtures/contram/fermate_partenza.json");
+/// Arrival stops keyed by departure stop ID.
+const FERMATE_ARRIVO: &str = include_str!("../fixtures/contram/fermate_arrivo.json");
+const RICERCA: &str = include_str!("../fixtures/contram/ricerca.html");
+const RICERCA_VUOTA: &str = include_str!("../fixtures/contram/ricerca_vuota.html");
+const CARRELLO: &str = include_str!("../fixtures/contram/carrello.html");
+const RIEPILOGO: &str = include_str!("../fixtures/contram/riepilogo.html");
+const CONFERMA: &str = include_str!("../fixtures/contram/conferma.html");
+
+/// A path in the temp directory unique to the test, with whatever was there removed
+/// beforehand.
+pub fn temp_path(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("contram-{}-{}", std::process::id(), name));
+    let _ = std::fs::remove_file(&path);
+    let _ = std::fs::remove_dir_all(&path);
+    path
+}
+
+/// A form posted to the stand-in.
+#[derive(Debug, Clone)]
+pub struct Submission {
+    pub path: String,
+    pub fields: Vec<(String, String)>,
+}
+
+impl Submission {
+    pub fn field(&self, name: &str) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|(field, _)| field == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+#[derive(Default)]
+pub struct StubState {
+    submissions: Mutex<Vec<Submission>>,
+    sold_out_dates: Mutex<Vec<String>>,
+    searches: Mutex<Vec<SearchParams>>,
+    stop_requests: Mutex<usize>,
+    stops_down: Mutex<bool>,
+    purchase_rejected: Mutex<bool>,
+}
+
+impl StubState {
+    /// Number of passengers of the latest search, which sizes the cart form.
+    fn party_size(&self) -> usize {
+        self.searches
+            .lock()
+            .unwrap()
+            .last()
+            .map(|search| (search.numero_studenti + search.numero_adulti) as usize)
+            .unwrap_or(1)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct SearchParams {
+    #[serde(rename = "PartenzaID")]
+    pub partenza_id: u32,
+    #[serde(rename = "DestinazioneID")]
+    pub destinazione_id: u32,
+    pub data_partenza: String,
+    pub numero_studenti: u32,
+    pub numero_adulti: u32,
+}
+
+pub struct ContramStub {
+    pub base_url: String,
+    state: Arc<StubState>,
+}
+
+impl ContramStub {
+    /// Starts the stand-in on a random local port.
+    pub async fn start() -> Self {
+        let state = Arc::new(StubState::default());
+        let app = Router::new()
+            .route("/", get(home))
+            .route("/api/fermata/partenza", get(fermate_partenza))
+            .route("/api/fermata/arrivo/{id}", get(fermate_arrivo))
+            .route("/home/Ricerca", get(ricerca))
+            .route("/Home/AggiungiCarrello", post(aggiungi_carrello))
+            .route("/Home/RitornaCarrello", get(carrello))
+            .route("/Home/Checkout", post(checkout))
+            .route("/Home/ConfermaAcquisto", post(conferma_acquisto))
+            .with_state(state.clone());
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        Self { base_url, state }
+    }
+
+    /// Makes searches for `date` return no trips.
+    pub fn sell_out(&self, date: &str) {
+        self.state
+            .sold_out_dates
+            .lock()
+            .unwrap()
+            .push(date.to_string());
+    }
+
+    /// Makes searches for `date` return trips again.
+    pub fn restock(&self, date: &str) {
+        self.state
+            .sold_out_dates
+            .lock()
+            .unwrap()
+            .retain(|sold_out| sold_out != date);
+    }
+
+    /// Forms posted to `path`, oldest first.
+    pub fn submissions(&self, path: &str) -> Vec<Submission> {
+        self.state
+            .submissions
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|submission| submission.path == path)
+            .cloned()
+            .collect()
+    }
+
+    pub fn searches(&self) -> Vec<SearchParams> {
+        self.state.searches.lock().unwrap().clone()
+    }
+
+    /// Number of times the departure stops were requested.
+    pub fn stop_requests(&self) -> usize {
+        *self.state.stop_requests.lock().unwrap()
+    }
+
+    /// Makes the purchase fail, showing the summary again with an error.
+    pub fn reject_purchase(&self) {
+        *self.state.purchase_rejected.lock().unwrap() = true;
+    }
+
+    /// Makes the departure stops endpoint fail with a server error.
+    pub fn take_down_stops(&self) {
+        *self.state.stops_down.lock().unwrap() = true;
+    }
+}
+
+pub fn test_user() -> User {
+    User::new(
+        "mario.rossi@example.com".to_string(),
+        "Mario".to_string(),
+        "Rossi".to_string(),
+        "mario.rossi@studenti.unicam.it".to_string(),
+        "3331234567".to_string(),
+    )
+}
+
+pub fn test_passengers() -> Vec<Passenger> {
+    vec![Passenger::student(test_user())]
+}
+
+fn page_with_token(html: impl Into<String>) -> Response {
+    (
+        [(header::SET_COOKIE, format!("{}; Path=/", TOKEN_COOKIE))],
+        Html(html.into()),
+    )
+        .into_response()
+}
+
+/// Records the form and checks both halves of the anti-forgery token.
+fn accept(
+    state: &StubState,
+    path: &str,
+    headers: &HeaderMap,
+    fields: Vec<(String, String)>,
+) -> Result<Submission, StatusCode> {
+    let has_cookie = headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .any(|value| value.split("; ").any(|cookie| cookie == TOKEN_COOKIE));
+
+    let submission = Submission {
+        path: path.to_string(),
+        fields,
+    };
+    state.submissions.lock().unwrap().push(submission.clone());
+
+    if has_cookie && submission.field("__RequestVerificationToken") == Some(TOKEN_FIELD) {
+        Ok(submission)
+    } else {
+        Err(StatusCode::BAD_REQUEST)
+    }
+}
+
+async fn fermate_partenza(State(state): State<Arc<StubState>>) -> Result<Json<Value>, StatusCode> {
+    *state.stop_requests.lock().unwrap() += 1;
+    if *state.stops_down.lock().unwrap() {
+        return Err(StatusCode::INTERNAL_SERVER_ERROR);
+    }
+    Ok(Json(serde_json::from_str(FERMATE_PARTENZA).unwrap()))
+}
+
+async fn fermate_arrivo(Path(id): Path<u32>) -> Json<Value> {
+    let destinations: Value = serde_json::from_str(FERMATE_ARRIVO).unwrap();
+    Json(
+        destinations
+            .get(id.to_string())
+            .cloned()
+            .unwrap_or(Value::Array(vec![])),
+    )
+}
+
+async fn ricerca(
+    State(state): State<Arc<StubState>>,
+    Query(params): Query<SearchParams>,
+) -> Response {
+    let sold_out = state
+        .sold_out_dates
+        .lock()
+        .unwrap()
+        .contains(&params.data_partenza);
+    state.searches.lock().unwrap().push(params);
+
+    page_with_token(if sold_out { RICERCA_VUOTA } else { RICERCA })
+}
+
+async fn aggiungi_carrello(
+    State(state): State<Arc<StubState>>,
+    headers: HeaderMap,
+    Form(fields): Form<Vec<(String, String)>>,
+) -> Result<Redirect, StatusCode> {
+    accept(&state, "/Home/AggiungiCarrello", &headers, fields)?;
+    Ok(Redirect::to("/Home/RitornaCarrello"))
+}
+
+async fn home() -> Html<&'static str> {
+    Html("<html><body><h1>Contram</h1></body></html>")
+}
+
+async fn carrello(State(state): State<Arc<StubState>>) -> Response {
+    page_with_token(cart_page(state.party_size()))
+}
+
+/// Repeats the recorded passenger fieldset once per passenger.
+fn cart_page(party_size: usize) -> String {
+    let start = CARRELLO.find("<fieldset").unwrap();
+    let end = CARRELLO.find("</fieldset>").unwrap() + "</fieldset>".len();
+    let fieldset = &CARRELLO[start..end];
+
+    let fieldsets: String = (0..party_size)
+        .map(|index| {
+            fieldset
+                .replace("Nominativi[0]", &format!("Nominativi[{}]", index))
+                .replace("Passeggero 1", &format!("Passeggero {}", index + 1))
+        })
+        .collect();
+    format!("{}{}{}", &CARRELLO[..start], fieldsets, &CARRELLO[end..])
+}
+
+async fn checkout(
+    State(state): State<Arc<StubState>>,
+    headers: HeaderMap,
+    Form(fields): Form<Vec<(String, String)>>,
+) -> Result<Html<String>, StatusCode> {
+    let submission = accept(&state, "/Home/Checkout", &headers, fields)?;
+    let party_size = state.party_size();
+
+    let mut required = vec!["EmailAcquirente".to_string()];
+    for index in 0..party_size {
+        for field in ["Nome", "Cognome", "Email", "Telefono"] {
+            required.push(format!("Nominativi[{}].{}", index, field));
+        }
+    }
+    if required.iter().all(|name| {
+        submission
+            .field(name)
+            .is_some_and(|value| !value.is_empty())
+    }) {
+        Ok(Html(RIEPILOGO.to_string()))
+    } else {
+        // Back to the cart, flagged the way ASP.NET validation does
+        Ok(Html(cart_page(party_size).replacen(
+            "<fieldset",
+            "<div class=\"validation-summary-errors\"><ul><li>Compila tutti i campi obbligatori</li></ul></div>\n      <fieldset",
+            1,
+        )))
+    }
+}
+
+async fn conferma_acquisto(
+    State(state): State<Arc<StubState>>,
+    headers: HeaderMap,
+    Form(fields): Form<Vec<(String, String)>>,
+) -> Result<Html<String>, StatusCode> {
+    accept(&state, "/Home/ConfermaAcquisto", &headers, fields)?;
+    if *state.purchase_rejected.lock().unwrap() {
+        return Ok(Html(RIEPILOGO.replacen(
+            "<form",
+            "<div class=\"alert alert-danger\">Pagamento non riuscito, riprova.</div>\n    <form",
+            1,
+        )));
+    }
+    Ok(Html(CONFERMA.to_string()))
+}
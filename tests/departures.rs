@@ -0,0 +1,148 @@
+use chrono::NaiveTime;
+use contram_ticket_automated::utils::{
+    departures::{Departure, DepartureFilter, open_departure, parse_departures, select_departure},
+    http_booking::find_book_form,
+};
+use reqwest::Url;
+
+const RICERCA: &str = include_str!("fixtures/contram/ricerca.html");
+const RICERCA_VUOTA: &str = include_str!("fixtures/contram/ricerca_vuota.html");
+const RICERCA_ESAURITA: &str = include_str!("fixtures/contram/ricerca_esaurita.html");
+
+fn time(value: &str) -> NaiveTime {
+    NaiveTime::parse_from_str(value, "%H:%M").unwrap()
+}
+
+#[test]
+fn parses_every_run_on_the_results_page() {
+    let departures = parse_departures(RICERCA);
+
+    assert_eq!(departures.len(), 3);
+    assert_eq!(departures[0].index, 0);
+    assert_eq!(departures[0].time, time("06:10"));
+    assert_eq!(departures[0].arrival_time, Some(time("07:55")));
+    assert_eq!(departures[0].price, Some(9.5));
+    assert_eq!(departures[0].seats, Some(12));
+    assert_eq!(departures[2].index, 2);
+    assert_eq!(departures[2].time, time("17:30"));
+    assert_eq!(departures[2].seats, Some(7));
+}
+
+#[test]
+fn parses_empty_results_page() {
+    assert!(parse_departures(RICERCA_VUOTA).is_empty());
+}
+
+#[test]
+fn selects_departure_by_time_or_window() {
+    let departures = parse_departures(RICERCA);
+
+    let first = select_departure(&departures, None).unwrap();
+    assert_eq!(first.time, time("06:10"));
+
+    let exact = DepartureFilter::parse("13:40").unwrap();
+    assert_eq!(
+        select_departure(&departures, Some(&exact)).unwrap().index,
+        1
+    );
+
+    let window = DepartureFilter::parse("17:00-18:30").unwrap();
+    assert_eq!(
+        select_departure(&departures, Some(&window)).unwrap().index,
+        2
+    );
+
+    let missing = DepartureFilter::parse("12:00").unwrap();
+    assert!(select_departure(&departures, Some(&missing)).is_err());
+}
+
+#[test]
+fn rejects_invalid_time_filters() {
+    assert!(DepartureFilter::parse("25:00").is_err());
+    assert!(DepartureFilter::parse("18:00-17:00").is_err());
+    assert!(DepartureFilter::parse("evening").is_err());
+}
+
+#[test]
+fn open_departure_skips_full_runs() {
+    let mut departures = parse_departures(RICERCA);
+    departures[0].seats = Some(0);
+    departures[1].seats = None;
+
+    let departure = open_departure(&departures, None).unwrap();
+    assert_eq!(departure.time, time("13:40"));
+
+    let full = DepartureFilter::parse("06:10").unwrap();
+    assert!(open_departure(&departures, Some(&full)).is_none());
+    assert!(open_departure(&[] as &[Departure], None).is_none());
+}
+
+#[test]
+fn counts_button_and_input_runs_alike() {
+    let html = r#"<html><body>
+        <form><input type="submit" value="Cerca"></form>
+        <div class="corsa"><span>06:10 → 07:55</span><input type="submit" value="Prenota"></div>
+        <div class="corsa"><span>13:40 → 15:25</span><button>Prenota</button></div>
+        <div class="corsa"><span>17:30 → 19:15</span><input type="submit" value=" Prenota "></div>
+    </body></html>"#;
+
+    let departures = parse_departures(html);
+
+    let runs: Vec<(usize, NaiveTime)> = departures
+        .iter()
+        .map(|departure| (departure.index, departure.time))
+        .collect();
+    assert_eq!(
+        runs,
+        [(0, time("06:10")), (1, time("13:40")), (2, time("17:30"))]
+    );
+}
+
+#[test]
+fn books_the_form_of_the_run_the_button_belongs_to() {
+    let html = r#"<html><body>
+        <form action="/Home/Cerca"><input type="submit" value="Cerca"></form>
+        <form action="/Home/Prenota"><input type="hidden" name="corsa" value="1">
+            <span>06:10 → 07:55</span><input type="submit" value="Prenota">
+        </form>
+        <div class="corsa"><span>13:40 → 15:25</span><button>Prenota</button></div>
+        <input type="button" value="Prenota">
+        <form action="/Home/Prenota">
+            <div class="corsa"><span>17:30 → 19:15</span><button name="corsa" value="3">Prenota</button></div>
+            <div class="corsa"><span>19:00 → 20:45</span><button name="corsa" value="4">Prenota</button></div>
+        </form>
+    </body></html>"#;
+    let page_url = Url::parse("http://localhost/Home/Ricerca").unwrap();
+
+    let departures = parse_departures(html);
+    let corsa = |index: usize| {
+        find_book_form(html, &page_url, departures[index].index)
+            .map(|form| form.fields)
+            .map_err(|e| e.to_string())
+    };
+
+    assert_eq!(departures.len(), 4);
+    assert_eq!(corsa(0), Ok(vec![("corsa".to_string(), "1".to_string())]));
+    assert_eq!(
+        corsa(1),
+        Err("Button \"Prenota\" is not in a form".to_string())
+    );
+    assert_eq!(departures[2].time, time("17:30"));
+    assert_eq!(corsa(2), Ok(vec![("corsa".to_string(), "3".to_string())]));
+    assert_eq!(corsa(3), Ok(vec![("corsa".to_string(), "4".to_string())]));
+}
+
+#[test]
+fn open_departure_skips_runs_labelled_sold_out() {
+    let departures = parse_departures(RICERCA_ESAURITA);
+
+    assert_eq!(departures.len(), 3);
+    assert_eq!((departures[0].seats, departures[0].sold_out), (None, true));
+    assert_eq!((departures[1].seats, departures[1].sold_out), (None, false));
+    assert!(!departures[2].sold_out);
+
+    let departure = open_departure(&departures, None).unwrap();
+    assert_eq!(departure.time, time("13:40"));
+    let sold_out = DepartureFilter::parse("06:10").unwrap();
+    assert!(open_departure(&departures, Some(&sold_out)).is_none());
+}
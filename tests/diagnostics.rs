@@ -0,0 +1,65 @@
+//! Classification of failed booking steps.
+
+use color_eyre::eyre::Error;
+use contram_ticket_automated::utils::diagnostics::{FailureKind, classify};
+
+const RICERCA_VUOTA: &str = include_str!("fixtures/contram/ricerca_vuota.html");
+const RICERCA_ESAURITA: &str = include_str!("fixtures/contram/ricerca_esaurita.html");
+const CARRELLO: &str = include_str!("fixtures/contram/carrello.html");
+
+#[test]
+fn classify_detects_sold_out_page() {
+    let error = Error::msg("No trips available on this date");
+
+    assert_eq!(
+        classify(&error, "select departure", RICERCA_VUOTA, None),
+        FailureKind::SoldOut
+    );
+}
+
+#[test]
+fn classify_only_reads_the_run_being_booked() {
+    let missing = Error::msg("No departure at 09:00 (available: 06:10, 13:40, 17:30)");
+    let rejected = Error::msg("Booking form was not accepted");
+
+    assert_eq!(
+        classify(&missing, "select departure", RICERCA_ESAURITA, None),
+        FailureKind::Unknown
+    );
+    assert_eq!(
+        classify(&rejected, "add to cart", RICERCA_ESAURITA, Some(0)),
+        FailureKind::SoldOut
+    );
+    assert_eq!(
+        classify(&rejected, "add to cart", RICERCA_ESAURITA, Some(2)),
+        FailureKind::Unknown
+    );
+}
+
+#[test]
+fn classify_detects_rejected_form() {
+    let page = CARRELLO.replace(
+        "<fieldset",
+        "<span class=\"field-validation-error\">Campo obbligatorio</span><fieldset",
+    );
+    let error = Error::msg("Button \"Conferma acquisto\" not found");
+
+    assert_eq!(
+        classify(&error, "confirm", &page, None),
+        FailureKind::ValidationError
+    );
+}
+
+#[test]
+fn classify_detects_missing_elements() {
+    let error = Error::msg("Button \"Procedi all'acquisto\" not found");
+
+    assert_eq!(
+        classify(&error, "fill passenger data", CARRELLO, None),
+        FailureKind::SiteChanged
+    );
+    assert_eq!(
+        classify(&Error::msg("error sending request"), "open", "", None),
+        FailureKind::Unknown
+    );
+}
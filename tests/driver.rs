@@ -0,0 +1,127 @@
+//! Supervising the WebDriver binary, with `sleep` standing in for geckodriver and a
+//! local server answering its `/status`.
+#![cfg(unix)]
+
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::Duration,
+};
+
+use axum::{Json, Router, extract::State, routing::get};
+use contram_ticket_automated::utils::driver::{DriverConfig, DriverSupervisor};
+use serde_json::{Value, json};
+use tokio::net::TcpListener;
+
+/// Serves `/status`, ready or not as set in the returned flag.
+async fn status_server() -> (String, Arc<AtomicBool>) {
+    let ready = Arc::new(AtomicBool::new(false));
+    let app = Router::new()
+        .route(
+            "/status",
+            get(|State(ready): State<Arc<AtomicBool>>| async move {
+                Json::<Value>(json!({ "value": { "ready": ready.load(Ordering::SeqCst) } }))
+            }),
+        )
+        .with_state(ready.clone());
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    (url, ready)
+}
+
+fn driver_config(url: &str, seconds: &str) -> DriverConfig {
+    DriverConfig {
+        args: vec![seconds.to_string()],
+        health_interval: Duration::from_millis(100),
+        startup_timeout: Duration::from_secs(2),
+        ..DriverConfig::new("sleep", url).unwrap()
+    }
+}
+
+fn is_running(pid: u32) -> bool {
+    std::path::Path::new(&format!("/proc/{}", pid)).exists()
+}
+
+#[test]
+fn driver_config_runs_on_url_port() {
+    let config = DriverConfig::new("geckodriver", "http://localhost:4444/").unwrap();
+
+    assert_eq!(config.args, ["--port=4444"]);
+    assert_eq!(config.url, "http://localhost:4444");
+}
+
+#[tokio::test]
+async fn waits_until_driver_is_ready() {
+    let (url, ready) = status_server().await;
+    let supervisor = DriverSupervisor::start(driver_config(&url, "30"))
+        .await
+        .unwrap();
+
+    tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        ready.store(true, Ordering::SeqCst);
+    });
+    supervisor.wait_until_healthy().await.unwrap();
+
+    supervisor.shutdown().await;
+}
+
+#[tokio::test]
+async fn fails_when_driver_never_gets_ready() {
+    let (url, _) = status_server().await;
+    let supervisor = DriverSupervisor::start(DriverConfig {
+        startup_timeout: Duration::from_millis(300),
+        ..driver_config(&url, "30")
+    })
+    .await
+    .unwrap();
+
+    let error = supervisor.wait_until_healthy().await.unwrap_err();
+
+    assert!(error.to_string().contains("is not ready"));
+    supervisor.shutdown().await;
+}
+
+#[tokio::test]
+async fn restarts_driver_that_exits() {
+    let (url, ready) = status_server().await;
+    ready.store(true, Ordering::SeqCst);
+    let supervisor = DriverSupervisor::start(driver_config(&url, "0.2"))
+        .await
+        .unwrap();
+
+    tokio::time::sleep(Duration::from_secs(1)).await;
+
+    assert!(supervisor.restarts() >= 1);
+    supervisor.shutdown().await;
+}
+
+#[tokio::test]
+async fn shutdown_stops_driver() {
+    let (url, ready) = status_server().await;
+    ready.store(true, Ordering::SeqCst);
+    let supervisor = DriverSupervisor::start(driver_config(&url, "30"))
+        .await
+        .unwrap();
+    let pid = supervisor.pid().await.unwrap();
+    assert!(is_running(pid));
+
+    supervisor.shutdown().await;
+
+    assert!(!is_running(pid));
+    assert!(supervisor.pid().await.is_none());
+}
+
+#[tokio::test]
+async fn start_fails_without_binary() {
+    let result = DriverSupervisor::start(
+        DriverConfig::new("/nonexistent/geckodriver", "http://localhost:4444").unwrap(),
+    )
+    .await;
+
+    assert!(result.is_err());
+}
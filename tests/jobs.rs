@@ -0,0 +1,245 @@
+//! Booking jobs kept on disk across restarts.
+
+mod common;
+
+use chrono::{Duration, NaiveDate, Utc};
+use common::temp_path;
+use contram_ticket_automated::utils::{
+    backend::SearchQuery,
+    departures::DepartureFilter,
+    jobs::{BookingJob, JobStatus, JobStore, MissedJobPolicy, booking_open_datetime},
+};
+
+fn job(due_in: Duration) -> BookingJob {
+    let query = SearchQuery::new(24, 38, "2026-11-02".to_string())
+        .with_departure(Some(DepartureFilter::parse("13:00-18:00").unwrap()));
+    BookingJob::new(
+        42,
+        "mario",
+        "Camerino → Ancona Piazza Cavour".to_string(),
+        &query,
+        Utc::now() + due_in,
+    )
+}
+
+#[test]
+fn jobs_survive_reopening_the_store() {
+    let path = temp_path("jobs-reopen.json");
+    let store = JobStore::open(&path).unwrap();
+
+    let first = store.add(job(Duration::days(3))).unwrap();
+    let second = store.add(job(Duration::days(4))).unwrap();
+    store
+        .set_status(first.id, JobStatus::Booked, Some("CTR-1".to_string()))
+        .unwrap();
+
+    let reopened = JobStore::open(&path).unwrap();
+    let jobs = reopened.list();
+    assert_eq!(jobs.len(), 2);
+    assert_eq!((first.id, second.id), (1, 2));
+    assert_eq!(jobs[0].status, JobStatus::Booked);
+    assert_eq!(jobs[0].note.as_deref(), Some("CTR-1"));
+    assert_eq!(jobs[1], second);
+
+    let query = jobs[1].query().unwrap();
+    assert_eq!((query.from_id, query.to_id), (24, 38));
+    assert_eq!(query.departure.unwrap().to_string(), "13:00-18:00");
+
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn recover_runs_due_jobs_under_run_policy() {
+    let path = temp_path("jobs-run.json");
+    let store = JobStore::open(&path).unwrap();
+    store.add(job(Duration::days(3))).unwrap();
+    store.add(job(-Duration::hours(2))).unwrap();
+
+    let recovered = store.recover(Utc::now(), MissedJobPolicy::Run).unwrap();
+
+    assert_eq!(recovered.rearm.len(), 2);
+    assert!(recovered.missed.is_empty());
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn recover_reports_missed_jobs_under_report_policy() {
+    let path = temp_path("jobs-report.json");
+    let store = JobStore::open(&path).unwrap();
+    let upcoming = store.add(job(Duration::days(3))).unwrap();
+    let due = store.add(job(-Duration::hours(2))).unwrap();
+    let interrupted = store.add(job(-Duration::hours(1))).unwrap();
+    store
+        .set_status(interrupted.id, JobStatus::Running, None)
+        .unwrap();
+
+    let recovered = store.recover(Utc::now(), MissedJobPolicy::Report).unwrap();
+
+    assert_eq!(recovered.rearm, [upcoming]);
+    let missed: Vec<u64> = recovered.missed.iter().map(|job| job.id).collect();
+    assert_eq!(missed, [due.id, interrupted.id]);
+    let reopened = JobStore::open(&path).unwrap();
+    assert_eq!(reopened.get(due.id).unwrap().status, JobStatus::Missed);
+    assert_eq!(
+        reopened.get(interrupted.id).unwrap().status,
+        JobStatus::Missed
+    );
+
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn interrupted_jobs_are_never_run_again() {
+    let path = temp_path("jobs-interrupted.json");
+    let store = JobStore::open(&path).unwrap();
+    let interrupted = store.add(job(-Duration::minutes(5))).unwrap();
+    store
+        .set_status(interrupted.id, JobStatus::Running, None)
+        .unwrap();
+
+    let recovered = store.recover(Utc::now(), MissedJobPolicy::Run).unwrap();
+
+    assert!(recovered.rearm.is_empty());
+    assert_eq!(recovered.missed.len(), 1);
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn rescheduled_jobs_book_the_new_date() {
+    let path = temp_path("jobs-reschedule.json");
+    let store = JobStore::open(&path).unwrap();
+    let moved = store.add(job(Duration::days(3))).unwrap();
+    let cancelled = store.add(job(-Duration::hours(1))).unwrap();
+    store
+        .set_status(cancelled.id, JobStatus::Cancelled, None)
+        .unwrap();
+
+    let due = Utc::now() + Duration::days(5);
+    let rescheduled = store
+        .reschedule(moved.id, "2026-11-04".to_string(), due)
+        .unwrap();
+
+    assert_eq!(rescheduled.status, JobStatus::Pending);
+    assert_eq!(rescheduled.query().unwrap().date, "2026-11-04");
+    let reopened = JobStore::open(&path).unwrap();
+    assert_eq!(reopened.get(moved.id).unwrap(), rescheduled);
+    let recovered = reopened.recover(Utc::now(), MissedJobPolicy::Run).unwrap();
+    assert_eq!(recovered.rearm, [rescheduled]);
+    assert!(recovered.missed.is_empty());
+    assert_eq!(
+        reopened.get(cancelled.id).unwrap().status,
+        JobStatus::Cancelled
+    );
+
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn jobs_saved_before_groups_and_returns_still_load() {
+    let path = temp_path("jobs-legacy.json");
+    std::fs::write(
+        &path,
+        r#"[{"id":1,"chat_id":42,"username":"mario","route":"Camerino → Ancona Piazza Cavour",
+            "from_id":24,"to_id":38,"date":"2026-11-02","departure":null,
+            "due":"2026-10-26T23:00:00Z","status":"pending","note":null,
+            "created":"2026-10-17T10:00:00Z"}]"#,
+    )
+    .unwrap();
+
+    let store = JobStore::open(&path).unwrap();
+    let job = store.get(1).unwrap();
+
+    assert_eq!(job.return_date, None);
+    assert!(job.companions.is_empty());
+    assert_eq!(job.outbound_id, None);
+    assert_eq!(job.legs().unwrap().len(), 1);
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn group_return_jobs_keep_their_legs_and_passengers() {
+    let path = temp_path("jobs-group-return.json");
+    let store = JobStore::open(&path).unwrap();
+    let trip = store
+        .add(
+            job(Duration::days(3))
+                .with_return_date(Some("2026-11-05".to_string()))
+                .with_companions(vec!["@luigi".to_string()]),
+        )
+        .unwrap();
+
+    let reopened = JobStore::open(&path).unwrap();
+    let recovered = reopened.recover(Utc::now(), MissedJobPolicy::Run).unwrap();
+
+    assert_eq!(recovered.rearm, std::slice::from_ref(&trip));
+    assert_eq!(trip.companions, ["@luigi"]);
+    let legs = trip.legs().unwrap();
+    assert_eq!(legs.len(), 2);
+    assert_eq!((legs[1].from_id, legs[1].to_id), (38, 24));
+    assert_eq!(legs[1].date, "2026-11-05");
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn return_trips_only_move_while_the_way_back_stays_bookable() {
+    let path = temp_path("jobs-reschedule-return.json");
+    let store = JobStore::open(&path).unwrap();
+    let date = |day: &str| NaiveDate::parse_from_str(day, "%Y-%m-%d").unwrap();
+    let due = |day: &str| booking_open_datetime(date(day)).with_timezone(&Utc);
+    let query = SearchQuery::new(24, 38, "2026-11-04".to_string());
+    let trip = store
+        .add(
+            BookingJob::new(
+                42,
+                "mario",
+                "Camerino → Ancona Piazza Cavour".to_string(),
+                &query,
+                due("2026-11-04"),
+            )
+            .with_return_date(Some("2026-11-04".to_string())),
+        )
+        .unwrap();
+
+    // Booking for the 2nd opens before the way back on the 4th can be booked
+    assert!(
+        store
+            .reschedule(trip.id, "2026-11-02".to_string(), due("2026-11-02"))
+            .is_err()
+    );
+    assert!(
+        store
+            .reschedule(trip.id, "2026-11-05".to_string(), due("2026-11-05"))
+            .is_err()
+    );
+    assert_eq!(store.get(trip.id).unwrap(), trip);
+
+    // A day trip moves along with its way back
+    let moved = store
+        .reschedule(trip.id, "2026-11-04".to_string(), due("2026-11-04"))
+        .unwrap();
+    assert_eq!(moved.status, JobStatus::Pending);
+    assert_eq!(moved.legs().unwrap()[1].date, "2026-11-04");
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn both_legs_of_a_return_trip_are_found_from_either_job() {
+    let path = temp_path("jobs-trip.json");
+    let store = JobStore::open(&path).unwrap();
+    let outbound = store.add(job(Duration::days(3))).unwrap();
+    let unrelated = store.add(job(Duration::days(4))).unwrap();
+    let way_back = store
+        .add(job(Duration::days(5)).with_outbound(outbound.id))
+        .unwrap();
+
+    let reopened = JobStore::open(&path).unwrap();
+
+    assert_eq!(
+        reopened.trip(outbound.id),
+        [outbound.clone(), way_back.clone()]
+    );
+    assert_eq!(reopened.trip(way_back.id), [outbound, way_back]);
+    assert_eq!(reopened.trip(unrelated.id), [unrelated]);
+    assert!(reopened.trip(99).is_empty());
+    let _ = std::fs::remove_file(&path);
+}
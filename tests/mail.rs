@@ -0,0 +1,139 @@
+//! Matching ticket emails to bookings and fetching them from the IMAP stand-in.
+
+mod common;
+
+use std::time::Duration;
+
+use common::{
+    imap::{ImapStub, PASSWORD, USERNAME},
+    temp_path, test_passengers,
+};
+use contram_ticket_automated::utils::{
+    mail::{MailConfig, MailIngest, PendingTicket, parse_ticket_mail},
+    receipt::{BookingReceipt, ReceiptTrip},
+};
+
+const BIGLIETTO: &[u8] = include_bytes!("fixtures/mail/biglietto.eml");
+const NEWSLETTER: &[u8] = b"From: Contram <news@contram.it>\r\n\
+Subject: Orari invernali\r\n\
+Content-Type: text/plain\r\n\
+\r\n\
+Dal 2 novembre cambiano gli orari.\r\n";
+
+fn receipt(date: &str) -> BookingReceipt {
+    BookingReceipt {
+        code: Some("CTR-2026-004517".to_string()),
+        trips: vec![ReceiptTrip {
+            from: "Camerino".to_string(),
+            to: "Ancona Piazza Cavour".to_string(),
+            date: date.to_string(),
+            departure: "06:10".to_string(),
+        }],
+        passengers: vec!["Mario Rossi (student)".to_string()],
+        price: Some(9.5),
+        status: "Pagato".to_string(),
+        email: "mario.rossi@studenti.unicam.it".to_string(),
+    }
+}
+
+#[test]
+fn parse_ticket_mail_keeps_pdf_attachments() {
+    let mail = parse_ticket_mail(7, BIGLIETTO).unwrap();
+
+    assert_eq!(mail.uid, 7);
+    assert_eq!(
+        mail.subject,
+        "Biglietto Contram - Prenotazione CTR-2026-004517"
+    );
+    assert!(mail.text.contains("Camerino - Ancona Piazza Cavour"));
+    assert_eq!(mail.pdfs.len(), 1);
+    assert_eq!(mail.pdfs[0].file_name, "biglietto-CTR-2026-004517.pdf");
+    assert!(mail.pdfs[0].contents.starts_with(b"%PDF-"));
+
+    assert!(parse_ticket_mail(8, NEWSLETTER).is_none());
+}
+
+#[test]
+fn pending_ticket_matches_route_date_and_passenger() {
+    let mail = parse_ticket_mail(1, BIGLIETTO).unwrap();
+
+    let ticket = PendingTicket::new(42, &receipt("2026-11-02"), &test_passengers());
+    assert!(ticket.matches(&mail));
+
+    let other_day = PendingTicket::new(42, &receipt("2026-11-03"), &test_passengers());
+    assert!(!other_day.matches(&mail));
+}
+
+fn mail_config(server: &ImapStub) -> MailConfig {
+    MailConfig {
+        host: "127.0.0.1".to_string(),
+        port: server.port,
+        tls: false,
+        username: USERNAME.to_string(),
+        password: PASSWORD.to_string(),
+        mailbox: "INBOX".to_string(),
+        poll_interval: Duration::from_secs(60),
+        address: USERNAME.to_string(),
+    }
+}
+
+#[tokio::test]
+async fn poll_forwards_matching_ticket_and_marks_it_read() {
+    let server = ImapStub::start().await;
+    let newsletter = server.deliver(NEWSLETTER);
+    let ticket_uid = server.deliver(BIGLIETTO);
+
+    let path = temp_path("tickets.json");
+    let config = mail_config(&server);
+    let ingest = MailIngest::open(config.clone(), &path).unwrap();
+    ingest
+        .expect(PendingTicket::new(
+            42,
+            &receipt("2026-11-02"),
+            &test_passengers(),
+        ))
+        .unwrap();
+
+    // A restart before the email arrives keeps waiting for it
+    let ingest = MailIngest::open(config, &path).unwrap();
+    assert_eq!(ingest.pending().len(), 1);
+
+    let tickets = ingest.poll().await.unwrap();
+
+    assert_eq!(tickets.len(), 1);
+    let (ticket, mail) = &tickets[0];
+    assert_eq!(ticket.chat_id, 42);
+    assert_eq!(mail.uid, ticket_uid);
+    assert!(server.is_seen(ticket_uid));
+    assert!(!server.is_seen(newsletter));
+
+    // The booking no longer waits, so nothing else is fetched
+    assert!(ingest.poll().await.unwrap().is_empty());
+    assert!(std::fs::read_to_string(&path).unwrap().trim() == "[]");
+    let _ = std::fs::remove_file(&path);
+}
+
+#[tokio::test]
+async fn poll_forwards_matched_ticket_even_if_it_cannot_be_marked_read() {
+    let server = ImapStub::start().await;
+    let ticket_uid = server.deliver(BIGLIETTO);
+    server.refuse_flags();
+
+    let path = temp_path("tickets-unflagged.json");
+    let ingest = MailIngest::open(mail_config(&server), &path).unwrap();
+    ingest
+        .expect(PendingTicket::new(
+            42,
+            &receipt("2026-11-02"),
+            &test_passengers(),
+        ))
+        .unwrap();
+
+    let tickets = ingest.poll().await.unwrap();
+
+    assert_eq!(tickets.len(), 1);
+    assert_eq!(tickets[0].1.uid, ticket_uid);
+    assert!(!server.is_seen(ticket_uid));
+    assert!(ingest.pending().is_empty());
+    let _ = std::fs::remove_file(&path);
+}
@@ -0,0 +1,82 @@
+//! Parsing of the page shown after confirming a purchase.
+
+mod common;
+
+use std::{sync::Arc, thread};
+
+use common::temp_path;
+use contram_ticket_automated::utils::receipt::{
+    BookingReceipt, ReceiptStore, ReceiptTrip, parse_confirmation,
+};
+
+const CONFERMA: &str = include_str!("fixtures/contram/conferma.html");
+const RIEPILOGO: &str = include_str!("fixtures/contram/riepilogo.html");
+
+#[test]
+fn parse_confirmation_reads_details_list() {
+    let confirmation = parse_confirmation(CONFERMA).unwrap();
+
+    assert_eq!(confirmation.code.as_deref(), Some("CTR-2026-004517"));
+    assert_eq!(confirmation.price, Some(9.5));
+    assert_eq!(confirmation.status, "Pagato");
+}
+
+#[test]
+fn parse_confirmation_reads_inline_labels() {
+    let html = "<html><body><h1>Prenotazione confermata</h1>\
+        <p>Codice: AB12CD</p><p>Importo: 19,00 €</p></body></html>";
+
+    let confirmation = parse_confirmation(html).unwrap();
+
+    assert_eq!(confirmation.code.as_deref(), Some("AB12CD"));
+    assert_eq!(confirmation.price, Some(19.0));
+    assert_eq!(confirmation.status, "Prenotazione confermata");
+}
+
+#[test]
+fn parse_confirmation_rejects_other_pages() {
+    assert!(parse_confirmation(RIEPILOGO).is_err());
+}
+
+#[test]
+fn receipts_added_at_once_are_all_kept() {
+    let path = temp_path("receipts.json");
+    let store = Arc::new(ReceiptStore::open(&path).unwrap());
+
+    let bookings: Vec<_> = (0..8)
+        .map(|index| {
+            let store = store.clone();
+            thread::spawn(move || {
+                let receipt = BookingReceipt {
+                    code: Some(format!("CTR-{}", index)),
+                    trips: vec![ReceiptTrip {
+                        from: "Camerino".to_string(),
+                        to: "Ancona Piazza Cavour".to_string(),
+                        date: "2026-11-02".to_string(),
+                        departure: "06:10".to_string(),
+                    }],
+                    passengers: vec!["Mario Rossi (student)".to_string()],
+                    price: Some(9.5),
+                    status: "Pagato".to_string(),
+                    email: "mario.rossi@studenti.unicam.it".to_string(),
+                };
+                store.add("mario", &receipt).unwrap();
+            })
+        })
+        .collect();
+    for booking in bookings {
+        booking.join().unwrap();
+    }
+
+    let reopened = ReceiptStore::open(&path).unwrap();
+    let mut codes: Vec<String> = reopened
+        .list()
+        .into_iter()
+        .filter_map(|stored| stored.receipt.code)
+        .collect();
+    codes.sort();
+    assert_eq!(codes.len(), 8);
+    assert_eq!(codes[0], "CTR-0");
+    assert!(!path.with_extension("json.tmp").exists());
+    let _ = std::fs::remove_file(&path);
+}
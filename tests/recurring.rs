@@ -0,0 +1,112 @@
+//! Weekly recurring bookings and the dates they book.
+
+mod common;
+
+use chrono::{NaiveDate, Weekday};
+use common::temp_path;
+use contram_ticket_automated::utils::{
+    departures::DepartureFilter,
+    recurring::{RecurringRule, RecurringStore, parse_weekdays},
+};
+
+fn date(s: &str) -> NaiveDate {
+    NaiveDate::parse_from_str(s, "%Y-%m-%d").unwrap()
+}
+
+fn rule(weekdays: &str) -> RecurringRule {
+    RecurringRule::new(
+        42,
+        "mario",
+        "Camerino → Ancona Piazza Cavour".to_string(),
+        (24, 38),
+        parse_weekdays(weekdays).unwrap(),
+    )
+}
+
+#[test]
+fn parses_weekday_lists_and_ranges() {
+    assert_eq!(
+        parse_weekdays("fri,mon-wed,tuesday").unwrap(),
+        vec![Weekday::Mon, Weekday::Tue, Weekday::Wed, Weekday::Fri]
+    );
+    assert_eq!(
+        parse_weekdays("sat-mon").unwrap(),
+        vec![Weekday::Mon, Weekday::Sat, Weekday::Sun]
+    );
+    assert!(parse_weekdays("mon,funday").is_err());
+    assert!(parse_weekdays("").is_err());
+}
+
+#[test]
+fn dates_follow_weekdays_end_and_progress() {
+    let rule = rule("mon,thu").with_until(Some(date("2026-11-12")));
+
+    // 2026-11-02 is a Monday
+    assert_eq!(
+        rule.dates(date("2026-11-01"), date("2026-11-20")),
+        vec![
+            date("2026-11-02"),
+            date("2026-11-05"),
+            date("2026-11-09"),
+            date("2026-11-12")
+        ]
+    );
+
+    let scheduled = RecurringRule {
+        scheduled_until: Some(date("2026-11-05")),
+        ..rule.clone()
+    };
+    assert_eq!(
+        scheduled.dates(date("2026-11-01"), date("2026-11-20")),
+        vec![date("2026-11-09"), date("2026-11-12")]
+    );
+
+    let paused = RecurringRule {
+        paused: true,
+        ..rule
+    };
+    assert!(
+        paused
+            .dates(date("2026-11-01"), date("2026-11-20"))
+            .is_empty()
+    );
+}
+
+#[test]
+fn query_books_the_rule_run_on_a_date() {
+    let rule = rule("mon").with_departure(Some(DepartureFilter::parse("07:00-08:00").unwrap()));
+
+    let query = rule.query(date("2026-11-02")).unwrap();
+    assert_eq!((query.from_id, query.to_id), (24, 38));
+    assert_eq!(query.date, "2026-11-02");
+    assert_eq!(
+        query.departure.unwrap().to_string(),
+        rule.departure.unwrap()
+    );
+}
+
+#[test]
+fn rules_survive_reopening_the_store() {
+    let path = temp_path("recurring-reopen.json");
+    let store = RecurringStore::open(&path).unwrap();
+
+    let first = store.add(rule("mon-fri")).unwrap();
+    let second = store.add(rule("sat")).unwrap();
+    assert_eq!((first.id, second.id), (1, 2));
+    store.mark_scheduled(first.id, date("2026-11-06")).unwrap();
+    assert!(store.set_paused(7, second.id, true).unwrap().is_none());
+    assert!(
+        store
+            .set_paused(42, second.id, true)
+            .unwrap()
+            .unwrap()
+            .paused
+    );
+
+    let rules = RecurringStore::open(&path).unwrap().list();
+    assert_eq!(rules.len(), 2);
+    assert_eq!(rules[0].scheduled_until, Some(date("2026-11-06")));
+    assert!(!rules[0].paused);
+    assert!(rules[1].paused);
+    assert!(rules[1].to_string().ends_with("every Sat (paused)"));
+}
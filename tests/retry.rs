@@ -0,0 +1,195 @@
+//! Retrying failed bookings.
+
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use color_eyre::eyre::Error;
+use contram_ticket_automated::utils::{
+    booking_error::BookingError,
+    diagnostics::{BookingFailure, FailureKind},
+    retry::{RetryNotice, RetryPolicy, RetrySummary, with_retry, with_retry_from},
+};
+
+fn policy() -> RetryPolicy {
+    RetryPolicy {
+        backoff: Duration::ZERO,
+        ..RetryPolicy::default()
+    }
+}
+
+fn failure(step: &'static str, kind: FailureKind) -> Error {
+    BookingError::from(BookingFailure {
+        step,
+        kind,
+        error: Error::msg("Button \"Prenota\" not found"),
+        screenshot: None,
+        page: None,
+    })
+    .into()
+}
+
+/// Runs `with_retry` over `outcomes`, one per attempt, returning the result, the
+/// number of attempts and the notices sent.
+async fn run(
+    policy: &RetryPolicy,
+    outcomes: Vec<Result<u32, Error>>,
+) -> (Result<u32, Error>, usize, Vec<RetryNotice>) {
+    let outcomes = Mutex::new(outcomes.into_iter());
+    let attempts = Mutex::new(0);
+    let notices = Arc::new(Mutex::new(Vec::new()));
+
+    let result = with_retry(
+        policy,
+        || {
+            *attempts.lock().unwrap() += 1;
+            let outcome = outcomes.lock().unwrap().next().unwrap();
+            async move { outcome }
+        },
+        |notice| {
+            let notices = notices.clone();
+            async move { notices.lock().unwrap().push(notice) }
+        },
+    )
+    .await;
+
+    let notices = notices.lock().unwrap().clone();
+    (result, attempts.into_inner().unwrap(), notices)
+}
+
+#[tokio::test]
+async fn retries_until_booking_succeeds() {
+    let (result, attempts, notices) = run(
+        &policy(),
+        vec![
+            Err(Error::msg("WebDriver connection refused")),
+            Err(failure("search", FailureKind::SiteChanged)),
+            Ok(7),
+        ],
+    )
+    .await;
+
+    assert_eq!(result.unwrap(), 7);
+    assert_eq!(attempts, 3);
+    assert_eq!(notices.len(), 2);
+    assert_eq!(notices[1].attempt, 2);
+    assert_eq!(notices[1].max_attempts, 3);
+    assert!(notices[0].error.contains("connection refused"));
+}
+
+#[tokio::test]
+async fn gives_up_with_summary_of_every_attempt() {
+    let (result, attempts, _) = run(
+        &policy(),
+        (0..3)
+            .map(|_| Err(failure("add to cart", FailureKind::Unknown)))
+            .collect(),
+    )
+    .await;
+
+    assert_eq!(attempts, 3);
+    let error = result.unwrap_err();
+    let summary = error.downcast_ref::<RetrySummary>().unwrap();
+    assert_eq!(summary.errors.len(), 3);
+    assert_eq!(summary.reason, "no attempts left");
+    assert!(error.to_string().starts_with("Gave up after 3 attempts"));
+    // The last failure stays reachable, to report its page
+    let booking_error = error.downcast_ref::<BookingError>().unwrap();
+    assert_eq!(booking_error.failure().unwrap().step, "add to cart");
+}
+
+#[tokio::test]
+async fn does_not_retry_failures_outside_policy() {
+    let (result, attempts, notices) = run(
+        &policy(),
+        vec![Err(failure("search", FailureKind::SoldOut))],
+    )
+    .await;
+
+    assert_eq!(attempts, 1);
+    assert!(notices.is_empty());
+    // A single attempt keeps its error as is
+    let error = result.unwrap_err();
+    assert!(error.downcast_ref::<RetrySummary>().is_none());
+    assert!(matches!(
+        error.downcast_ref::<BookingError>(),
+        Some(BookingError::SoldOut(_))
+    ));
+}
+
+#[tokio::test]
+async fn never_retries_after_confirming() {
+    let (_, attempts, _) = run(
+        &policy(),
+        vec![Err(failure("confirm", FailureKind::Unknown))],
+    )
+    .await;
+
+    assert_eq!(attempts, 1);
+}
+
+#[tokio::test]
+async fn stops_at_deadline() {
+    let policy = RetryPolicy {
+        backoff: Duration::from_secs(10),
+        deadline: Duration::from_secs(5),
+        ..RetryPolicy::default()
+    };
+
+    let (result, attempts, _) = run(
+        &policy,
+        vec![Err(Error::msg("timeout")), Err(Error::msg("timeout"))],
+    )
+    .await;
+
+    assert_eq!(attempts, 1);
+    assert!(result.is_err());
+}
+
+#[tokio::test]
+async fn deadline_counts_from_given_start() {
+    let policy = RetryPolicy {
+        backoff: Duration::ZERO,
+        deadline: Duration::from_millis(200),
+        ..RetryPolicy::default()
+    };
+    // The first attempt spends longer than the deadline waiting to start
+    let attempt = |attempts: &Mutex<u32>| {
+        let first = {
+            let mut attempts = attempts.lock().unwrap();
+            *attempts += 1;
+            *attempts == 1
+        };
+        async move {
+            if first {
+                tokio::time::sleep(Duration::from_millis(300)).await;
+                return Err(Error::msg("timeout"));
+            }
+            Ok(7)
+        }
+    };
+
+    let attempts = Mutex::new(0);
+    let result = with_retry(&policy, || attempt(&attempts), |_| async {}).await;
+    assert!(result.is_err());
+
+    let attempts = Mutex::new(0);
+    let opens_at = Instant::now() + Duration::from_millis(300);
+    let result = with_retry_from(&policy, opens_at, || attempt(&attempts), |_| async {}).await;
+    assert_eq!(result.unwrap(), 7);
+}
+
+#[test]
+fn backoff_doubles_up_to_maximum() {
+    let policy = RetryPolicy {
+        backoff: Duration::from_secs(5),
+        max_backoff: Duration::from_secs(30),
+        ..RetryPolicy::default()
+    };
+
+    assert_eq!(policy.delay_after(1), Duration::from_secs(5));
+    assert_eq!(policy.delay_after(2), Duration::from_secs(10));
+    assert_eq!(policy.delay_after(3), Duration::from_secs(20));
+    assert_eq!(policy.delay_after(4), Duration::from_secs(30));
+}
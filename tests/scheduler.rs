@@ -0,0 +1,91 @@
+//! Running jobs at their due time.
+
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use chrono::Utc;
+use contram_ticket_automated::utils::scheduler::Scheduler;
+use futures::FutureExt;
+use tokio::sync::mpsc;
+
+fn in_millis(millis: i64) -> chrono::DateTime<Utc> {
+    Utc::now() + chrono::Duration::milliseconds(millis)
+}
+
+#[tokio::test]
+async fn runs_jobs_in_due_order() {
+    let (sender, mut receiver) = mpsc::unbounded_channel();
+    let scheduler = Scheduler::start(move |job: &'static str| {
+        let sender = sender.clone();
+        async move { sender.send(job).unwrap() }.boxed()
+    });
+
+    scheduler.schedule(1, in_millis(300), "late");
+    scheduler.schedule(2, in_millis(100), "early");
+    scheduler.schedule(3, in_millis(-1000), "overdue");
+    assert!(scheduler.due(1).is_some());
+
+    let mut order = Vec::new();
+    for _ in 0..3 {
+        let job = tokio::time::timeout(Duration::from_secs(2), receiver.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        order.push(job);
+    }
+    assert_eq!(order, ["overdue", "early", "late"]);
+    assert_eq!(scheduler.pending(), 0);
+}
+
+#[tokio::test]
+async fn cancelled_jobs_do_not_run() {
+    let ran = Arc::new(Mutex::new(Vec::new()));
+    let scheduler = Scheduler::start({
+        let ran = ran.clone();
+        move |job: u64| {
+            ran.lock().unwrap().push(job);
+            async {}.boxed()
+        }
+    });
+
+    scheduler.schedule(1, in_millis(100), 1);
+    scheduler.schedule(2, in_millis(150), 2);
+    assert_eq!(scheduler.cancel(1), Some(1));
+    assert_eq!(scheduler.cancel(1), None);
+
+    tokio::time::sleep(Duration::from_millis(400)).await;
+    assert_eq!(*ran.lock().unwrap(), [2]);
+}
+
+#[tokio::test]
+async fn handles_hundreds_of_waiting_jobs() {
+    let (sender, mut receiver) = mpsc::unbounded_channel();
+    let scheduler = Scheduler::start(move |job: u64| {
+        let sender = sender.clone();
+        async move {
+            // Jobs run side by side, a slow one does not hold up the others
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            sender.send(job).unwrap();
+        }
+        .boxed()
+    });
+
+    for id in 0..500 {
+        scheduler.schedule(id, in_millis(100 + (id % 10) as i64 * 10), id);
+    }
+    // Far-off jobs wait without holding up the rest
+    scheduler.schedule(1000, in_millis(60 * 60 * 1000), 1000);
+
+    let mut done = 0;
+    tokio::time::timeout(Duration::from_secs(2), async {
+        while done < 500 {
+            receiver.recv().await.unwrap();
+            done += 1;
+        }
+    })
+    .await
+    .unwrap();
+    assert_eq!(scheduler.pending(), 1);
+}
@@ -0,0 +1,147 @@
+//! Tracking, closing and reaping booking sessions.
+
+mod common;
+
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use axum::{
+    Json, Router,
+    extract::{Path, State},
+    routing::{delete, get},
+};
+use serde_json::json;
+
+use common::{ContramStub, test_passengers};
+use contram_ticket_automated::utils::{
+    backend::{BookingBackend, ManagedSession, SearchQuery, book_ticket},
+    http_booking::HttpBackend,
+    sessions::SessionRegistry,
+};
+
+const DATE: &str = "2026-11-02";
+
+#[test]
+fn lease_keeps_session_listed() {
+    let registry = Arc::new(SessionRegistry::new());
+
+    let first = registry.register("http", None);
+    let second = registry.register("http", None);
+    let sessions = registry.list();
+    assert_eq!(sessions.len(), 2);
+    assert_eq!(sessions[0].backend, "http");
+    assert!(sessions[0].webdriver_id.is_none());
+    assert!(sessions[0].id < sessions[1].id);
+
+    drop(first);
+    let sessions = registry.list();
+    assert_eq!(sessions.len(), 1);
+    assert_eq!(sessions[0].id, 2);
+
+    drop(second);
+    assert!(registry.list().is_empty());
+}
+
+#[tokio::test]
+async fn reaps_only_stale_sessions() {
+    let registry = Arc::new(SessionRegistry::new());
+    let _lease = registry.register("http", None);
+
+    assert_eq!(registry.reap(Duration::from_secs(60)).await, 0);
+    assert_eq!(registry.list().len(), 1);
+
+    assert_eq!(registry.reap(Duration::ZERO).await, 1);
+    assert!(registry.list().is_empty());
+}
+
+#[tokio::test]
+async fn booking_closes_session() {
+    let stub = ContramStub::start().await;
+    let registry = Arc::new(SessionRegistry::new());
+    let backend = HttpBackend::new(&stub.base_url).with_sessions(registry.clone());
+
+    book_ticket(
+        &backend,
+        &test_passengers(),
+        &SearchQuery::new(24, 38, DATE.to_string()),
+    )
+    .await
+    .unwrap();
+
+    assert!(registry.list().is_empty());
+}
+
+#[tokio::test]
+async fn failed_booking_closes_session() {
+    let stub = ContramStub::start().await;
+    stub.sell_out(DATE);
+    let registry = Arc::new(SessionRegistry::new());
+    let backend = HttpBackend::new(&stub.base_url).with_sessions(registry.clone());
+
+    book_ticket(
+        &backend,
+        &test_passengers(),
+        &SearchQuery::new(24, 38, DATE.to_string()),
+    )
+    .await
+    .unwrap_err();
+
+    assert!(registry.list().is_empty());
+}
+
+#[tokio::test]
+async fn dropped_session_is_closed() {
+    let stub = ContramStub::start().await;
+    let registry = Arc::new(SessionRegistry::new());
+    let backend = HttpBackend::new(&stub.base_url).with_sessions(registry.clone());
+
+    let session = ManagedSession::open(&backend).await.unwrap();
+    assert_eq!(backend.sessions().unwrap().list().len(), 1);
+
+    drop(session);
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert!(registry.list().is_empty());
+}
+
+#[tokio::test]
+async fn closes_sessions_left_on_the_webdriver() {
+    let deleted = Arc::new(Mutex::new(Vec::<String>::new()));
+    let app = Router::new()
+        .route(
+            "/sessions",
+            get(|| async { Json(json!({ "value": [{ "id": "chrome-1" }] })) }),
+        )
+        .route(
+            "/status",
+            get(|| async {
+                Json(json!({ "value": { "ready": true, "nodes": [
+                    { "slots": [{ "session": { "sessionId": "grid-1" } }, { "session": null }] }
+                ] } }))
+            }),
+        )
+        .route(
+            "/session/{id}",
+            delete(
+                |State(deleted): State<Arc<Mutex<Vec<String>>>>, Path(id): Path<String>| async move {
+                    deleted.lock().unwrap().push(id);
+                    Json(json!({ "value": null }))
+                },
+            ),
+        )
+        .with_state(deleted.clone());
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+    let registry = SessionRegistry::new();
+    assert_eq!(registry.close_orphans(&url).await.unwrap(), 2);
+    assert_eq!(*deleted.lock().unwrap(), ["chrome-1", "grid-1"]);
+
+    // A server listing no sessions, or not answering, has nothing to close
+    assert_eq!(
+        registry.close_orphans("http://127.0.0.1:9").await.unwrap(),
+        0
+    );
+}
@@ -0,0 +1,132 @@
+//! Caching of the departure stops against the local Contram stand-in.
+
+mod common;
+
+use std::time::Duration;
+
+use common::{ContramStub, temp_path};
+use contram_ticket_automated::utils::stops::{StopCatalogue, StopMatch, find_stop};
+
+#[tokio::test]
+async fn catalogue_caches_stops_until_ttl_expires() {
+    let stub = ContramStub::start().await;
+    let catalogue = StopCatalogue::new(&stub.base_url);
+
+    let cities = catalogue.cities().await.unwrap();
+    catalogue.cities().await.unwrap();
+
+    assert_eq!(cities.len(), 5);
+    assert_eq!(stub.stop_requests(), 1);
+
+    let expired = StopCatalogue::new(&stub.base_url).with_ttl(Duration::ZERO);
+    expired.cities().await.unwrap();
+    expired.cities().await.unwrap();
+    assert_eq!(stub.stop_requests(), 3);
+}
+
+#[tokio::test]
+async fn catalogue_refresh_bypasses_cache() {
+    let stub = ContramStub::start().await;
+    let catalogue = StopCatalogue::new(&stub.base_url);
+
+    catalogue.cities().await.unwrap();
+    catalogue.refresh().await.unwrap();
+
+    assert_eq!(stub.stop_requests(), 2);
+}
+
+#[tokio::test]
+async fn catalogue_falls_back_to_snapshot_when_site_is_down() {
+    let stub = ContramStub::start().await;
+    let path = temp_path("stops-fallback.json");
+
+    let cities = StopCatalogue::new(&stub.base_url)
+        .with_snapshot(&path)
+        .cities()
+        .await
+        .unwrap();
+    assert!(path.exists());
+
+    // A fresh catalogue, as after a restart, while the site is down
+    stub.take_down_stops();
+    let restarted = StopCatalogue::new(&stub.base_url).with_snapshot(&path);
+    assert_eq!(restarted.cities().await.unwrap(), cities);
+    assert!(restarted.refresh().await.is_err());
+
+    let _ = std::fs::remove_file(&path);
+}
+
+#[tokio::test]
+async fn catalogue_fails_without_site_or_snapshot() {
+    let stub = ContramStub::start().await;
+    stub.take_down_stops();
+
+    let catalogue =
+        StopCatalogue::new(&stub.base_url).with_snapshot(temp_path("stops-missing.json"));
+
+    assert!(catalogue.cities().await.is_err());
+}
+
+fn stops() -> Vec<(String, u32)> {
+    vec![
+        ("Camerino".to_string(), 24),
+        ("Ancona Piazza Cavour".to_string(), 38),
+        ("Ancona Stazione F.S.".to_string(), 39),
+        ("Civitanova Marche Via Sonnino".to_string(), 42),
+        ("Sant'Elpidio a Mare".to_string(), 61),
+    ]
+}
+
+fn found(name: &str, id: u32) -> StopMatch {
+    StopMatch::Found(name.to_string(), id)
+}
+
+#[test]
+fn find_stop_accepts_ids_and_names() {
+    let stops = stops();
+
+    assert_eq!(find_stop(&stops, "24"), found("Camerino", 24));
+    assert_eq!(find_stop(&stops, "99"), StopMatch::NotFound);
+    assert_eq!(find_stop(&stops, "CAMERINO"), found("Camerino", 24));
+    assert_eq!(
+        find_stop(&stops, "ancona stazione"),
+        found("Ancona Stazione F.S.", 39)
+    );
+    assert_eq!(
+        find_stop(&stops, "ancona-piazza_cavour"),
+        found("Ancona Piazza Cavour", 38)
+    );
+    assert_eq!(
+        find_stop(&stops, "sant elpìdio"),
+        found("Sant'Elpidio a Mare", 61)
+    );
+    assert_eq!(
+        find_stop(&stops, "civitanova"),
+        found("Civitanova Marche Via Sonnino", 42)
+    );
+}
+
+#[test]
+fn find_stop_tolerates_typos() {
+    let stops = stops();
+
+    assert_eq!(find_stop(&stops, "camerno"), found("Camerino", 24));
+    assert_eq!(
+        find_stop(&stops, "ancona stazone"),
+        found("Ancona Stazione F.S.", 39)
+    );
+    assert_eq!(find_stop(&stops, "macerata"), StopMatch::NotFound);
+}
+
+#[test]
+fn find_stop_reports_ambiguous_names() {
+    let stops = stops();
+
+    assert_eq!(
+        find_stop(&stops, "ancona"),
+        StopMatch::Ambiguous(vec![
+            ("Ancona Piazza Cavour".to_string(), 38),
+            ("Ancona Stazione F.S.".to_string(), 39),
+        ])
+    );
+}
@@ -0,0 +1,111 @@
+//! Seat watches polling the local Contram stand-in.
+
+mod common;
+
+use std::time::Duration;
+
+use common::ContramStub;
+use contram_ticket_automated::utils::{
+    backend::SearchQuery,
+    departures::DepartureFilter,
+    http_booking::HttpBackend,
+    watch::{RateLimiter, Watch, WatchAction, WatchConfig, WatchList},
+};
+use tokio::time::Instant;
+
+const DATE: &str = "2026-11-02";
+
+fn watch_list() -> WatchList {
+    WatchList::new(WatchConfig {
+        interval: Duration::from_millis(50),
+        rate_limit: Duration::ZERO,
+    })
+}
+
+fn watch(watches: &WatchList, chat_id: i64) -> Watch {
+    Watch {
+        id: watches.next_id(),
+        chat_id,
+        username: "mario".to_string(),
+        query: SearchQuery::new(24, 38, DATE.to_string()),
+        route: "Camerino → Ancona Piazza Cavour".to_string(),
+        action: WatchAction::Notify,
+        started: chrono::Local::now(),
+    }
+}
+
+#[tokio::test]
+async fn waits_until_a_seat_frees_up() {
+    let stub = ContramStub::start().await;
+    stub.sell_out(DATE);
+    let backend = HttpBackend::new(&stub.base_url);
+    let watches = watch_list();
+    let query = SearchQuery::new(24, 38, DATE.to_string())
+        .with_departure(Some(DepartureFilter::parse("13:00-18:00").unwrap()));
+
+    let search = watches.wait_for_seat(&backend, &query);
+    let restock = async {
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        stub.restock(DATE);
+    };
+    let (departure, _) = tokio::time::timeout(Duration::from_secs(5), async {
+        tokio::join!(search, restock)
+    })
+    .await
+    .unwrap();
+
+    assert_eq!(departure.time.format("%H:%M").to_string(), "13:40");
+    assert!(stub.searches().len() > 1);
+}
+
+#[tokio::test]
+async fn rate_limiter_spaces_out_callers() {
+    let limiter = RateLimiter::new(Duration::from_millis(100));
+    let start = Instant::now();
+
+    limiter.wait().await;
+    limiter.wait().await;
+    limiter.wait().await;
+
+    assert!(start.elapsed() >= Duration::from_millis(200));
+}
+
+#[tokio::test]
+async fn lists_and_cancels_watches_of_a_chat() {
+    let watches = watch_list();
+    let first = watch(&watches, 1);
+    let other_chat = watch(&watches, 2);
+    let task = tokio::spawn(std::future::pending::<()>());
+    watches.insert(first.clone(), task.abort_handle());
+    watches.insert(
+        other_chat,
+        tokio::spawn(std::future::pending::<()>()).abort_handle(),
+    );
+
+    let listed = watches.list(1);
+    assert_eq!(listed.len(), 1);
+    assert_eq!(listed[0].id, first.id);
+    assert!(
+        listed[0]
+            .to_string()
+            .contains("Camerino → Ancona Piazza Cavour")
+    );
+
+    // Only the chat that started a watch can stop it
+    assert!(watches.cancel(2, first.id).is_none());
+    assert!(watches.cancel(1, first.id).is_some());
+    assert!(task.await.unwrap_err().is_cancelled());
+    assert!(watches.list(1).is_empty());
+    assert_eq!(watches.list(2).len(), 1);
+}
+
+#[tokio::test]
+async fn finished_watches_are_not_listed() {
+    let watches = watch_list();
+    let task = tokio::spawn(async {});
+    watches.insert(watch(&watches, 1), task.abort_handle());
+
+    task.await.unwrap();
+
+    assert!(watches.list(1).is_empty());
+}